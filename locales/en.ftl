@@ -0,0 +1,223 @@
+# Bot messages. Everything except `*bold*` and `code` markers is plain text:
+# MarkdownV2 escaping is applied after formatting.
+
+by-user = { $text } by { $user }
+
+wipe-done = *Wipe successful*
+wipe-cancelled = Wipe cancelled
+
+confirm-wipe = Clear all players and timers?
+confirm-delete-player = Delete player *{ $name }*?
+confirm-delete-timer = Delete timer *{ $name }*?
+confirm-reset-stress = Clear the stress of all players?
+confirm-expired = Too late, ask again
+confirm-timeout = The buttons work for { $minutes } min, later they only cancel
+cancelled-toast = Cancelled
+stress-reset = Stress of all players cleared
+
+dashboard-off = Dashboard is no longer updated
+
+poll-title = *When do we play next?*
+poll-closed = *Poll closed:*
+poll-option = `{ $date }`: { $count ->
+    [0] nobody yet
+   *[other] { $names }
+}
+poll-waiting = Waiting for: { $names }
+vote-added = You can come on { $date }
+vote-removed = You can no longer come on { $date }
+session-chosen = Next session: *{ $date }* with { $names }{ $reminder ->
+    [1] , I will remind you an hour before
+   *[0] {""}
+}
+session-none = Nobody can come on any of the dates
+session-reminder = Session on { $date }
+
+player-name-required = Player name is required
+timer-name-required = Timer name is required
+player-added = Player *{ $name }* added
+timer-added = Timer *{ $name }* added
+
+player-harm = Player *{ $name }* has *{ $harm }* harm
+player-harm-toast = { $name }: { $harm } harm
+player-stress = Player *{ $name }* has *{ $stress }* stress
+player-stress-toast = { $name }: { $stress } stress
+player-removed = Player *{ $name }* with *{ $harm }* harm and *{ $stress }* stress has been removed
+timer-ticks = Timer *{ $name }* has *{ $value }* ticks left
+timer-ticks-toast = { $name }: { $value } ticks left
+timer-fired = Timer *{ $name }* has fired!
+timer-fired-toast = { $name } has fired!
+timer-due = ⏰ Timer *{ $name }* is due!
+timer-scheduled = Timer *{ $name }* is due { $when }
+due-at = { $days ->
+    [0] at { $time }
+    [1] tomorrow at { $time }
+   *[other] in { $days } days, at { $time }
+}
+timer-removed = Timer *{ $name }* with *{ $value }* ticks has been removed
+removed-toast = { $name } removed
+
+announce-on = Button changes will be announced in chat
+announce-off = Button changes will only be shown to whoever pressed the button
+
+players-title = *Players:*
+player-line = *{ $name }*: *{ $harm }* harm, *{ $stress }* stress
+timers-title = *Timers:*
+timer-line = *{ $name }*: *{ $value }* ticks left
+timer-line-max = *{ $name }*: *{ $value }* of { $max } ticks left
+timer-line-due = *{ $name }*: due { $when }
+player-renamed = Player *{ $old }* is now *{ $name }*
+timer-renamed = Timer *{ $old }* is now *{ $name }*
+timer-edited = Timer *{ $name }* set to *{ $value }* ticks
+pinned-toast = { $name } pinned
+unpinned-toast = { $name } unpinned
+rename-prompt = { $user }, reply with the new name of *{ $name }*
+
+keyboard-outdated = This keyboard is outdated, list the players or timers again
+keypad-harm = { $name }, harm: { $value }
+keypad-stress = { $name }, stress: { $value }
+keypad-timer = { $name }: { $value } ticks
+manage = *Manage:*
+manage-harm = *Manage harm:*
+manage-stress = *Manage stress:*
+manage-players = *Manage players:*
+settings = *Settings:*
+
+button-manage = Manage
+button-manage-harm = Manage harm
+button-manage-stress = Manage stress
+button-manage-players = Manage players
+button-back = Back
+button-delete = Delete
+button-harm = { $step } harm
+button-stress = { $step } stress
+button-cancel = Cancel
+button-pin = Pin
+button-unpin = Unpin
+button-rename = Rename
+button-confirm = Yes
+button-reset-stress = Reset stress
+button-close-poll = Close poll
+
+on-off = { $enabled ->
+    [1] on
+   *[0] off
+}
+settings-announce = Announce changes: { on-off }
+settings-stress-cap = Stress cap: { $cap }
+settings-stress-cap-none = Stress cap: none
+settings-clock-size = Clock size: { $size }
+settings-steps = Step buttons: { $steps }
+settings-page-size = Rows per page: { $size }
+settings-delete-fired = Delete fired timers: { on-off }
+settings-utc-offset = Time zone: { $zone }
+settings-language = Language: { $language }
+language-auto = auto
+language-en = English
+language-ru = Русский
+
+inline-roll-title = Roll { $dice }
+inline-roll-description = The result is shown once sent
+inline-roll = 🎲 { $dice }: { $values } = *{ $total }*
+inline-action-title = Action roll with { $pool ->
+    [one] { $pool } die
+   *[other] { $pool } dice
+}
+inline-action = 🎲 Action roll with { $pool ->
+    [one] { $pool } die
+   *[other] { $pool } dice
+}: { $values } → *{ $result }*, { $outcome }
+outcome-critical = critical success!
+outcome-success = full success
+outcome-partial = partial success
+outcome-failure = bad outcome
+
+roll-highest = Highest *{ $result }* — { $outcome }
+roll-sixes = *{ $sixes }* sixes — { $outcome }
+roll-stakes = { $position }, { $effect } effect: { $consequence }
+roll-mark-xp = Mark xp for the desperate action.
+position-controlled = Controlled
+position-risky = Risky
+position-desperate = Desperate
+effect-limited = limited
+effect-standard = standard
+effect-great = great
+consequence-success = you do it.
+consequence-controlled-partial = you hesitate. Withdraw and try another way, or do it with a minor consequence.
+consequence-controlled-failure = you falter. Press on by seizing a risky opportunity, or withdraw and try another way.
+consequence-risky-partial = you do it, but there's a consequence: harm, a complication, reduced effect or a desperate position.
+consequence-risky-failure = things go badly: harm, a complication, a desperate position or a lost opportunity.
+consequence-desperate-partial = you do it, but there's a serious consequence: severe harm, a serious complication or reduced effect.
+consequence-desperate-failure = the worst outcome: severe harm, a serious complication or a lost opportunity.
+
+odds-action-title = *Action roll odds:*
+odds-pool-title = *Action roll with { $pool ->
+    [one] { $pool } die
+   *[other] { $pool } dice
+}:*
+odds-dice-title = *{ $dice }*, average { $average }:
+odds-check = Chance of { $dice } { $check }: *{ $chance }*
+odds-dice = dice
+odds-critical = crit
+odds-success = full
+odds-partial = partial
+odds-failure = bad
+
+stats-session = *Dice this session:*
+stats-campaign = *Dice in this campaign:*
+stats-none = No dice rolled yet
+stats-line = *{ $name }*: { $rolls ->
+    [one] { $rolls } roll
+   *[other] { $rolls } rolls
+} of { $dice ->
+    [one] { $dice } die
+   *[other] { $dice } dice
+}, average { $averages }, { $luck } of the expected total
+stats-average = { $average } per d{ $sides }
+stats-crits = { $crits ->
+    [one] { $crits } crit
+   *[other] { $crits } crits
+} in { $action_rolls ->
+    [one] { $action_rolls } action roll
+   *[other] { $action_rolls } action rolls
+}
+stats-cursed = *Cursed dice:*
+stats-cursed-line = { $place }. { $name }: { $luck } of the expected total
+
+fair-started = *Fair dice on.* Bot rolls now come from a secret seed with the SHA-256 hash `{ $commitment }`, revealed at /session end.
+fair-ended = *Fair dice off.* The seed was `{ $seed }`, with the hash `{ $commitment }`. Check the { $rolls ->
+    [one] { $rolls } roll
+   *[other] { $rolls } rolls
+} made from it with /verify.
+fair-roll = { $roll }, fair roll #{ $number }
+fair-verified = The seed matches the hash `{ $commitment }` and gives { $rolls ->
+    [one] the only roll
+   *[other] all { $rolls } rolls
+} of the session.
+fair-wrong-seed = This is not the seed with the hash `{ $commitment }`.
+fair-mismatches = *The seed does not give these rolls:*
+fair-mismatch = #{ $number } { $dice }: rolled { $values }, the seed gives { $expected }
+
+help-header = These commands are supported:
+cmd-help = display this text
+cmd-wipe = clears everything
+cmd-r1 = [position] [effect] - rolls 1 die, e.g. /r1 desperate great
+cmd-r2 = [position] [effect] - rolls 2 dice
+cmd-r3 = [position] [effect] - rolls 3 dice
+cmd-t = [*] [filter] - manage timers: * for running ones, filter by name or by group with "Group:"
+cmd-p = [*] [filter] - manage players: * for those with harm or stress, filter by name
+cmd-pa = <name> - add player
+cmd-ta = <name> [start_value] - add timer
+cmd-pr = <name> = <new name> - rename player
+cmd-tr = <name> = <new name> - rename timer
+cmd-te = <name> = <value>[/<max>] - set timer value and max
+cmd-td = <name> = <delay or time> - make a timer fire on the clock, e.g. 30m, 1h30m or 20:00
+cmd-quiet = toggle announcing button changes in chat
+cmd-settings = chat settings
+cmd-dash = [off] - pin a message showing players and timers, kept up to date
+cmd-roll = <dice> - roll dice such as 3d6, d20+2 or 4d6kh3
+cmd-stats = [all] - dice statistics of the session, or of the whole campaign
+cmd-odds = [pool or dice] - exact odds, e.g. /odds 2 or /odds 4d6kh3 >= 15
+cmd-schedule = <date>; <date>... - poll on when to play, e.g. 2024-05-04 19:00
+cmd-session = <start|end> - provably fair bot rolls from a seed whose hash is shown at start and the seed itself at end
+cmd-verify = [seed] - check the rolls of the last fair session against its seed
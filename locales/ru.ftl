@@ -0,0 +1,255 @@
+# Сообщения бота. Всё, кроме пометок `*жирный*` и `код`, пишется обычным
+# текстом: экранирование MarkdownV2 применяется после подстановки.
+
+by-user = { $text } ({ $user })
+
+wipe-done = *Всё очищено*
+wipe-cancelled = Очистка отменена
+
+confirm-wipe = Удалить всех игроков и таймеры?
+confirm-delete-player = Удалить игрока *{ $name }*?
+confirm-delete-timer = Удалить таймер *{ $name }*?
+confirm-reset-stress = Сбросить стресс всех игроков?
+confirm-expired = Время вышло, попробуйте ещё раз
+confirm-timeout = Кнопки работают { $minutes } мин., потом они только отменяют
+cancelled-toast = Отменено
+stress-reset = Стресс всех игроков сброшен
+
+dashboard-off = Сводка больше не обновляется
+
+poll-title = *Когда играем в следующий раз?*
+poll-closed = *Опрос закрыт:*
+poll-option = `{ $date }`: { $count ->
+    [0] пока никто
+   *[other] { $names }
+}
+poll-waiting = Ещё не ответили: { $names }
+vote-added = Вы сможете прийти { $date }
+vote-removed = Вы больше не можете прийти { $date }
+session-chosen = Следующая игра: *{ $date }*, придут { $names }{ $reminder ->
+    [1] , напомню за час
+   *[0] {""}
+}
+session-none = Ни одна из дат никому не подходит
+session-reminder = Игра { $date }
+
+player-name-required = Нужно указать имя игрока
+timer-name-required = Нужно указать название таймера
+player-added = Игрок *{ $name }* добавлен
+timer-added = Таймер *{ $name }* добавлен
+
+player-harm = У игрока *{ $name }* урон: *{ $harm }*
+player-harm-toast = { $name }: урон { $harm }
+player-stress = У игрока *{ $name }* стресс: *{ $stress }*
+player-stress-toast = { $name }: стресс { $stress }
+player-removed = Игрок *{ $name }* удалён (урон *{ $harm }*, стресс *{ $stress }*)
+timer-ticks = У таймера *{ $name }* { $value ->
+    [one] остался *{ $value }* тик
+    [few] осталось *{ $value }* тика
+   *[other] осталось *{ $value }* тиков
+}
+timer-ticks-toast = { $name }: { $value ->
+    [one] остался { $value } тик
+    [few] осталось { $value } тика
+   *[other] осталось { $value } тиков
+}
+timer-fired = Таймер *{ $name }* сработал!
+timer-fired-toast = { $name } сработал!
+timer-due = ⏰ Время таймера *{ $name }* пришло!
+timer-scheduled = Таймер *{ $name }* сработает { $when }
+due-at = { $days ->
+    [0] в { $time }
+    [1] завтра в { $time }
+   *[other] через { $days } { $days ->
+        [one] день
+        [few] дня
+       *[other] дней
+    } в { $time }
+}
+timer-removed = Таймер *{ $name }* удалён (тиков: *{ $value }*)
+removed-toast = { $name } удалён
+
+announce-on = Изменения с кнопок будут объявляться в чате
+announce-off = Изменения с кнопок будет видеть только нажавший
+
+players-title = *Игроки:*
+player-line = *{ $name }*: урон *{ $harm }*, стресс *{ $stress }*
+timers-title = *Таймеры:*
+timer-line = *{ $name }*: { $value ->
+    [one] остался *{ $value }* тик
+    [few] осталось *{ $value }* тика
+   *[other] осталось *{ $value }* тиков
+}
+timer-line-max = *{ $name }*: *{ $value }* из { $max }
+timer-line-due = *{ $name }*: сработает { $when }
+player-renamed = Игрок *{ $old }* теперь *{ $name }*
+timer-renamed = Таймер *{ $old }* теперь *{ $name }*
+timer-edited = У таймера *{ $name }* теперь *{ $value }* { $value ->
+    [one] тик
+    [few] тика
+   *[other] тиков
+}
+pinned-toast = { $name } закреплён
+unpinned-toast = { $name } откреплён
+rename-prompt = { $user }, ответьте новым названием для *{ $name }*
+
+keyboard-outdated = Эта клавиатура устарела, выведите список игроков или таймеров заново
+keypad-harm = { $name }, урон: { $value }
+keypad-stress = { $name }, стресс: { $value }
+keypad-timer = { $name }: { $value } { $value ->
+    [one] тик
+    [few] тика
+   *[other] тиков
+}
+manage = *Управление:*
+manage-harm = *Урон:*
+manage-stress = *Стресс:*
+manage-players = *Игроки:*
+settings = *Настройки:*
+
+button-manage = Управление
+button-manage-harm = Урон
+button-manage-stress = Стресс
+button-manage-players = Игроки
+button-back = Назад
+button-delete = Удалить
+button-harm = { $step } урон
+button-stress = { $step } стресс
+button-cancel = Отмена
+button-pin = Закрепить
+button-unpin = Открепить
+button-rename = Переименовать
+button-confirm = Да
+button-reset-stress = Сбросить стресс
+button-close-poll = Закрыть опрос
+
+on-off = { $enabled ->
+    [1] вкл
+   *[0] выкл
+}
+settings-announce = Объявлять изменения: { on-off }
+settings-stress-cap = Предел стресса: { $cap }
+settings-stress-cap-none = Предел стресса: нет
+settings-clock-size = Размер часов: { $size }
+settings-steps = Кнопки шагов: { $steps }
+settings-page-size = Строк на странице: { $size }
+settings-delete-fired = Удалять сработавшие таймеры: { on-off }
+settings-utc-offset = Часовой пояс: { $zone }
+settings-language = Язык: { $language }
+language-auto = авто
+language-en = English
+language-ru = Русский
+
+inline-roll-title = Бросить { $dice }
+inline-roll-description = Результат будет виден после отправки
+inline-roll = 🎲 { $dice }: { $values } = *{ $total }*
+inline-action-title = Бросок действия: { $pool ->
+    [one] { $pool } кубик
+    [few] { $pool } кубика
+   *[other] { $pool } кубиков
+}
+inline-action = 🎲 Бросок действия, { $pool ->
+    [one] { $pool } кубик
+    [few] { $pool } кубика
+   *[other] { $pool } кубиков
+}: { $values } → *{ $result }*, { $outcome }
+outcome-critical = критический успех!
+outcome-success = полный успех
+outcome-partial = частичный успех
+outcome-failure = провал
+
+roll-highest = Лучший *{ $result }* — { $outcome }
+roll-sixes = Шестёрок: *{ $sixes }* — { $outcome }
+roll-stakes = { $position }, { $effect } эффект: { $consequence }
+roll-mark-xp = Отметьте опыт за отчаянное действие.
+position-controlled = Под контролем
+position-risky = Рискованно
+position-desperate = Отчаянно
+effect-limited = ограниченный
+effect-standard = обычный
+effect-great = сильный
+consequence-success = у вас получилось.
+consequence-controlled-partial = вы колеблетесь. Отступите и попробуйте иначе или сделайте это с небольшими последствиями.
+consequence-controlled-failure = вы сбились. Рискните, чтобы продолжить, или отступите и попробуйте иначе.
+consequence-risky-partial = получилось, но с последствиями: урон, осложнение, сниженный эффект или отчаянная позиция.
+consequence-risky-failure = всё плохо: урон, осложнение, отчаянная позиция или упущенная возможность.
+consequence-desperate-partial = получилось, но с серьёзными последствиями: тяжёлый урон, серьёзное осложнение или сниженный эффект.
+consequence-desperate-failure = худший исход: тяжёлый урон, серьёзное осложнение или упущенная возможность.
+
+odds-action-title = *Шансы броска действия:*
+odds-pool-title = *Бросок действия, { $pool ->
+    [one] { $pool } кубик
+    [few] { $pool } кубика
+   *[other] { $pool } кубиков
+}:*
+odds-dice-title = *{ $dice }*, в среднем { $average }:
+odds-check = Шанс { $dice } { $check }: *{ $chance }*
+odds-dice = кубы
+odds-critical = крит
+odds-success = успех
+odds-partial = частично
+odds-failure = провал
+
+stats-session = *Кубики за эту игру:*
+stats-campaign = *Кубики за всю кампанию:*
+stats-none = Кубики ещё не бросали
+stats-line = *{ $name }*: { $rolls ->
+    [one] { $rolls } бросок
+    [few] { $rolls } броска
+   *[other] { $rolls } бросков
+}, { $dice ->
+    [one] { $dice } кубик
+    [few] { $dice } кубика
+   *[other] { $dice } кубиков
+}, в среднем { $averages }, { $luck } от ожидаемой суммы
+stats-average = { $average } на d{ $sides }
+stats-crits = { $crits ->
+    [one] { $crits } крит
+    [few] { $crits } крита
+   *[other] { $crits } критов
+} в { $action_rolls ->
+    [one] { $action_rolls } броске действия
+   *[other] { $action_rolls } бросках действия
+}
+stats-cursed = *Проклятые кубики:*
+stats-cursed-line = { $place }. { $name }: { $luck } от ожидаемой суммы
+
+fair-started = *Честные кубики включены.* Бот бросает по секретному зерну с хешем SHA-256 `{ $commitment }`, оно будет раскрыто по /session end.
+fair-ended = *Честные кубики выключены.* Зерно: `{ $seed }`, его хеш: `{ $commitment }`. { $rolls ->
+    [one] Проверьте { $rolls } бросок
+    [few] Проверьте { $rolls } броска
+   *[other] Проверьте { $rolls } бросков
+} по нему командой /verify.
+fair-roll = { $roll }, честный бросок №{ $number }
+fair-verified = Зерно совпадает с хешем `{ $commitment }` и даёт { $rolls ->
+    [one] { $rolls } бросок
+    [few] все { $rolls } броска
+   *[other] все { $rolls } бросков
+} этой игры.
+fair-wrong-seed = Это не зерно с хешем `{ $commitment }`.
+fair-mismatches = *Зерно не даёт эти броски:*
+fair-mismatch = №{ $number } { $dice }: выпало { $values }, по зерну { $expected }
+
+help-header = Поддерживаются команды:
+cmd-help = показать эту справку
+cmd-wipe = очистить всё
+cmd-r1 = [позиция] [эффект] - бросить 1 кубик, например /r1 desperate great
+cmd-r2 = [позиция] [эффект] - бросить 2 кубика
+cmd-r3 = [позиция] [эффект] - бросить 3 кубика
+cmd-t = [*] [фильтр] - таймеры: * для идущих, фильтр по названию или по группе вида "Группа:"
+cmd-p = [*] [фильтр] - игроки: * для тех, у кого есть вред или стресс, фильтр по имени
+cmd-pa = <имя> - добавить игрока
+cmd-ta = <название> [значение] - добавить таймер
+cmd-pr = <имя> = <новое имя> - переименовать игрока
+cmd-tr = <название> = <новое название> - переименовать таймер
+cmd-te = <название> = <значение>[/<максимум>] - изменить значение и максимум таймера
+cmd-td = <название> = <задержка или время> - таймер по часам, например 30m, 1h30m или 20:00
+cmd-quiet = переключить объявления изменений с кнопок
+cmd-settings = настройки чата
+cmd-dash = [off] - закрепить сообщение с игроками и таймерами, которое всегда актуально
+cmd-roll = <кубики> - бросить кубики, например 3d6, d20+2 или 4d6kh3
+cmd-stats = [all] - статистика кубиков за игру или за всю кампанию
+cmd-odds = [пул или кубики] - точные шансы, например /odds 2 или /odds 4d6kh3 >= 15
+cmd-schedule = <дата>; <дата>... - опрос, когда играть, например 2024-05-04 19:00
+cmd-session = <start|end> - честные броски бота по зерну, хеш которого виден в начале, а само зерно в конце
+cmd-verify = [зерно] - проверить броски последней честной игры по её зерну
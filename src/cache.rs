@@ -0,0 +1,53 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use teloxide::types::ChatId;
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+use crate::tracker::Tracker;
+
+/// Process-wide cache of trackers, used when the bot runs as a long-lived
+/// process. Every chat gets a slot holding the last stored tracker and a lock
+/// which serializes updates for that chat.
+#[derive(Clone, Default)]
+pub struct TrackerCache {
+    chats: Arc<Mutex<HashMap<ChatId, Arc<ChatSlot>>>>,
+}
+
+#[derive(Default)]
+pub struct ChatSlot {
+    lock: Arc<AsyncMutex<()>>,
+    tracker: Mutex<Option<Tracker>>,
+}
+
+impl TrackerCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn slot(&self, chat_id: ChatId) -> Arc<ChatSlot> {
+        self.chats
+            .lock()
+            .unwrap()
+            .entry(chat_id)
+            .or_default()
+            .clone()
+    }
+}
+
+impl ChatSlot {
+    /// Waits until no other update for this chat is being handled.
+    pub async fn lock(&self) -> OwnedMutexGuard<()> {
+        self.lock.clone().lock_owned().await
+    }
+
+    pub fn get(&self) -> Option<Tracker> {
+        self.tracker.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, tracker: &Tracker) {
+        *self.tracker.lock().unwrap() = Some(tracker.clone());
+    }
+}
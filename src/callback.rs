@@ -1,139 +1,819 @@
-use anyhow::bail;
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, bail};
 use strum::{AsRefStr, EnumString};
-use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+use teloxide::types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::{
+    i18n::Locale,
+    poll::PollOption,
+    scheduler::{format_date_time, format_utc_offset},
+    settings::ChatSettings,
+    tracker::{ListView, Tracker},
+};
 
-use crate::tracker::{Player, Timer};
+/// Layout of the callback data, bumped whenever it changes. Buttons with
+/// any other version are answered as outdated.
+const CALLBACK_VERSION: &str = "2";
 
-#[derive(Clone, EnumString, AsRefStr)]
+/// Codes are stored in buttons already sent to chats: keep them when
+/// renaming variants, and never reuse the code of a removed action.
+#[derive(Clone, Copy, Debug, PartialEq, EnumString, AsRefStr)]
 pub enum CallbackAction {
+    #[strum(serialize = "n")]
     NoAction,
+    #[strum(serialize = "t+")]
     AddTimer,
+    #[strum(serialize = "t-")]
     SubTimer,
+    #[strum(serialize = "tx")]
     DeleteTimer,
+    #[strum(serialize = "h+")]
     AddHarm,
+    #[strum(serialize = "h-")]
     SubHarm,
+    #[strum(serialize = "s+")]
     AddStress,
+    #[strum(serialize = "s-")]
     SubStress,
+    #[strum(serialize = "px")]
     DeletePlayer,
+    #[strum(serialize = "kt")]
     ShowTimersKb,
+    #[strum(serialize = "kp")]
     ShowPlayersKb,
+    #[strum(serialize = "kh")]
     ShowHarmKb,
+    #[strum(serialize = "ks")]
     ShowStressKb,
+    #[strum(serialize = "xt")]
     HideTimersKb,
+    #[strum(serialize = "xp")]
     HidePlayersKb,
+    #[strum(serialize = "oa")]
+    ToggleAnnounceChanges,
+    #[strum(serialize = "c+")]
+    AddStressCap,
+    #[strum(serialize = "c-")]
+    SubStressCap,
+    #[strum(serialize = "z+")]
+    AddClockSize,
+    #[strum(serialize = "z-")]
+    SubClockSize,
+    #[strum(serialize = "od")]
+    ToggleDeleteFiredTimers,
+    #[strum(serialize = "ol")]
+    NextLanguage,
+    #[strum(serialize = "pt")]
+    TimersPage,
+    #[strum(serialize = "pp")]
+    PlayersPage,
+    #[strum(serialize = "g+")]
+    AddPageSize,
+    #[strum(serialize = "g-")]
+    SubPageSize,
+    #[strum(serialize = "hk")]
+    HarmKeypad,
+    #[strum(serialize = "sk")]
+    StressKeypad,
+    #[strum(serialize = "tk")]
+    TimerKeypad,
+    #[strum(serialize = "h=")]
+    SetHarm,
+    #[strum(serialize = "s=")]
+    SetStress,
+    #[strum(serialize = "t=")]
+    SetTimer,
+    #[strum(serialize = "os")]
+    NextSteps,
+    #[strum(serialize = "p*")]
+    PinPlayer,
+    #[strum(serialize = "t*")]
+    PinTimer,
+    #[strum(serialize = "pn")]
+    RenamePlayer,
+    #[strum(serialize = "tn")]
+    RenameTimer,
+    #[strum(serialize = "sr")]
+    ResetStress,
+    #[strum(serialize = "ok")]
+    Confirm,
+    #[strum(serialize = "no")]
+    CancelConfirm,
+    #[strum(serialize = "uo")]
+    ChangeUtcOffset,
+    #[strum(serialize = "v")]
+    Vote,
+    #[strum(serialize = "vc")]
+    ClosePoll,
 }
 
+/// Button data, serialized as
+/// `<version>.<action>.<item id>.<revision>.<value>.<checksum>`, well within
+/// Telegram's 64 byte limit.
+#[derive(Debug, PartialEq)]
 pub struct Callback {
     pub item_id: usize,
     pub action: CallbackAction,
+    /// Revision of the listed players or timers when the keyboard was made,
+    /// or the id of the confirmation or poll
+    pub revision: u32,
+    /// Step of `+`/`-` buttons, or the number entered on a keypad
+    pub value: i32,
 }
 
 impl Callback {
-    fn serialize(&self) -> String {
-        format!("{}|{}", self.item_id, self.action.as_ref())
+    pub fn serialize(&self, chat_id: ChatId) -> String {
+        let payload = format!(
+            "{}.{}.{}.{}.{}",
+            CALLBACK_VERSION,
+            self.action.as_ref(),
+            self.item_id,
+            self.revision,
+            self.value
+        );
+        format!("{}.{:08x}", payload, checksum(chat_id, &payload))
     }
 
-    pub fn deserialize(cb: &str) -> anyhow::Result<Self> {
-        let split = cb.split("|").collect::<Vec<_>>();
-        if split.len() != 2 {
-            bail!("Invalid callback data: {}", cb);
+    pub fn deserialize(chat_id: ChatId, cb: &str) -> anyhow::Result<Self> {
+        let Some((payload, sum)) = cb.rsplit_once('.') else {
+            // Buttons made before the data was versioned
+            return Err(StaleKeyboard.into());
+        };
+        let split = payload.split('.').collect::<Vec<_>>();
+        if split[0] != CALLBACK_VERSION {
+            return Err(StaleKeyboard.into());
         }
+        if u32::from_str_radix(sum, 16).ok() != Some(checksum(chat_id, payload)) {
+            bail!("Invalid callback data checksum: {}", cb);
+        }
+        let [_, action, item_id, revision, value] = split.as_slice() else {
+            bail!("Invalid callback data: {}", cb);
+        };
         Ok(Callback {
-            item_id: split[0].parse()?,
-            action: CallbackAction::from_str(split[1])?,
+            item_id: item_id.parse()?,
+            // Actions which no longer exist
+            action: CallbackAction::from_str(action).map_err(|_| StaleKeyboard)?,
+            revision: revision.parse()?,
+            value: value.parse()?,
         })
     }
 }
 
-pub fn make_manage_timers_keyboard(timers: &[Timer]) -> InlineKeyboardMarkup {
+/// FNV-1a hash of the chat id and the payload. Catches corrupted or
+/// hand-made data and buttons copied from other chats; it is not a signature.
+fn checksum(chat_id: ChatId, payload: &str) -> u32 {
+    chat_id
+        .0
+        .to_le_bytes()
+        .iter()
+        .chain(payload.as_bytes())
+        .fold(0x811c_9dc5, |hash, byte| {
+            (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+        })
+}
+
+/// Returned for buttons of keyboards made before the items they list were
+/// added or removed, or made by an older version of the bot.
+#[derive(Debug)]
+pub struct StaleKeyboard;
+
+impl StaleKeyboard {
+    /// Fails unless the button was made for the current revision of its list
+    pub fn check(revision: u32, current: u32) -> anyhow::Result<()> {
+        if revision != current {
+            return Err(StaleKeyboard.into());
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for StaleKeyboard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "This keyboard is outdated")
+    }
+}
+
+impl std::error::Error for StaleKeyboard {}
+
+/// Makes buttons bound to a chat and to a revision of the listed items
+struct Buttons {
+    chat_id: ChatId,
+    revision: u32,
+}
+
+impl Buttons {
+    fn button(&self, item_id: usize, name: &str, action: CallbackAction) -> InlineKeyboardButton {
+        self.button_with_value(item_id, 0, name, action)
+    }
+
+    fn button_with_value(
+        &self,
+        item_id: usize,
+        value: i32,
+        name: &str,
+        action: CallbackAction,
+    ) -> InlineKeyboardButton {
+        let callback = Callback {
+            item_id,
+            action,
+            revision: self.revision,
+            value,
+        };
+        InlineKeyboardButton::callback(name, callback.serialize(self.chat_id))
+    }
+
+    /// `+step` buttons for each step, then `-step` buttons
+    fn steps(
+        &self,
+        item_id: usize,
+        steps: &[u16],
+        label: impl Fn(String) -> String,
+        add: CallbackAction,
+        sub: CallbackAction,
+    ) -> Vec<InlineKeyboardButton> {
+        let add = steps.iter().map(|step| {
+            self.button_with_value(item_id, (*step).into(), &label(format!("+{step}")), add)
+        });
+        let sub = steps.iter().map(|step| {
+            self.button_with_value(item_id, (*step).into(), &label(format!("-{step}")), sub)
+        });
+        add.chain(sub).collect()
+    }
+}
+
+/// Items of one keyboard page
+struct Page<'a, T> {
+    items: Vec<&'a T>,
+    number: usize,
+    count: usize,
+}
+
+impl<'a, T> Page<'a, T> {
+    /// `active` tells whether an item is shown when only active items are
+    fn new(
+        items: &'a [T],
+        name: impl Fn(&T) -> &str,
+        active: impl Fn(&T) -> bool,
+        view: &ListView,
+        size: u16,
+    ) -> Self {
+        let size = usize::from(size.max(1));
+        let matching = items
+            .iter()
+            .filter(|item| view.matches(name(item), active(item)))
+            .collect::<Vec<_>>();
+        let count = matching.len().div_ceil(size).max(1);
+        let number = view.page.min(count - 1);
+        let items = matching
+            .into_iter()
+            .skip(number * size)
+            .take(size)
+            .collect();
+        Self {
+            items,
+            number,
+            count,
+        }
+    }
+
+    /// Previous and next page buttons, which wrap around. The page number is
+    /// passed as the item id.
+    fn buttons(
+        &self,
+        buttons: &Buttons,
+        action: CallbackAction,
+    ) -> Option<Vec<InlineKeyboardButton>> {
+        if self.count < 2 {
+            return None;
+        }
+        let prev = (self.number + self.count - 1) % self.count;
+        let next = (self.number + 1) % self.count;
+        let position = format!("{}/{}", self.number + 1, self.count);
+        Some(vec![
+            buttons.button(prev, "◀", action),
+            buttons.button(0, &position, CallbackAction::NoAction),
+            buttons.button(next, "▶", action),
+        ])
+    }
+}
+
+pub fn make_manage_timers_keyboard(
+    locale: &Locale,
+    chat_id: ChatId,
+    tracker: &Tracker,
+    view: &ListView,
+) -> InlineKeyboardMarkup {
+    let buttons = Buttons {
+        chat_id,
+        revision: tracker.timers_revision,
+    };
+    let steps = &tracker.settings.steps;
     let mut keyboard: Vec<Vec<InlineKeyboardButton>> = Vec::new();
 
-    for timer in timers.iter() {
-        keyboard.push(vec![
-            create_button(timer.id, timer.name.as_str(), CallbackAction::NoAction),
-            create_button(timer.id, "+1", CallbackAction::AddTimer),
-            create_button(timer.id, "-1", CallbackAction::SubTimer),
-            create_button(timer.id, "Delete", CallbackAction::DeleteTimer),
-        ]);
+    let page = Page::new(
+        &tracker.timers,
+        |timer| &timer.name,
+        |timer| timer.value > 0 || timer.due_at.is_some(),
+        view,
+        tracker.settings.page_size,
+    );
+    for timer in page.items.iter() {
+        // Real-time timers run on their own
+        let mut row = if timer.due_at.is_some() {
+            vec![buttons.button(timer.id, timer.name.as_str(), CallbackAction::NoAction)]
+        } else {
+            let mut row =
+                vec![buttons.button(timer.id, timer.name.as_str(), CallbackAction::TimerKeypad)];
+            row.extend(buttons.steps(
+                timer.id,
+                steps,
+                |step| step,
+                CallbackAction::AddTimer,
+                CallbackAction::SubTimer,
+            ));
+            row
+        };
+        row.push(buttons.button(
+            timer.id,
+            &locale.plain("button-delete", &[]),
+            CallbackAction::DeleteTimer,
+        ));
+        keyboard.push(row);
     }
-    // keyboard.push(vec![create_button(0, "Hide", CallbackAction::HideTimersKb)]);
+    keyboard.extend(page.buttons(&buttons, CallbackAction::TimersPage));
+    // keyboard.push(vec![buttons.button(0, "Hide", CallbackAction::HideTimersKb)]);
 
     InlineKeyboardMarkup::new(keyboard)
 }
 
-pub fn make_manage_harm_keyboard(players: &[Player]) -> InlineKeyboardMarkup {
+pub fn make_manage_harm_keyboard(
+    locale: &Locale,
+    chat_id: ChatId,
+    tracker: &Tracker,
+    view: &ListView,
+) -> InlineKeyboardMarkup {
+    let buttons = Buttons {
+        chat_id,
+        revision: tracker.players_revision,
+    };
+    let steps = &tracker.settings.steps;
     let mut keyboard: Vec<Vec<InlineKeyboardButton>> = Vec::new();
 
-    for player in players.iter() {
-        keyboard.push(vec![
-            create_button(player.id, player.name.as_str(), CallbackAction::NoAction),
-            create_button(player.id, "+1 harm", CallbackAction::AddHarm),
-            create_button(player.id, "-1 harm", CallbackAction::SubHarm),
-        ]);
+    let page = Page::new(
+        &tracker.players,
+        |player| &player.name,
+        |player| player.harm > 0 || player.stress > 0,
+        view,
+        tracker.settings.page_size,
+    );
+    for player in page.items.iter() {
+        let mut row =
+            vec![buttons.button(player.id, player.name.as_str(), CallbackAction::HarmKeypad)];
+        row.extend(buttons.steps(
+            player.id,
+            steps,
+            |step| locale.plain("button-harm", &[("step", (&step).into())]),
+            CallbackAction::AddHarm,
+            CallbackAction::SubHarm,
+        ));
+        keyboard.push(row);
     }
-    keyboard.push(vec![create_button(0, "Back", CallbackAction::HidePlayersKb)]);
+    keyboard.extend(page.buttons(&buttons, CallbackAction::PlayersPage));
+    keyboard.push(vec![buttons.button(
+        0,
+        &locale.plain("button-back", &[]),
+        CallbackAction::HidePlayersKb,
+    )]);
 
     InlineKeyboardMarkup::new(keyboard)
 }
 
-pub fn make_manage_stress_keyboard(players: &[Player]) -> InlineKeyboardMarkup {
+pub fn make_manage_stress_keyboard(
+    locale: &Locale,
+    chat_id: ChatId,
+    tracker: &Tracker,
+    view: &ListView,
+) -> InlineKeyboardMarkup {
+    let buttons = Buttons {
+        chat_id,
+        revision: tracker.players_revision,
+    };
+    let steps = &tracker.settings.steps;
     let mut keyboard: Vec<Vec<InlineKeyboardButton>> = Vec::new();
 
-    for player in players.iter() {
-        keyboard.push(vec![
-            create_button(player.id, player.name.as_str(), CallbackAction::NoAction),
-            create_button(player.id, "+1 stress", CallbackAction::AddStress),
-            create_button(player.id, "-1 stress", CallbackAction::SubStress),
-        ]);
+    let page = Page::new(
+        &tracker.players,
+        |player| &player.name,
+        |player| player.harm > 0 || player.stress > 0,
+        view,
+        tracker.settings.page_size,
+    );
+    for player in page.items.iter() {
+        let mut row = vec![buttons.button(
+            player.id,
+            player.name.as_str(),
+            CallbackAction::StressKeypad,
+        )];
+        row.extend(buttons.steps(
+            player.id,
+            steps,
+            |step| locale.plain("button-stress", &[("step", (&step).into())]),
+            CallbackAction::AddStress,
+            CallbackAction::SubStress,
+        ));
+        keyboard.push(row);
     }
-    keyboard.push(vec![create_button(0, "Back", CallbackAction::HidePlayersKb)]);
+    keyboard.extend(page.buttons(&buttons, CallbackAction::PlayersPage));
+    keyboard.push(vec![
+        buttons.button(
+            0,
+            &locale.plain("button-reset-stress", &[]),
+            CallbackAction::ResetStress,
+        ),
+        buttons.button(
+            0,
+            &locale.plain("button-back", &[]),
+            CallbackAction::HidePlayersKb,
+        ),
+    ]);
 
     InlineKeyboardMarkup::new(keyboard)
 }
 
-pub fn make_manage_players_keyboard(players: &[Player]) -> InlineKeyboardMarkup {
+pub fn make_manage_players_keyboard(
+    locale: &Locale,
+    chat_id: ChatId,
+    tracker: &Tracker,
+    view: &ListView,
+) -> InlineKeyboardMarkup {
+    let buttons = Buttons {
+        chat_id,
+        revision: tracker.players_revision,
+    };
     let mut keyboard: Vec<Vec<InlineKeyboardButton>> = Vec::new();
 
-    for player in players.iter() {
+    let page = Page::new(
+        &tracker.players,
+        |player| &player.name,
+        |player| player.harm > 0 || player.stress > 0,
+        view,
+        tracker.settings.page_size,
+    );
+    for player in page.items.iter() {
         keyboard.push(vec![
-            create_button(player.id, player.name.as_str(), CallbackAction::NoAction),
-            create_button(player.id, "Delete", CallbackAction::DeletePlayer),
+            buttons.button(player.id, player.name.as_str(), CallbackAction::NoAction),
+            buttons.button(
+                player.id,
+                &locale.plain("button-delete", &[]),
+                CallbackAction::DeletePlayer,
+            ),
         ]);
     }
-    keyboard.push(vec![create_button(
+    keyboard.extend(page.buttons(&buttons, CallbackAction::PlayersPage));
+    keyboard.push(vec![buttons.button(
         0,
-        "Back",
+        &locale.plain("button-back", &[]),
         CallbackAction::HidePlayersKb,
     )]);
 
     InlineKeyboardMarkup::new(keyboard)
 }
 
-pub fn make_players_keyboard() -> InlineKeyboardMarkup {
+pub fn make_players_keyboard(locale: &Locale, chat_id: ChatId) -> InlineKeyboardMarkup {
+    let buttons = Buttons {
+        chat_id,
+        revision: 0,
+    };
     let mut keyboard: Vec<Vec<InlineKeyboardButton>> = Vec::new();
 
     keyboard.push(vec![
-        create_button(0, "Manage harm", CallbackAction::ShowHarmKb),
-        create_button(0, "Manage stress", CallbackAction::ShowStressKb),
-        create_button(0, "Manage players", CallbackAction::ShowPlayersKb),
+        buttons.button(
+            0,
+            &locale.plain("button-manage-harm", &[]),
+            CallbackAction::ShowHarmKb,
+        ),
+        buttons.button(
+            0,
+            &locale.plain("button-manage-stress", &[]),
+            CallbackAction::ShowStressKb,
+        ),
+        buttons.button(
+            0,
+            &locale.plain("button-manage-players", &[]),
+            CallbackAction::ShowPlayersKb,
+        ),
     ]);
 
     InlineKeyboardMarkup::new(keyboard)
 }
 
-pub fn make_timers_keyboard() -> InlineKeyboardMarkup {
+pub fn make_timers_keyboard(locale: &Locale, chat_id: ChatId) -> InlineKeyboardMarkup {
+    let buttons = Buttons {
+        chat_id,
+        revision: 0,
+    };
     let mut keyboard: Vec<Vec<InlineKeyboardButton>> = Vec::new();
 
-    keyboard.push(vec![create_button(
+    keyboard.push(vec![buttons.button(
         0,
-        "Manage",
+        &locale.plain("button-manage", &[]),
         CallbackAction::ShowTimersKb,
     )]);
 
     InlineKeyboardMarkup::new(keyboard)
 }
 
-fn create_button(item_id: usize, name: &str, action: CallbackAction) -> InlineKeyboardButton {
-    InlineKeyboardButton::callback(name, Callback { item_id, action }.serialize())
+/// Confirm and cancel buttons for the confirmation `confirm_id`
+pub fn make_confirm_keyboard(
+    locale: &Locale,
+    chat_id: ChatId,
+    confirm_id: u32,
+) -> InlineKeyboardMarkup {
+    let buttons = Buttons {
+        chat_id,
+        revision: confirm_id,
+    };
+    InlineKeyboardMarkup::new(vec![vec![
+        buttons.button(
+            0,
+            &locale.plain("button-confirm", &[]),
+            CallbackAction::Confirm,
+        ),
+        buttons.button(
+            0,
+            &locale.plain("button-cancel", &[]),
+            CallbackAction::CancelConfirm,
+        ),
+    ]])
+}
+
+/// A button per proposed date showing how many can come, and one to close
+/// the poll
+pub fn make_poll_keyboard(
+    locale: &Locale,
+    chat_id: ChatId,
+    poll_id: u32,
+    options: &[PollOption],
+    utc_offset: i32,
+) -> InlineKeyboardMarkup {
+    let buttons = Buttons {
+        chat_id,
+        revision: poll_id,
+    };
+    let mut keyboard = options
+        .iter()
+        .enumerate()
+        .map(|(i, option)| {
+            let label = format!(
+                "{} ({})",
+                format_date_time(option.starts_at, utc_offset),
+                option.votes.len()
+            );
+            vec![buttons.button(i, &label, CallbackAction::Vote)]
+        })
+        .collect::<Vec<_>>();
+    keyboard.push(vec![buttons.button(
+        0,
+        &locale.plain("button-close-poll", &[]),
+        CallbackAction::ClosePoll,
+    )]);
+    InlineKeyboardMarkup::new(keyboard)
+}
+
+/// What a keypad sets. The keypad also lets the item be pinned or renamed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeypadTarget {
+    Harm,
+    Stress,
+    Timer,
+}
+
+/// Largest number that can be entered on a keypad
+const MAX_KEYPAD_VALUE: i32 = 999;
+
+/// Digits to enter a new value for an item. Every button carries the number
+/// entered so far, so the keypad keeps no state of its own.
+pub fn make_keypad_keyboard(
+    locale: &Locale,
+    chat_id: ChatId,
+    tracker: &Tracker,
+    target: KeypadTarget,
+    item_id: usize,
+    entered: i32,
+) -> anyhow::Result<InlineKeyboardMarkup> {
+    let player = || {
+        tracker
+            .players
+            .iter()
+            .find(|player| player.id == item_id)
+            .map(|player| (&player.name, player.pinned))
+            .ok_or(anyhow!("Player id {} not found", item_id))
+    };
+    let ((name, pinned), revision) = match target {
+        KeypadTarget::Harm | KeypadTarget::Stress => (player()?, tracker.players_revision),
+        KeypadTarget::Timer => (
+            tracker
+                .timers
+                .iter()
+                .find(|timer| timer.id == item_id)
+                .map(|timer| (&timer.name, timer.pinned))
+                .ok_or(anyhow!("Timer id {} not found", item_id))?,
+            tracker.timers_revision,
+        ),
+    };
+    let (key, digit, set, cancel, pin, rename) = match target {
+        KeypadTarget::Harm => (
+            "keypad-harm",
+            CallbackAction::HarmKeypad,
+            CallbackAction::SetHarm,
+            CallbackAction::ShowHarmKb,
+            CallbackAction::PinPlayer,
+            CallbackAction::RenamePlayer,
+        ),
+        KeypadTarget::Stress => (
+            "keypad-stress",
+            CallbackAction::StressKeypad,
+            CallbackAction::SetStress,
+            CallbackAction::ShowStressKb,
+            CallbackAction::PinPlayer,
+            CallbackAction::RenamePlayer,
+        ),
+        KeypadTarget::Timer => (
+            "keypad-timer",
+            CallbackAction::TimerKeypad,
+            CallbackAction::SetTimer,
+            CallbackAction::ShowTimersKb,
+            CallbackAction::PinTimer,
+            CallbackAction::RenameTimer,
+        ),
+    };
+    let buttons = Buttons { chat_id, revision };
+    let digit_button = |number: i32| {
+        let label = number.to_string();
+        match entered
+            .checked_mul(10)
+            .and_then(|value| value.checked_add(number))
+            .filter(|value| *value <= MAX_KEYPAD_VALUE)
+        {
+            Some(value) => buttons.button_with_value(item_id, value, &label, digit),
+            None => buttons.button(item_id, &label, CallbackAction::NoAction),
+        }
+    };
+
+    let title = locale.plain(key, &[("name", name.into()), ("value", entered.into())]);
+    let mut keyboard = vec![vec![buttons.button(
+        item_id,
+        &title,
+        CallbackAction::NoAction,
+    )]];
+    for row in [[1, 2, 3], [4, 5, 6], [7, 8, 9]] {
+        keyboard.push(row.into_iter().map(digit_button).collect());
+    }
+    keyboard.push(vec![
+        buttons.button_with_value(item_id, entered / 10, "⌫", digit),
+        digit_button(0),
+        buttons.button_with_value(item_id, entered, "✓", set),
+    ]);
+    let pin_label = if pinned { "button-unpin" } else { "button-pin" };
+    keyboard.push(vec![
+        buttons.button(item_id, &locale.plain(pin_label, &[]), pin),
+        buttons.button(item_id, &locale.plain("button-rename", &[]), rename),
+    ]);
+    keyboard.push(vec![buttons.button(
+        0,
+        &locale.plain("button-cancel", &[]),
+        cancel,
+    )]);
+
+    Ok(InlineKeyboardMarkup::new(keyboard))
+}
+
+pub fn make_settings_keyboard(
+    locale: &Locale,
+    chat_id: ChatId,
+    settings: &ChatSettings,
+) -> InlineKeyboardMarkup {
+    let buttons = Buttons {
+        chat_id,
+        revision: 0,
+    };
+    let stress_cap = match settings.stress_cap {
+        Some(cap) => locale.plain("settings-stress-cap", &[("cap", cap.into())]),
+        None => locale.plain("settings-stress-cap-none", &[]),
+    };
+    let language = match settings.language {
+        Some(language) => locale.plain(&format!("language-{}", language.as_ref()), &[]),
+        None => locale.plain("language-auto", &[]),
+    };
+
+    let steps = settings
+        .steps
+        .iter()
+        .map(|step| step.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let keyboard = vec![
+        vec![buttons.button(
+            0,
+            &locale.plain(
+                "settings-announce",
+                &[("enabled", settings.announce_changes.into())],
+            ),
+            CallbackAction::ToggleAnnounceChanges,
+        )],
+        vec![
+            buttons.button(0, &stress_cap, CallbackAction::NoAction),
+            buttons.button(0, "+1", CallbackAction::AddStressCap),
+            buttons.button(0, "-1", CallbackAction::SubStressCap),
+        ],
+        vec![
+            buttons.button(
+                0,
+                &locale.plain(
+                    "settings-clock-size",
+                    &[("size", settings.clock_size.into())],
+                ),
+                CallbackAction::NoAction,
+            ),
+            buttons.button(0, "+1", CallbackAction::AddClockSize),
+            buttons.button(0, "-1", CallbackAction::SubClockSize),
+        ],
+        vec![
+            buttons.button(
+                0,
+                &locale.plain("settings-page-size", &[("size", settings.page_size.into())]),
+                CallbackAction::NoAction,
+            ),
+            buttons.button(0, "+1", CallbackAction::AddPageSize),
+            buttons.button(0, "-1", CallbackAction::SubPageSize),
+        ],
+        vec![
+            buttons.button(
+                0,
+                &locale.plain(
+                    "settings-utc-offset",
+                    &[("zone", (&format_utc_offset(settings.utc_offset)).into())],
+                ),
+                CallbackAction::NoAction,
+            ),
+            buttons.button_with_value(0, 60, "+1h", CallbackAction::ChangeUtcOffset),
+            buttons.button_with_value(0, -60, "-1h", CallbackAction::ChangeUtcOffset),
+            buttons.button_with_value(0, 30, "+30m", CallbackAction::ChangeUtcOffset),
+        ],
+        vec![buttons.button(
+            0,
+            &locale.plain(
+                "settings-delete-fired",
+                &[("enabled", settings.delete_fired_timers.into())],
+            ),
+            CallbackAction::ToggleDeleteFiredTimers,
+        )],
+        vec![buttons.button(
+            0,
+            &locale.plain("settings-steps", &[("steps", (&steps).into())]),
+            CallbackAction::NextSteps,
+        )],
+        vec![buttons.button(
+            0,
+            &locale.plain("settings-language", &[("language", (&language).into())]),
+            CallbackAction::NextLanguage,
+        )],
+    ];
+
+    InlineKeyboardMarkup::new(keyboard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn callback_data_is_bound_to_chat() {
+        let callback = Callback {
+            item_id: usize::MAX,
+            action: CallbackAction::ToggleDeleteFiredTimers,
+            revision: u32::MAX,
+            value: i32::MIN,
+        };
+        let data = callback.serialize(ChatId(-1001));
+        assert!(data.len() <= 64, "{data} is too long");
+        assert_eq!(
+            Callback::deserialize(ChatId(-1001), &data).unwrap(),
+            callback
+        );
+
+        assert!(Callback::deserialize(ChatId(-1002), &data).is_err());
+        let tampered = data.replacen(".od.", ".tx.", 1);
+        assert!(Callback::deserialize(ChatId(-1001), &tampered).is_err());
+    }
+
+    #[test]
+    fn old_formats_are_stale() {
+        for data in ["1|AddHarm", "1.h+.1.0.00000000"] {
+            let err = Callback::deserialize(ChatId(1), data).unwrap_err();
+            assert!(err.is::<StaleKeyboard>(), "{data}: {err}");
+        }
+    }
 }
@@ -1,87 +1,624 @@
-use std::env;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    env, fmt,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use aws_config::{meta::region::RegionProviderChain, BehaviorVersion};
-use aws_sdk_s3::{operation::get_object::GetObjectError, primitives::ByteStream, Client};
-use teloxide::types::ChatId;
+use aws_sdk_s3::{
+    error::SdkError, operation::get_object::GetObjectError, primitives::ByteStream, types::Object,
+    Client,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use teloxide::types::{ChatId, UpdateId, UserId};
+use tokio::sync::OwnedMutexGuard;
 use tracing::{info, instrument, warn};
 
-use crate::tracker::Tracker;
+use crate::{
+    cache::{ChatSlot, TrackerCache},
+    sqlite::SqliteStore,
+    tracker::Tracker,
+};
 
 const S3_BUCKET_ENV_VAR: &str = "S3_BUCKET";
+const STORAGE_ENV_VAR: &str = "STORAGE";
+const SQLITE_PATH_ENV_VAR: &str = "SQLITE_PATH";
+const DEFAULT_SQLITE_PATH: &str = "dnd_bot.sqlite";
+/// Next due time of real-time timers per chat, kept in S3 as empty objects
+/// named `schedule/<chat id>/<unix time>` so that due chats can be found by
+/// listing them, without reading every tracker. Every chat only writes its
+/// own keys, so concurrent updates of different chats cannot lose entries.
+const S3_SCHEDULE_PREFIX: &str = "schedule/";
+/// Handled updates are kept in S3 as empty objects named
+/// `updates/<chat dir>/<update id>`, created only if missing so that one of
+/// two concurrent deliveries fails. Telegram stops redelivering after a day,
+/// so a lifecycle rule of the bucket may expire them after that.
+const S3_UPDATES_PREFIX: &str = "updates/";
+/// Chats of each user, kept in S3 as empty objects named
+/// `users/<user id>/<chat id>/<percent-encoded title>`. Every membership has
+/// its own key, so concurrent updates in different chats cannot lose one.
+const S3_MEMBERS_PREFIX: &str = "users/";
+/// Keeps member keys within the 1024 bytes S3 allows, even for emoji
+const MAX_S3_MEMBER_TITLE_CHARS: usize = 64;
 
-pub struct BotContext {
-    client: Client,
-    s3_path: String,
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StorageKind {
+    S3,
+    Sqlite,
 }
 
-impl BotContext {
-    pub async fn new(chat_id: ChatId) -> Self {
+impl StorageKind {
+    fn from_env() -> anyhow::Result<Self> {
+        match env::var(STORAGE_ENV_VAR).as_deref() {
+            Ok("s3") | Err(_) => Ok(StorageKind::S3),
+            Ok("sqlite") => Ok(StorageKind::Sqlite),
+            Ok(other) => bail!("Unknown {STORAGE_ENV_VAR} value: {other}"),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Backend {
+    S3(Client),
+    Sqlite(SqliteStore),
+}
+
+/// Storage shared by all updates. Construct once per process and hand out
+/// per-chat [`BotContext`]s.
+#[derive(Clone)]
+pub struct Storage {
+    backend: Backend,
+    cache: Option<TrackerCache>,
+    /// Chat titles stored per member by this process, to skip writes which
+    /// would change nothing
+    members: Arc<Mutex<HashMap<(UserId, ChatId), String>>>,
+}
+
+impl Storage {
+    #[instrument]
+    pub async fn from_env() -> anyhow::Result<Self> {
+        let start = Instant::now();
+        let storage = match StorageKind::from_env()? {
+            StorageKind::S3 => {
+                info!("Using S3 storage");
+                Self::s3().await
+            }
+            StorageKind::Sqlite => {
+                let path = env::var(SQLITE_PATH_ENV_VAR).unwrap_or(DEFAULT_SQLITE_PATH.to_owned());
+                info!("Using SQLite storage at {}", path);
+                Self::sqlite(&path)?
+            }
+        };
+        info!("Storage initialized in {:?}", start.elapsed());
+        Ok(storage)
+    }
+
+    pub async fn s3() -> Self {
         let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
         let config = aws_config::defaults(BehaviorVersion::latest())
             .region(region_provider)
             .load()
             .await;
+        Self {
+            backend: Backend::S3(Client::new(&config)),
+            cache: None,
+            members: Default::default(),
+        }
+    }
 
-        let dir = if chat_id.0 < 0 {
-            format!("_{}", -chat_id.0)
-        } else {
-            chat_id.to_string()
+    pub fn sqlite(path: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            backend: Backend::Sqlite(SqliteStore::open(path)?),
+            cache: None,
+            members: Default::default(),
+        })
+    }
+
+    /// Keeps trackers in memory between updates and serializes updates per
+    /// chat. Only valid when this process is the sole writer to the store.
+    pub fn with_cache(self) -> Self {
+        Self {
+            cache: Some(TrackerCache::new()),
+            ..self
+        }
+    }
+
+    /// Whether the process outlives the update it handles, which is when the
+    /// cache is used. Lambda freezes the process once an update is answered.
+    pub fn long_lived(&self) -> bool {
+        self.cache.is_some()
+    }
+
+    /// Remembers that the user takes part in the chat, so that the chat's
+    /// timers can be shown to them in inline mode.
+    pub async fn add_member(
+        &self,
+        user_id: UserId,
+        chat_id: ChatId,
+        title: &str,
+    ) -> anyhow::Result<()> {
+        let known = self
+            .members
+            .lock()
+            .unwrap()
+            .get(&(user_id, chat_id))
+            .cloned();
+        if known.as_deref() == Some(title) {
+            return Ok(());
+        }
+        match &self.backend {
+            // Written without reading. A key left by an older title loses to
+            // the newer one when listing, and is deleted here once known.
+            Backend::S3(client) => {
+                let key = |title: &str| {
+                    let title = title
+                        .chars()
+                        .take(MAX_S3_MEMBER_TITLE_CHARS)
+                        .collect::<String>();
+                    format!(
+                        "{}{}/{}/{}",
+                        S3_MEMBERS_PREFIX,
+                        user_id.0,
+                        chat_id.0,
+                        encode_key_part(&title)
+                    )
+                };
+                let bucket = env::var(S3_BUCKET_ENV_VAR).unwrap();
+                client
+                    .put_object()
+                    .bucket(&bucket)
+                    .key(key(title))
+                    .send()
+                    .await
+                    .with_context(|| "Error putting member to S3")?;
+                if let Some(known) = known {
+                    client
+                        .delete_object()
+                        .bucket(&bucket)
+                        .key(key(&known))
+                        .send()
+                        .await
+                        .with_context(|| "Error deleting member from S3")?;
+                }
+            }
+            Backend::Sqlite(store) => store.add_member(user_id, chat_id, title).await?,
+        }
+        self.members
+            .lock()
+            .unwrap()
+            .insert((user_id, chat_id), title.to_owned());
+        Ok(())
+    }
+
+    /// Chats the user has been seen in, with their titles
+    pub async fn member_chats(&self, user_id: UserId) -> anyhow::Result<Vec<(ChatId, String)>> {
+        match &self.backend {
+            Backend::S3(client) => {
+                let prefix = format!("{}{}/", S3_MEMBERS_PREFIX, user_id.0);
+                let mut objects = list_s3_objects(client, &prefix).await?;
+                // The latest title of a chat wins
+                objects.sort_by_key(|object| object.last_modified().copied());
+                // Chats stored in one object per user before, until seen again
+                let legacy_path = format!("{}{}.json", S3_MEMBERS_PREFIX, user_id.0);
+                let mut chats: BTreeMap<i64, String> = get_json_from_s3(client, &legacy_path)
+                    .await?
+                    .unwrap_or_default();
+                for object in &objects {
+                    let parsed = object.key().and_then(|key| {
+                        let (chat_id, title) = key.strip_prefix(&prefix)?.split_once('/')?;
+                        Some((chat_id.parse().ok()?, decode_key_part(title)?))
+                    });
+                    if let Some((chat_id, title)) = parsed {
+                        chats.insert(chat_id, title);
+                    }
+                }
+                let mut chats = chats
+                    .into_iter()
+                    .map(|(chat_id, title)| (ChatId(chat_id), title))
+                    .collect::<Vec<_>>();
+                chats.sort_by(|a, b| a.1.cmp(&b.1));
+                Ok(chats)
+            }
+            Backend::Sqlite(store) => store.member_chats(user_id).await,
+        }
+    }
+
+    /// Chats with real-time timers due at `now`
+    pub async fn due_chats(&self, now: u64) -> anyhow::Result<Vec<ChatId>> {
+        match &self.backend {
+            Backend::S3(client) => {
+                let keys = list_s3_keys(client, S3_SCHEDULE_PREFIX).await?;
+                // A chat has several entries only after a failed update
+                let chats: BTreeSet<i64> = keys
+                    .iter()
+                    .filter_map(|key| {
+                        let (chat_id, due_at) =
+                            key.strip_prefix(S3_SCHEDULE_PREFIX)?.split_once('/')?;
+                        let (chat_id, due_at): (i64, u64) =
+                            (chat_id.parse().ok()?, due_at.parse().ok()?);
+                        (due_at <= now).then_some(chat_id)
+                    })
+                    .collect();
+                Ok(chats.into_iter().map(ChatId).collect())
+            }
+            Backend::Sqlite(store) => store.due_chats(now).await,
+        }
+    }
+
+    /// Creates a context for the chat. With the cache enabled, waits until
+    /// other updates for the same chat are done.
+    pub async fn context(&self, chat_id: ChatId) -> BotContext {
+        let slot = self.cache.as_ref().map(|cache| cache.slot(chat_id));
+        let guard = match &slot {
+            Some(slot) => Some(slot.lock().await),
+            None => None,
         };
+        BotContext {
+            backend: self.backend.clone(),
+            chat_id,
+            update_id: None,
+            resync_schedule: false,
+            loaded: Mutex::default(),
+            slot,
+            _guard: guard,
+        }
+    }
+}
+
+/// Returned by [`BotContext::claim_update`] when the update has already been
+/// handled or is being handled, e.g. when Telegram redelivers a webhook.
+#[derive(Debug)]
+pub struct DuplicateUpdate(pub UpdateId);
+
+impl fmt::Display for DuplicateUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Update {} has already been processed", self.0 .0)
+    }
+}
+
+impl std::error::Error for DuplicateUpdate {}
+
+pub struct BotContext {
+    backend: Backend,
+    chat_id: ChatId,
+    update_id: Option<UpdateId>,
+    /// Whether to rewrite the S3 schedule entries of the chat even if the due
+    /// time is unchanged
+    resync_schedule: bool,
+    /// Tracker as last read or written through this context, so that SQLite
+    /// only writes what changed since
+    loaded: Mutex<Option<Tracker>>,
+    slot: Option<Arc<ChatSlot>>,
+    _guard: Option<OwnedMutexGuard<()>>,
+}
 
-        let s3_path = format!("{}/store.json", dir);
+impl BotContext {
+    /// Binds the context to an update, see [`BotContext::claim_update`]
+    pub fn with_update(self, update_id: UpdateId) -> Self {
         Self {
-            client: Client::new(&config),
-            s3_path,
+            update_id: Some(update_id),
+            ..self
         }
     }
 
-    pub async fn get(&self) -> anyhow::Result<Tracker> {
-        self.get_from_s3().await
+    /// Makes writes check the S3 schedule entries of the chat, dropping any
+    /// left behind by a failed update. Used when the chat was found due.
+    pub fn resync_schedule(self) -> Self {
+        Self {
+            resync_schedule: true,
+            ..self
+        }
     }
 
-    #[instrument(skip(self), fields(s3_path = %self.s3_path))]
-    async fn get_from_s3(&self) -> anyhow::Result<Tracker> {
-        let bucket = env::var(S3_BUCKET_ENV_VAR).unwrap();
-        info!("Fetching from S3 bucket {}", bucket);
-
-        let response = self
-            .client
-            .get_object()
-            .bucket(bucket)
-            .key(&self.s3_path)
-            .send()
-            .await;
-        match response {
-            Ok(response) => Ok(serde_json::from_slice(
-                &response.body.collect().await?.to_vec(),
-            )?),
-            Err(sdk_err) => {
-                warn!("Error fetching from S3: {:?}", sdk_err);
-                match sdk_err.into_service_error() {
-                    GetObjectError::NoSuchKey(_) => Ok(Tracker::new()),
-                    err => Err(err),
+    pub async fn get(&self) -> anyhow::Result<Tracker> {
+        let tracker = match self.slot.as_ref().and_then(|slot| slot.get()) {
+            Some(tracker) => tracker,
+            None => {
+                let tracker = match &self.backend {
+                    // Boxed, as the S3 futures are large and would make every
+                    // handler future large, overflowing the stack in debug
+                    // builds
+                    Backend::S3(client) => Box::pin(get_json_from_s3(client, &self.s3_path()))
+                        .await?
+                        .unwrap_or_default(),
+                    Backend::Sqlite(store) => store.get(self.chat_id).await?,
+                };
+                if let Some(slot) = &self.slot {
+                    slot.set(&tracker);
                 }
+                tracker
+            }
+        };
+        *self.loaded.lock().unwrap() = Some(tracker.clone());
+        Ok(tracker)
+    }
+
+    /// Records the update as handled before handling it, failing with
+    /// [`DuplicateUpdate`] if it was recorded before. Checking and recording
+    /// is one atomic write, so a redelivery arriving while the first delivery
+    /// is still handled is skipped too. Failed updates are not retried.
+    pub async fn claim_update(&self) -> anyhow::Result<()> {
+        let Some(update_id) = self.update_id else {
+            return Ok(());
+        };
+        let claimed = match &self.backend {
+            Backend::S3(client) => {
+                let key = format!("{}{}/{}", S3_UPDATES_PREFIX, self.s3_dir(), update_id.0);
+                Box::pin(create_s3_marker(client, &key)).await?
             }
+            Backend::Sqlite(store) => store.claim_update(self.chat_id, update_id).await?,
+        };
+        if !claimed {
+            return Err(DuplicateUpdate(update_id).into());
         }
-        .with_context(|| "Error fetching from S3")
+        Ok(())
     }
 
-    #[instrument(skip_all, fields(s3_path = %self.s3_path))]
+    #[instrument(skip_all, fields(chat_id = %self.chat_id))]
     pub async fn put(&self, tracker: &Tracker) -> anyhow::Result<()> {
+        let mut tracker = tracker.clone();
+        match &self.backend {
+            // Boxed like in `get`
+            Backend::S3(client) => {
+                Box::pin(async {
+                    let next_due = tracker.next_due();
+                    if next_due != tracker.indexed_due || self.resync_schedule {
+                        self.update_s3_schedule(client, next_due).await?;
+                        tracker.indexed_due = next_due;
+                    }
+                    put_json_to_s3(client, &self.s3_path(), &tracker).await
+                })
+                .await?
+            }
+            Backend::Sqlite(store) => {
+                let stored = self.loaded.lock().unwrap().take();
+                store.put(self.chat_id, stored, &tracker).await?
+            }
+        }
+        if let Some(slot) = &self.slot {
+            slot.set(&tracker);
+        }
+        *self.loaded.lock().unwrap() = Some(tracker);
+        Ok(())
+    }
+
+    /// Appends an entry to the chat log. Only the SQLite backend keeps logs.
+    pub async fn log(&self, user: &str, message: &str) -> anyhow::Result<()> {
+        match &self.backend {
+            Backend::S3(_) => Ok(()),
+            Backend::Sqlite(store) => store.log(self.chat_id, user, message).await,
+        }
+    }
+
+    /// Leaves the chat with the single schedule entry `due_at`, or none. The
+    /// new entry is added before the others are deleted, so that a failure
+    /// makes the chat due early rather than never.
+    async fn update_s3_schedule(&self, client: &Client, due_at: Option<u64>) -> anyhow::Result<()> {
         let bucket = env::var(S3_BUCKET_ENV_VAR).unwrap();
-        info!("Writing to S3 bucket {}", bucket);
-
-        self.client
-            .put_object()
-            .bucket(bucket)
-            .key(&self.s3_path)
-            .body(ByteStream::from(
-                serde_json::to_string_pretty(tracker)?.as_bytes().to_owned(),
-            ))
-            .send()
-            .await
-            .with_context(|| "Error putting to S3")?;
+        let prefix = format!("{}{}/", S3_SCHEDULE_PREFIX, self.chat_id.0);
+        let keys = list_s3_keys(client, &prefix).await?;
+        let new_key = due_at.map(|due_at| format!("{}{}", prefix, due_at));
+        if let Some(key) = new_key.as_ref().filter(|key| !keys.contains(key)) {
+            client
+                .put_object()
+                .bucket(&bucket)
+                .key(key)
+                .send()
+                .await
+                .with_context(|| "Error putting schedule entry to S3")?;
+        }
+        for key in keys.iter().filter(|key| Some(*key) != new_key.as_ref()) {
+            client
+                .delete_object()
+                .bucket(&bucket)
+                .key(key)
+                .send()
+                .await
+                .with_context(|| "Error deleting schedule entry from S3")?;
+        }
         Ok(())
     }
+
+    fn s3_path(&self) -> String {
+        format!("{}/store.json", self.s3_dir())
+    }
+
+    fn s3_dir(&self) -> String {
+        if self.chat_id.0 < 0 {
+            format!("_{}", -self.chat_id.0)
+        } else {
+            self.chat_id.to_string()
+        }
+    }
+}
+
+#[instrument(skip(client))]
+async fn get_json_from_s3<T: DeserializeOwned>(
+    client: &Client,
+    s3_path: &str,
+) -> anyhow::Result<Option<T>> {
+    let bucket = env::var(S3_BUCKET_ENV_VAR).unwrap();
+    info!("Fetching from S3 bucket {}", bucket);
+
+    let response = client
+        .get_object()
+        .bucket(bucket)
+        .key(s3_path)
+        .send()
+        .await;
+    match response {
+        Ok(response) => Ok(Some(serde_json::from_slice(
+            &response.body.collect().await?.to_vec(),
+        )?)),
+        Err(sdk_err) => {
+            warn!("Error fetching from S3: {:?}", sdk_err);
+            match sdk_err.into_service_error() {
+                GetObjectError::NoSuchKey(_) => Ok(None),
+                err => Err(err),
+            }
+        }
+    }
+    .with_context(|| "Error fetching from S3")
+}
+
+#[instrument(skip(client, value))]
+async fn put_json_to_s3<T: Serialize>(
+    client: &Client,
+    s3_path: &str,
+    value: &T,
+) -> anyhow::Result<()> {
+    let bucket = env::var(S3_BUCKET_ENV_VAR).unwrap();
+    info!("Writing to S3 bucket {}", bucket);
+
+    client
+        .put_object()
+        .bucket(bucket)
+        .key(s3_path)
+        .body(ByteStream::from(
+            serde_json::to_string_pretty(value)?.as_bytes().to_owned(),
+        ))
+        .send()
+        .await
+        .with_context(|| "Error putting to S3")?;
+    Ok(())
+}
+
+/// Creates an empty object at `s3_path` unless one exists. Returns whether
+/// it was created.
+#[instrument(skip(client))]
+async fn create_s3_marker(client: &Client, s3_path: &str) -> anyhow::Result<bool> {
+    let bucket = env::var(S3_BUCKET_ENV_VAR).unwrap();
+    let response = client
+        .put_object()
+        .bucket(bucket)
+        .key(s3_path)
+        .if_none_match("*")
+        .send()
+        .await;
+    match response {
+        Ok(_) => Ok(true),
+        // 412 if the object exists, 409 if another request is creating it
+        Err(SdkError::ServiceError(err)) if [409, 412].contains(&err.raw().status().as_u16()) => {
+            Ok(false)
+        }
+        Err(err) => Err(err).with_context(|| "Error putting marker to S3"),
+    }
+}
+
+/// Keys starting with `prefix`
+async fn list_s3_keys(client: &Client, prefix: &str) -> anyhow::Result<Vec<String>> {
+    Ok(list_s3_objects(client, prefix)
+        .await?
+        .iter()
+        .filter_map(|object| object.key().map(str::to_owned))
+        .collect())
+}
+
+/// Objects whose keys start with `prefix`
+async fn list_s3_objects(client: &Client, prefix: &str) -> anyhow::Result<Vec<Object>> {
+    let bucket = env::var(S3_BUCKET_ENV_VAR).unwrap();
+    let pages = client
+        .list_objects_v2()
+        .bucket(bucket)
+        .prefix(prefix)
+        .into_paginator()
+        .send()
+        .try_collect()
+        .await
+        .with_context(|| "Error listing S3 keys")?;
+    Ok(pages
+        .into_iter()
+        .flat_map(|page| page.contents.unwrap_or_default())
+        .collect())
+}
+
+/// Escapes text for a part of an S3 key, leaving only characters which are
+/// safe in keys
+fn encode_key_part(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+fn decode_key_part(part: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    let mut rest = part.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn redelivered_update_is_not_reapplied() {
+        let storage = Storage::sqlite(":memory:").unwrap();
+        let chat_id = ChatId(-42);
+
+        let context = storage.context(chat_id).await.with_update(UpdateId(7));
+        context.claim_update().await.unwrap();
+        let mut tracker = context.get().await.unwrap();
+        let player = tracker.create_player("Alice").unwrap();
+        tracker.change_harm(player.id, 1).unwrap();
+        context.put(&tracker).await.unwrap();
+
+        let context = storage.context(chat_id).await.with_update(UpdateId(7));
+        let err = context
+            .claim_update()
+            .await
+            .expect_err("duplicate must be rejected");
+        assert!(err.is::<DuplicateUpdate>());
+
+        let context = storage.context(chat_id).await.with_update(UpdateId(8));
+        context.claim_update().await.unwrap();
+        let tracker = context.get().await.unwrap();
+        assert_eq!(tracker.players[0].harm, 1);
+
+        // Ids are per chat
+        let context = storage.context(ChatId(42)).await.with_update(UpdateId(7));
+        context.claim_update().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn update_is_claimed_without_storing_anything() {
+        let storage = Storage::sqlite(":memory:").unwrap().with_cache();
+        let chat_id = ChatId(42);
+
+        let context = storage.context(chat_id).await.with_update(UpdateId(1));
+        context.claim_update().await.unwrap();
+        drop(context);
+
+        let context = storage.context(chat_id).await.with_update(UpdateId(1));
+        let err = context
+            .claim_update()
+            .await
+            .expect_err("duplicate must be rejected");
+        assert!(err.is::<DuplicateUpdate>());
+    }
+
+    #[test]
+    fn encodes_titles_for_keys() {
+        let title = "Blades / 100% ночь";
+        let encoded = encode_key_part(title);
+        assert_eq!(encoded, "Blades%20%2F%20100%25%20%D0%BD%D0%BE%D1%87%D1%8C");
+        assert_eq!(decode_key_part(&encoded).as_deref(), Some(title));
+        assert_eq!(decode_key_part("%2"), None);
+    }
 }
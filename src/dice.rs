@@ -0,0 +1,566 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, bail};
+use rand::RngCore;
+
+const MAX_DICE: u32 = 100;
+const MAX_SIDES: u32 = 1000;
+const MAX_MODIFIER: i32 = 1000;
+const MAX_ACTION_POOL: u32 = 10;
+/// Largest pool whose odds are worked out, by going through every roll
+const MAX_ODDS_POOL: u32 = 6;
+/// Rough number of steps allowed for the odds of keeping some of the dice
+const MAX_KEEP_ODDS_WORK: u64 = 100_000_000;
+
+/// Which dice count towards the total
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Keep {
+    Highest(u32),
+    Lowest(u32),
+}
+
+/// Dice notation such as `3d6`, `d20+2` or `4d6kh3`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DiceRoll {
+    pub count: u32,
+    pub sides: u32,
+    pub keep: Option<Keep>,
+    /// Added to the total
+    pub modifier: i32,
+}
+
+impl FromStr for DiceRoll {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow!("Invalid dice: {}", s);
+        let input = s.trim().to_lowercase();
+        let (count, rest) = input.split_once('d').ok_or_else(invalid)?;
+        let count = if count.is_empty() { 1 } else { count.parse()? };
+        let (sides, mut rest) = split_number(rest);
+        let sides = sides.parse().map_err(|_| invalid())?;
+        let mut keep = None;
+        for (prefix, make) in [
+            ("kh", Keep::Highest as fn(u32) -> Keep),
+            ("kl", Keep::Lowest),
+        ] {
+            if let Some(after) = rest.strip_prefix(prefix) {
+                let (kept, after) = split_number(after);
+                keep = Some(make(kept.parse().map_err(|_| invalid())?));
+                rest = after;
+            }
+        }
+        let modifier: i32 = match rest {
+            "" => 0,
+            _ if rest.starts_with(['+', '-']) => rest.parse().map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        };
+        if !(1..=MAX_DICE).contains(&count) {
+            bail!("Between 1 and {} dice can be rolled", MAX_DICE);
+        }
+        if !(2..=MAX_SIDES).contains(&sides) {
+            bail!("Dice must have between 2 and {} sides", MAX_SIDES);
+        }
+        if let Some(Keep::Highest(kept) | Keep::Lowest(kept)) = keep {
+            if !(1..=count).contains(&kept) {
+                bail!("Between 1 and {} dice can be kept", count);
+            }
+        }
+        if modifier.abs() > MAX_MODIFIER {
+            bail!("Modifiers can be at most {}", MAX_MODIFIER);
+        }
+        Ok(Self {
+            count,
+            sides,
+            keep,
+            modifier,
+        })
+    }
+}
+
+/// Splits leading digits off `s`
+fn split_number(s: &str) -> (&str, &str) {
+    s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()))
+}
+
+impl fmt::Display for DiceRoll {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}d{}", self.count, self.sides)?;
+        match self.keep {
+            Some(Keep::Highest(kept)) => write!(f, "kh{}", kept)?,
+            Some(Keep::Lowest(kept)) => write!(f, "kl{}", kept)?,
+            None => {}
+        }
+        match self.modifier {
+            0 => Ok(()),
+            modifier => write!(f, "{:+}", modifier),
+        }
+    }
+}
+
+impl DiceRoll {
+    /// Rolls the dice in order, each as described in [`crate::fair`]
+    pub fn roll(&self, rng: &mut impl RngCore) -> Vec<u32> {
+        (0..self.count).map(|_| roll_die(rng, self.sides)).collect()
+    }
+
+    /// Sum of the kept dice plus the modifier
+    pub fn total(&self, values: &[u32]) -> i32 {
+        let mut values = values.to_vec();
+        values.sort_unstable();
+        let kept = match self.keep {
+            Some(Keep::Highest(kept)) => &values[values.len().saturating_sub(kept as usize)..],
+            Some(Keep::Lowest(kept)) => &values[..values.len().min(kept as usize)],
+            None => &values[..],
+        };
+        kept.iter().sum::<u32>() as i32 + self.modifier
+    }
+
+    /// Exact chance of every total
+    pub fn distribution(&self) -> anyhow::Result<Distribution> {
+        let (kept, chances) = match self.keep {
+            None => (self.count, sum_chances(self.count, self.sides)),
+            Some(keep) => {
+                let kept = match keep {
+                    Keep::Highest(kept) | Keep::Lowest(kept) => kept,
+                };
+                let work =
+                    u64::from(self.sides).pow(2) * u64::from(self.count).pow(2) * u64::from(kept);
+                if work > MAX_KEEP_ODDS_WORK {
+                    bail!("Too many dice to work out the odds of {}", self);
+                }
+                (kept, keep_chances(self.count, self.sides, keep))
+            }
+        };
+        Ok(Distribution {
+            min: kept as i32 + self.modifier,
+            chances,
+        })
+    }
+}
+
+/// Chances of the sums of `count` dice, from the lowest sum
+fn sum_chances(count: u32, sides: u32) -> Vec<f64> {
+    let face = 1.0 / f64::from(sides);
+    let sides = sides as usize;
+    // Sums less the number of dice, so that they start from zero
+    let mut chances = vec![1.0];
+    for _ in 0..count {
+        let mut next = vec![0.0; chances.len() + sides - 1];
+        // Sliding sum over the last `sides` chances
+        let mut window = 0.0;
+        for (sum, chance) in next.iter_mut().enumerate() {
+            window += chances.get(sum).copied().unwrap_or(0.0);
+            if sum >= sides {
+                window -= chances[sum - sides];
+            }
+            *chance = window * face;
+        }
+        chances = next;
+    }
+    chances
+}
+
+/// Chances of the sums of the kept dice, from the lowest sum. Goes through
+/// the faces starting with those kept first, choosing how many dice show
+/// each face.
+fn keep_chances(count: u32, sides: u32, keep: Keep) -> Vec<f64> {
+    let (faces, kept): (Vec<u32>, u32) = match keep {
+        Keep::Highest(kept) => ((1..=sides).rev().collect(), kept),
+        Keep::Lowest(kept) => ((1..=sides).collect(), kept),
+    };
+    let (count, kept) = (count as usize, kept as usize);
+    let face = 1.0 / f64::from(sides);
+    let binomial = binomials(count);
+    let max_sum = kept * sides as usize;
+    // By number of dice given a face so far, then by sum of the kept ones
+    let mut chances = vec![vec![0.0; max_sum + 1]; count + 1];
+    chances[0][0] = 1.0;
+    for value in faces {
+        let mut next = vec![vec![0.0; max_sum + 1]; count + 1];
+        for (done, sums) in chances.iter().enumerate() {
+            for (sum, chance) in sums.iter().enumerate().filter(|(_, chance)| **chance > 0.0) {
+                let mut weight = *chance;
+                for showing in 0..=count - done {
+                    let newly_kept = showing.min(kept.saturating_sub(done));
+                    next[done + showing][sum + newly_kept * value as usize] +=
+                        weight * binomial[count - done][showing];
+                    weight *= face;
+                }
+            }
+        }
+        chances = next;
+    }
+    chances.swap_remove(count).split_off(kept)
+}
+
+/// Pascal's triangle up to row `n`
+fn binomials(n: usize) -> Vec<Vec<f64>> {
+    let mut rows: Vec<Vec<f64>> = vec![vec![1.0]];
+    for row in 1..=n {
+        let previous = &rows[row - 1];
+        let next = (0..=row)
+            .map(|k| {
+                let left = if k > 0 { previous[k - 1] } else { 0.0 };
+                left + previous.get(k).copied().unwrap_or(0.0)
+            })
+            .collect();
+        rows.push(next);
+    }
+    rows
+}
+
+/// Chances of every total of a roll
+#[derive(Clone, Debug, PartialEq)]
+pub struct Distribution {
+    /// Lowest total
+    pub min: i32,
+    /// Chance of each total, starting from `min`
+    pub chances: Vec<f64>,
+}
+
+impl Distribution {
+    pub fn totals(&self) -> impl Iterator<Item = (i32, f64)> + '_ {
+        (self.min..).zip(self.chances.iter().copied())
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.totals()
+            .map(|(total, chance)| f64::from(total) * chance)
+            .sum()
+    }
+
+    /// Chance of a total for which `check` holds
+    pub fn chance(&self, check: impl Fn(i32) -> bool) -> f64 {
+        self.totals()
+            .filter(|(total, _)| check(*total))
+            .map(|(_, chance)| chance)
+            .sum()
+    }
+}
+
+/// Result of a Forged in the Dark action roll
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Critical,
+    Success,
+    Partial,
+    Failure,
+}
+
+impl Outcome {
+    pub const ALL: [Outcome; 4] = [
+        Outcome::Critical,
+        Outcome::Success,
+        Outcome::Partial,
+        Outcome::Failure,
+    ];
+
+    /// Message catalog key
+    pub fn key(&self) -> &'static str {
+        match self {
+            Outcome::Critical => "outcome-critical",
+            Outcome::Success => "outcome-success",
+            Outcome::Partial => "outcome-partial",
+            Outcome::Failure => "outcome-failure",
+        }
+    }
+
+    /// Catalog key of the short name used in odds tables
+    pub fn short_key(&self) -> &'static str {
+        match self {
+            Outcome::Critical => "odds-critical",
+            Outcome::Success => "odds-success",
+            Outcome::Partial => "odds-partial",
+            Outcome::Failure => "odds-failure",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ActionRoll {
+    pub pool: u32,
+    pub dice: Vec<u32>,
+}
+
+impl ActionRoll {
+    pub fn new(pool: u32) -> anyhow::Result<Self> {
+        if pool > MAX_ACTION_POOL {
+            bail!("At most {} dice can be rolled", MAX_ACTION_POOL);
+        }
+        Ok(Self {
+            pool,
+            dice: Vec::new(),
+        })
+    }
+
+    /// Rolls the pool. With zero dice, two are rolled and the lowest counts.
+    pub fn roll(self, rng: &mut impl RngCore) -> Self {
+        let count = if self.pool == 0 { 2 } else { self.pool };
+        let dice = DiceRoll {
+            count,
+            sides: 6,
+            keep: None,
+            modifier: 0,
+        }
+        .roll(rng);
+        Self { dice, ..self }
+    }
+
+    pub fn result(&self) -> u32 {
+        let result = if self.pool == 0 {
+            self.dice.iter().min()
+        } else {
+            self.dice.iter().max()
+        };
+        result.copied().unwrap_or(0)
+    }
+
+    /// Exact chance of each outcome in [`Outcome::ALL`] order, found by
+    /// going through every possible roll
+    pub fn odds(pool: u32) -> anyhow::Result<[f64; 4]> {
+        if pool > MAX_ODDS_POOL {
+            bail!("Odds can be shown for at most {} dice", MAX_ODDS_POOL);
+        }
+        let count = if pool == 0 { 2 } else { pool };
+        let rolls = 6u32.pow(count);
+        let mut odds = [0.0; 4];
+        for index in 0..rolls {
+            // Digits of the index in base 6 are the dice
+            let dice = (0..count)
+                .map(|digit| index / 6u32.pow(digit) % 6 + 1)
+                .collect();
+            let outcome = ActionRoll { pool, dice }.outcome();
+            odds[outcome as usize] += 1.0 / f64::from(rolls);
+        }
+        Ok(odds)
+    }
+
+    pub fn outcome(&self) -> Outcome {
+        let sixes = self.dice.iter().filter(|die| **die == 6).count();
+        match self.result() {
+            6 if self.pool > 0 && sixes > 1 => Outcome::Critical,
+            6 => Outcome::Success,
+            4 | 5 => Outcome::Partial,
+            _ => Outcome::Failure,
+        }
+    }
+}
+
+/// How dangerous an action is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Position {
+    Controlled,
+    Risky,
+    Desperate,
+}
+
+/// How much an action achieves
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Effect {
+    Limited,
+    Standard,
+    Great,
+}
+
+/// Position and effect of an action roll, given as words such as
+/// `risky great` or their first letters
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stakes {
+    pub position: Position,
+    pub effect: Effect,
+}
+
+impl Stakes {
+    /// None for no words. Either word may be left out, and defaults to
+    /// risky or standard.
+    pub fn parse(s: &str) -> anyhow::Result<Option<Self>> {
+        let words = s.split_whitespace().collect::<Vec<_>>();
+        if words.is_empty() {
+            return Ok(None);
+        }
+        let mut stakes = Stakes {
+            position: Position::Risky,
+            effect: Effect::Standard,
+        };
+        for word in words {
+            match word.to_lowercase().as_str() {
+                "c" | "controlled" => stakes.position = Position::Controlled,
+                "r" | "risky" => stakes.position = Position::Risky,
+                "d" | "desperate" => stakes.position = Position::Desperate,
+                "l" | "limited" => stakes.effect = Effect::Limited,
+                "s" | "standard" => stakes.effect = Effect::Standard,
+                "g" | "great" => stakes.effect = Effect::Great,
+                _ => bail!("Unknown position or effect: {}", word),
+            }
+        }
+        Ok(Some(stakes))
+    }
+
+    /// Effect of the action, one level higher on a critical
+    pub fn effect(&self, outcome: Outcome) -> Effect {
+        match (outcome, self.effect) {
+            (Outcome::Critical, Effect::Limited) => Effect::Standard,
+            (Outcome::Critical, _) => Effect::Great,
+            (_, effect) => effect,
+        }
+    }
+
+    /// Catalog key of what happens on `outcome` from this position
+    pub fn consequence_key(&self, outcome: Outcome) -> &'static str {
+        match (self.position, outcome) {
+            (_, Outcome::Critical | Outcome::Success) => "consequence-success",
+            (Position::Controlled, Outcome::Partial) => "consequence-controlled-partial",
+            (Position::Controlled, Outcome::Failure) => "consequence-controlled-failure",
+            (Position::Risky, Outcome::Partial) => "consequence-risky-partial",
+            (Position::Risky, Outcome::Failure) => "consequence-risky-failure",
+            (Position::Desperate, Outcome::Partial) => "consequence-desperate-partial",
+            (Position::Desperate, Outcome::Failure) => "consequence-desperate-failure",
+        }
+    }
+}
+
+impl Position {
+    /// Catalog key of the name
+    pub fn key(&self) -> &'static str {
+        match self {
+            Position::Controlled => "position-controlled",
+            Position::Risky => "position-risky",
+            Position::Desperate => "position-desperate",
+        }
+    }
+}
+
+impl Effect {
+    /// Catalog key of the name
+    pub fn key(&self) -> &'static str {
+        match self {
+            Effect::Limited => "effect-limited",
+            Effect::Standard => "effect-standard",
+            Effect::Great => "effect-great",
+        }
+    }
+}
+
+/// Takes 32-bit words until one is below the largest multiple of `sides`
+/// which fits, so that every face is equally likely, and maps it to a face.
+/// Spelled out rather than left to `rand`, as fair rolls have to be
+/// repeatable by anyone, see [`crate::fair`].
+fn roll_die(rng: &mut impl RngCore, sides: u32) -> u32 {
+    let sides = u64::from(sides);
+    let limit = (1 << 32) / sides * sides;
+    loop {
+        let word = u64::from(rng.next_u32());
+        if word < limit {
+            return (word % sides) as u32 + 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_dice_notation() {
+        assert_eq!(
+            "3d6".parse::<DiceRoll>().unwrap(),
+            DiceRoll {
+                count: 3,
+                sides: 6,
+                keep: None,
+                modifier: 0
+            }
+        );
+        assert_eq!(
+            "D20".parse::<DiceRoll>().unwrap(),
+            DiceRoll {
+                count: 1,
+                sides: 20,
+                keep: None,
+                modifier: 0
+            }
+        );
+        assert_eq!(
+            "4d6kh3-1".parse::<DiceRoll>().unwrap(),
+            DiceRoll {
+                count: 4,
+                sides: 6,
+                keep: Some(Keep::Highest(3)),
+                modifier: -1
+            }
+        );
+        assert_eq!(
+            "2d20kl1+5".parse::<DiceRoll>().unwrap().to_string(),
+            "2d20kl1+5"
+        );
+        assert!("0d6".parse::<DiceRoll>().is_err());
+        assert!("3d1".parse::<DiceRoll>().is_err());
+        assert!("3d6kh4".parse::<DiceRoll>().is_err());
+        assert!("3d6x".parse::<DiceRoll>().is_err());
+        assert!("clocks".parse::<DiceRoll>().is_err());
+    }
+
+    #[test]
+    fn works_out_exact_odds() {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        let two_d6 = "2d6".parse::<DiceRoll>().unwrap().distribution().unwrap();
+        assert_eq!((two_d6.min, two_d6.chances.len()), (2, 11));
+        assert!(close(two_d6.chance(|total| total == 7), 6.0 / 36.0));
+        assert!(close(two_d6.mean(), 7.0));
+
+        let stats = "4d6kh3".parse::<DiceRoll>().unwrap();
+        let odds = stats.distribution().unwrap();
+        assert_eq!(odds.min, 3);
+        assert!(close(odds.chances.iter().sum(), 1.0));
+        assert!(close(odds.chance(|total| total == 18), 21.0 / 1296.0));
+        assert!(close(odds.mean(), 15869.0 / 1296.0));
+        let disadvantage = "2d20kl1+1".parse::<DiceRoll>().unwrap();
+        let odds = disadvantage.distribution().unwrap();
+        assert!(close(odds.chance(|total| total == 21), 1.0 / 400.0));
+        assert!("100d1000kh50"
+            .parse::<DiceRoll>()
+            .unwrap()
+            .distribution()
+            .is_err());
+
+        let action = |pool| ActionRoll::odds(pool).unwrap();
+        let one_die = [0.0, 1.0 / 6.0, 2.0 / 6.0, 3.0 / 6.0];
+        assert!(action(1).iter().zip(one_die).all(|(a, b)| close(*a, b)));
+        assert!(close(action(2)[0], 1.0 / 36.0));
+        assert!(close(action(0)[1], 1.0 / 36.0));
+        assert!(ActionRoll::odds(7).is_err());
+    }
+
+    #[test]
+    fn parses_stakes() {
+        assert_eq!(Stakes::parse(" ").unwrap(), None);
+        let stakes = Stakes::parse("great D").unwrap().unwrap();
+        assert_eq!(
+            (stakes.position, stakes.effect),
+            (Position::Desperate, Effect::Great)
+        );
+        let limited = Stakes::parse("l").unwrap().unwrap();
+        assert_eq!(limited.position, Position::Risky);
+        assert_eq!(limited.effect(Outcome::Critical), Effect::Standard);
+        assert!(Stakes::parse("risky bold").is_err());
+    }
+
+    #[test]
+    fn interprets_action_rolls() {
+        let outcome = |pool, dice: &[u32]| {
+            ActionRoll {
+                pool,
+                dice: dice.to_vec(),
+            }
+            .outcome()
+        };
+        assert_eq!(outcome(2, &[6, 6]), Outcome::Critical);
+        assert_eq!(outcome(2, &[3, 6]), Outcome::Success);
+        assert_eq!(outcome(3, &[1, 5, 2]), Outcome::Partial);
+        assert_eq!(outcome(1, &[3]), Outcome::Failure);
+        // Zero dice take the lowest of two and cannot crit
+        assert_eq!(outcome(0, &[6, 6]), Outcome::Success);
+        assert_eq!(outcome(0, &[6, 2]), Outcome::Failure);
+    }
+}
@@ -1,48 +1,140 @@
 use anyhow::anyhow;
 use teloxide::{
     prelude::*,
-    requests::Requester,
-    types::{Message, UpdateKind},
-    utils::command::BotCommands,
+    types::{BotCommand, Message, UpdateKind},
+    utils::command::{BotCommands, ParseError},
+    utils::markdown::escape,
 };
 use tracing::{info, instrument, warn};
 
-use crate::callback::CallbackAction;
+use crate::callback::{CallbackAction, KeypadTarget, StaleKeyboard};
+use crate::context::{DuplicateUpdate, Storage};
 use crate::handler::BotHandler;
-use crate::utils::Bot;
+use crate::i18n::Locale;
+use crate::inline::handle_inline;
+use crate::messenger::Messenger;
+use crate::settings::SettingChange;
+use crate::tracker::Amount::{By, To};
+use crate::tracker::Destructive::{DeletePlayer, DeleteTimer, ResetStress, Wipe};
+use crate::tracker::ItemKind::{self, Player, Timer};
 use crate::{callback::Callback, utils::debug_err};
 
+/// Descriptions are in the message catalog, see [`localized_commands`]
 #[derive(BotCommands, PartialEq, Clone, Debug)]
-#[command(
-    rename_rule = "lowercase",
-    description = "These commands are supported:",
-    parse_with = "split"
-)]
+#[command(rename_rule = "lowercase", parse_with = "split")]
 pub enum Command {
-    #[command(description = "display this text")]
     Help,
-    #[command(description = "clears everything")]
+    /// `/wipe yes` confirmed before there were buttons, the argument is
+    /// ignored
+    #[command(parse_with = "default")]
     Wipe(String),
-    #[command(description = "rolls 1 die")]
-    R1,
-    #[command(description = "rolls 2 dice")]
-    R2,
-    #[command(description = "rolls 3 dice")]
-    R3,
-    #[command(description = "manage timers")]
-    T,
-    #[command(description = "manage players")]
-    P,
-    #[command(description = "<name> - add player")]
+    #[command(parse_with = "default")]
+    R1(String),
+    #[command(parse_with = "default")]
+    R2(String),
+    #[command(parse_with = "default")]
+    R3(String),
+    #[command(parse_with = "default")]
+    T(String),
+    #[command(parse_with = "default")]
+    P(String),
     Pa(String),
-    #[command(description = "<name> <start_value> - add timer")]
-    Ta(String, u16),
+    #[command(parse_with = parse_timer_args)]
+    Ta(String, Option<u16>),
+    #[command(parse_with = parse_rename_args)]
+    Pr(String, String),
+    #[command(parse_with = parse_rename_args)]
+    Tr(String, String),
+    #[command(parse_with = parse_timer_edit_args)]
+    Te(String, i32, Option<i32>),
+    #[command(parse_with = parse_schedule_args)]
+    Td(String, String),
+    Quiet,
+    Settings,
+    #[command(parse_with = "default")]
+    Dash(String),
+    #[command(parse_with = "default")]
+    Schedule(String),
+    #[command(parse_with = "default")]
+    Odds(String),
+    Roll(String),
+    #[command(parse_with = "default")]
+    Stats(String),
+    #[command(parse_with = "default")]
+    Session(String),
+    #[command(parse_with = "default")]
+    Verify(String),
 }
 
-#[instrument(skip(bot))]
-pub async fn dispatch_update(bot: Bot, update: Update) -> anyhow::Result<()> {
+/// Bot commands with descriptions in the language of `locale`
+pub fn localized_commands(locale: &Locale) -> Vec<BotCommand> {
+    Command::bot_commands()
+        .into_iter()
+        .map(|mut command| {
+            let key = format!("cmd-{}", command.command.trim_start_matches('/'));
+            command.description = locale.plain(&key, &[]);
+            command
+        })
+        .collect()
+}
+
+/// Splits `<name> = <value>`, `usage` describes the expected input
+fn split_assignment(input: &str, usage: &str) -> Result<(String, String), ParseError> {
+    let (name, value) = input
+        .split_once('=')
+        .ok_or_else(|| ParseError::IncorrectFormat(anyhow!("Expected {}", usage).into()))?;
+    Ok((name.trim().to_owned(), value.trim().to_owned()))
+}
+
+fn parse_rename_args(input: String) -> Result<(String, String), ParseError> {
+    split_assignment(&input, "<name> = <new name>")
+}
+
+fn parse_schedule_args(input: String) -> Result<(String, String), ParseError> {
+    split_assignment(&input, "<name> = <delay or time>")
+}
+
+/// Splits `<name> = <value>[/<max>]`
+fn parse_timer_edit_args(input: String) -> Result<(String, i32, Option<i32>), ParseError> {
+    let incorrect =
+        || ParseError::IncorrectFormat(anyhow!("Expected <name> = <value>[/<max>]").into());
+    let (name, values) = input.split_once('=').ok_or_else(incorrect)?;
+    let (value, max) = match values.split_once('/') {
+        Some((value, max)) => (value, Some(max.trim().parse().map_err(|_| incorrect())?)),
+        None => (values, None),
+    };
+    let value = value.trim().parse().map_err(|_| incorrect())?;
+    Ok((name.trim().to_owned(), value, max))
+}
+
+/// Splits `<name> [start_value]`, the name may contain spaces
+fn parse_timer_args(input: String) -> Result<(String, Option<u16>), ParseError> {
+    let input = input.trim();
+    if let Some((name, value)) = input.rsplit_once(char::is_whitespace) {
+        if let Ok(value) = value.parse() {
+            return Ok((name.trim().to_owned(), Some(value)));
+        }
+    }
+    Ok((input.to_owned(), None))
+}
+
+#[instrument(skip(bot, storage))]
+pub async fn dispatch_update<M: Messenger>(
+    bot: M,
+    update: Update,
+    storage: Storage,
+) -> anyhow::Result<()> {
     info!("Handle update called with {:?}", update);
-    let handler = match BotHandler::new(bot, &update).await {
+    // Inline queries come from no particular chat
+    if let UpdateKind::InlineQuery(inline) = &update.kind {
+        if let Err(err) = handle_inline(&bot, &storage, inline).await {
+            let err = err.context(format!("Error handling inline query {:?}", inline.query));
+            warn!("{}", &err);
+            debug_err(&err).await;
+        }
+        return Ok(());
+    }
+    let handler = match BotHandler::new(bot, &storage, &update).await {
         Ok(handler) => handler,
         Err(err) => {
             let err = err.context(format!(
@@ -59,16 +151,23 @@ pub async fn dispatch_update(bot: Bot, update: Update) -> anyhow::Result<()> {
             return Ok(());
         }
     };
-    let ret = match &update.kind {
-        // UpdateKind::InlineQuery(inline) => handle_inline(bot, inline, context).await,
-        UpdateKind::Message(msg) => dispatch_command(&handler, &msg).await,
-        UpdateKind::CallbackQuery(cb) => dispatch_callback(&handler, &cb).await,
-        _ => {
-            warn!("Unsupported update kind: {:?}", update.kind);
-            Ok(())
-        }
+    let ret = match handler.context.claim_update().await {
+        Ok(()) => match &update.kind {
+            UpdateKind::Message(msg) => dispatch_command(&handler, &msg).await,
+            UpdateKind::CallbackQuery(cb) => dispatch_callback(&handler, &cb).await,
+            _ => {
+                warn!("Unsupported update kind: {:?}", update.kind);
+                Ok(())
+            }
+        },
+        Err(err) => Err(err),
     };
+    handler.run_deferred().await;
     if let Err(err) = ret {
+        if err.is::<DuplicateUpdate>() {
+            info!("Skipping redelivered update {}", update.id.0);
+            return Ok(());
+        }
         let err = err.context(format!(
             "Error handling update. Update ID {}, user {}",
             update.id.0,
@@ -84,52 +183,1006 @@ pub async fn dispatch_update(bot: Bot, update: Update) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Longest text Telegram shows in a callback notification
+const MAX_CALLBACK_ANSWER_LEN: usize = 200;
+
 #[instrument(skip(handler), fields(from = %handler.format_user()))]
-pub async fn dispatch_callback(handler: &BotHandler, cb: &CallbackQuery) -> anyhow::Result<()> {
+pub async fn dispatch_callback<M: Messenger>(
+    handler: &BotHandler<M>,
+    cb: &CallbackQuery,
+) -> anyhow::Result<()> {
+    let ret = handle_callback(handler, cb).await;
+    // Every query has to be answered, or the button keeps spinning
+    let (text, show_alert) = match &ret {
+        Ok(toast) => (toast.clone(), false),
+        // Nothing to report: the keyboard just has to be listed again
+        Err(err) if err.is::<StaleKeyboard>() => {
+            info!("Stale keyboard: {}", err);
+            let text = handler.chat_locale().await.plain("keyboard-outdated", &[]);
+            (Some(text), true)
+        }
+        Err(err) => (Some(err.to_string()), true),
+    };
+    let text = text.map(|text| text.chars().take(MAX_CALLBACK_ANSWER_LEN).collect());
+    if let Err(err) = handler
+        .bot
+        .answer_callback_query(cb.id.clone(), text, show_alert)
+        .await
+    {
+        warn!("Error answering callback query: {}", err);
+    }
+    match ret {
+        Err(err) if err.is::<StaleKeyboard>() => Ok(()),
+        ret => ret.map(drop),
+    }
+}
+
+async fn handle_callback<M: Messenger>(
+    handler: &BotHandler<M>,
+    cb: &CallbackQuery,
+) -> anyhow::Result<Option<String>> {
     let data = cb.data.as_deref().ok_or(anyhow!("Missing callback data"))?;
     info!("Handling callback '{}'", data);
 
-    let callback = Callback::deserialize(data)?;
+    let callback = Callback::deserialize(handler.chat_id, data)?;
+    let change_setting = |change| async move {
+        let msg = cb
+            .message
+            .as_ref()
+            .ok_or(anyhow!("Settings message not found"))?;
+        handler.handle_change_setting(msg.id(), change).await
+    };
 
+    let (id, revision, value) = (callback.item_id, callback.revision, callback.value);
+    let keypad = |target| async move {
+        handler
+            .handle_keypad(target, id, revision, value)
+            .await
+            .map(|_| None)
+    };
+    let confirm = |action| async move {
+        handler
+            .handle_request_confirm(action, revision)
+            .await
+            .map(|_| None)
+    };
+    let rename = |kind| async move {
+        handler
+            .handle_start_rename(kind, id, revision)
+            .await
+            .map(|_| None)
+    };
     match callback.action {
-        CallbackAction::DeleteTimer => handler.handle_delete_timer(callback.item_id).await,
-        CallbackAction::AddHarm => handler.handle_change_harm(callback.item_id, 1).await,
-        CallbackAction::SubHarm => handler.handle_change_harm(callback.item_id, -1).await,
-        CallbackAction::AddStress => handler.handle_change_stress(callback.item_id, 1).await,
-        CallbackAction::SubStress => handler.handle_change_stress(callback.item_id, -1).await,
-        CallbackAction::NoAction => Ok(()),
-        CallbackAction::AddTimer => handler.handle_change_timer(callback.item_id, 1).await,
-        CallbackAction::SubTimer => handler.handle_change_timer(callback.item_id, -1).await,
-        CallbackAction::DeletePlayer => handler.handle_delete_player(callback.item_id).await,
-        CallbackAction::ShowTimersKb => handler.handle_show_timers_kb().await,
-        CallbackAction::ShowPlayersKb => handler.handle_show_players_kb().await,
-        CallbackAction::ShowHarmKb => handler.handle_show_harm_kb().await,
-        CallbackAction::ShowStressKb => handler.handle_show_stress_kb().await,
-        CallbackAction::HideTimersKb => handler.handle_hide_timers_kb().await,
-        CallbackAction::HidePlayersKb => handler.handle_hide_players_kb().await,
+        CallbackAction::DeleteTimer => confirm(DeleteTimer(id)).await,
+        CallbackAction::AddHarm => handler.handle_change_harm(id, revision, By(value)).await,
+        CallbackAction::SubHarm => handler.handle_change_harm(id, revision, By(-value)).await,
+        CallbackAction::SetHarm => handler.handle_change_harm(id, revision, To(value)).await,
+        CallbackAction::AddStress => handler.handle_change_stress(id, revision, By(value)).await,
+        CallbackAction::SubStress => handler.handle_change_stress(id, revision, By(-value)).await,
+        CallbackAction::SetStress => handler.handle_change_stress(id, revision, To(value)).await,
+        CallbackAction::NoAction => Ok(None),
+        CallbackAction::AddTimer => handler.handle_change_timer(id, revision, By(value)).await,
+        CallbackAction::SubTimer => handler.handle_change_timer(id, revision, By(-value)).await,
+        CallbackAction::SetTimer => handler.handle_change_timer(id, revision, To(value)).await,
+        CallbackAction::HarmKeypad => keypad(KeypadTarget::Harm).await,
+        CallbackAction::StressKeypad => keypad(KeypadTarget::Stress).await,
+        CallbackAction::TimerKeypad => keypad(KeypadTarget::Timer).await,
+        CallbackAction::PinPlayer => handler.handle_toggle_pin(Player, id, revision).await,
+        CallbackAction::PinTimer => handler.handle_toggle_pin(Timer, id, revision).await,
+        CallbackAction::RenamePlayer => rename(Player).await,
+        CallbackAction::RenameTimer => rename(Timer).await,
+        CallbackAction::DeletePlayer => confirm(DeletePlayer(id)).await,
+        CallbackAction::ResetStress => confirm(ResetStress).await,
+        CallbackAction::Confirm => handler.handle_confirm(revision, true).await,
+        CallbackAction::CancelConfirm => handler.handle_confirm(revision, false).await,
+        CallbackAction::ShowTimersKb => handler.handle_show_timers_kb().await.map(|_| None),
+        CallbackAction::ShowPlayersKb => handler.handle_show_players_kb().await.map(|_| None),
+        CallbackAction::ShowHarmKb => handler.handle_show_harm_kb().await.map(|_| None),
+        CallbackAction::ShowStressKb => handler.handle_show_stress_kb().await.map(|_| None),
+        CallbackAction::HideTimersKb => handler.handle_hide_timers_kb().await.map(|_| None),
+        CallbackAction::HidePlayersKb => handler.handle_hide_players_kb().await.map(|_| None),
+        CallbackAction::ToggleAnnounceChanges => {
+            change_setting(SettingChange::ToggleAnnounceChanges).await
+        }
+        CallbackAction::AddStressCap => change_setting(SettingChange::StressCap(1)).await,
+        CallbackAction::SubStressCap => change_setting(SettingChange::StressCap(-1)).await,
+        CallbackAction::AddClockSize => change_setting(SettingChange::ClockSize(1)).await,
+        CallbackAction::SubClockSize => change_setting(SettingChange::ClockSize(-1)).await,
+        CallbackAction::ToggleDeleteFiredTimers => {
+            change_setting(SettingChange::ToggleDeleteFiredTimers).await
+        }
+        CallbackAction::NextLanguage => change_setting(SettingChange::NextLanguage).await,
+        CallbackAction::AddPageSize => change_setting(SettingChange::PageSize(1)).await,
+        CallbackAction::SubPageSize => change_setting(SettingChange::PageSize(-1)).await,
+        CallbackAction::NextSteps => change_setting(SettingChange::NextSteps).await,
+        CallbackAction::ChangeUtcOffset => change_setting(SettingChange::UtcOffset(value)).await,
+        CallbackAction::Vote => handler.handle_vote(revision, id).await,
+        CallbackAction::ClosePoll => handler.handle_close_poll(revision).await.map(|_| None),
+        CallbackAction::TimersPage => handler.handle_timers_page(id).await.map(|_| None),
+        CallbackAction::PlayersPage => handler.handle_players_page(id).await.map(|_| None),
     }
 }
 
 #[instrument(skip(handler), fields(from = %handler.format_user()))]
-pub async fn dispatch_command(handler: &BotHandler, msg: &Message) -> anyhow::Result<()> {
+pub async fn dispatch_command<M: Messenger>(
+    handler: &BotHandler<M>,
+    msg: &Message,
+) -> anyhow::Result<()> {
     let text = msg.text().ok_or(anyhow!("Error parsing command"))?;
     info!("Received command '{}'", text);
+    if let Some(prompt) = msg.reply_to_message() {
+        if !text.starts_with('/') {
+            return handler.handle_reply(prompt.id, text).await;
+        }
+    }
 
-    match Command::parse(text, handler.bot.get_me().await?.username())? {
+    match Command::parse(text, &handler.bot.username().await?)? {
         Command::Help => {
+            let locale = handler.locale(&handler.context.get().await?);
+            let mut help = locale.plain("help-header", &[]);
+            help.push('\n');
+            for command in localized_commands(&locale) {
+                help.push_str(&format!("\n{} — {}", command.command, command.description));
+            }
             handler
                 .bot
-                .send_message(msg.chat.id, Command::descriptions().to_string())
+                .send_message(msg.chat.id, escape(&help), None)
                 .await?;
             Ok(())
         }
-        Command::Wipe(confirm) => handler.handle_wipe(&confirm).await,
-        Command::R1 => handler.handle_roll(1).await,
-        Command::R2 => handler.handle_roll(2).await,
-        Command::R3 => handler.handle_roll(3).await,
-        Command::T => handler.handle_list_timers().await,
-        Command::P => handler.handle_list_players().await,
+        Command::Wipe(_) => handler.handle_request_confirm(Wipe, 0).await,
+        Command::R1(stakes) => handler.handle_roll(1, &stakes).await,
+        Command::R2(stakes) => handler.handle_roll(2, &stakes).await,
+        Command::R3(stakes) => handler.handle_roll(3, &stakes).await,
+        Command::T(filter) => handler.handle_list_timers(&filter).await,
+        Command::P(filter) => handler.handle_list_players(&filter).await,
         Command::Ta(name, start_val) => handler.handle_create_timer(&name, start_val).await,
         Command::Pa(name) => handler.handle_create_player(&name).await,
+        Command::Pr(name, new_name) => {
+            handler
+                .handle_rename(ItemKind::Player, &name, &new_name)
+                .await
+        }
+        Command::Tr(name, new_name) => {
+            handler
+                .handle_rename(ItemKind::Timer, &name, &new_name)
+                .await
+        }
+        Command::Te(name, value, max) => handler.handle_edit_timer(&name, value, max).await,
+        Command::Td(name, when) => handler.handle_schedule_timer(&name, &when).await,
+        Command::Quiet => handler.handle_toggle_announcements().await,
+        Command::Settings => handler.handle_settings().await,
+        Command::Dash(arg) => handler.handle_dashboard(&arg).await,
+        Command::Schedule(dates) => handler.handle_schedule(&dates).await,
+        Command::Odds(query) => handler.handle_odds(&query).await,
+        Command::Roll(dice) => handler.handle_dice_roll(&dice).await,
+        Command::Stats(arg) => handler.handle_stats(&arg).await,
+        Command::Session(arg) => handler.handle_session(&arg).await,
+        Command::Verify(seed) => handler.handle_verify(&seed).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::{InlineKeyboardMarkup, MessageId};
+
+    use super::dispatch_update;
+    use crate::callback::{Callback, CallbackAction};
+    use crate::testing::{Sent, TestChat};
+    use crate::tracker::Tracker;
+
+    #[tokio::test]
+    async fn adds_player() {
+        let mut chat = TestChat::new();
+        chat.command("/pa Alice").await.unwrap();
+
+        let messages = chat.bot.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].starts_with("Player *Alice* added by "));
+        let tracker = chat.tracker().await;
+        assert_eq!(tracker.players.len(), 1);
+        assert_eq!(tracker.players[0].name, "Alice");
+    }
+
+    #[tokio::test]
+    async fn rejects_duplicate_player() {
+        let mut chat = TestChat::new();
+        chat.command("/pa Alice").await.unwrap();
+        let err = chat.command("/pa Alice").await.unwrap_err();
+
+        assert_eq!(err.to_string(), "Player Alice already present");
+        assert_eq!(chat.tracker().await.players.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn changes_harm_and_stress() {
+        let mut chat = TestChat::new();
+        chat.command("/pa Alice").await.unwrap();
+        chat.command("/p").await.unwrap();
+        chat.bot.take();
+
+        chat.tap(1, CallbackAction::AddHarm).await.unwrap();
+        chat.tap(1, CallbackAction::AddStress).await.unwrap();
+        chat.tap(1, CallbackAction::AddStress).await.unwrap();
+        chat.tap(1, CallbackAction::SubStress).await.unwrap();
+
+        let messages = chat.bot.messages();
+        assert!(messages[0].starts_with("Player *Alice* has *1* harm"));
+        assert!(messages[3].starts_with("Player *Alice* has *1* stress"));
+        let tracker = chat.tracker().await;
+        assert_eq!(tracker.players[0].harm, 1);
+        assert_eq!(tracker.players[0].stress, 1);
+
+        let players_msg = tracker.players_msg.unwrap().msg_id;
+        let last_edit = chat
+            .bot
+            .take()
+            .into_iter()
+            .rev()
+            .find_map(|sent| match sent {
+                Sent::EditText { msg_id, text, .. } if msg_id == players_msg => Some(text),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(last_edit, "*Players:*\n\n*Alice*: *1* harm, *1* stress\n");
+    }
+
+    #[tokio::test]
+    async fn timer_fires_at_zero() {
+        let mut chat = TestChat::new();
+        chat.command("/ta Clock 2").await.unwrap();
+        chat.tap(1, CallbackAction::SubTimer).await.unwrap();
+        assert_eq!(chat.tracker().await.timers[0].value, 1);
+
+        chat.tap(1, CallbackAction::SubTimer).await.unwrap();
+
+        let messages = chat.bot.messages();
+        assert!(messages[1].starts_with("Timer *Clock* has *1* ticks left"));
+        assert!(messages[2].starts_with("Timer *Clock* has fired\\!"));
+        assert!(chat.tracker().await.timers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn lists_timers_replacing_previous_messages() {
+        let mut chat = TestChat::new();
+        chat.command("/ta Clock 4").await.unwrap();
+        chat.command("/t").await.unwrap();
+        let first = chat.tracker().await.timers_msg.unwrap();
+        chat.bot.take();
+
+        chat.command("/t").await.unwrap();
+
+        let sent = chat.bot.take();
+        assert_eq!(
+            sent[0],
+            Sent::Delete {
+                msg_id: first.msg_id
+            }
+        );
+        assert_eq!(
+            sent[1],
+            Sent::Delete {
+                msg_id: first.kb_id
+            }
+        );
+        assert!(
+            matches!(&sent[2], Sent::Message { text, .. } if text.contains("*Clock*: *4* ticks left"))
+        );
+        assert!(matches!(
+            &sent[3],
+            Sent::Message {
+                markup: Some(_),
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn pages_and_filters_timers() {
+        let mut chat = TestChat::new();
+        for i in 1..=9 {
+            chat.command(&format!("/ta Clock{i}")).await.unwrap();
+        }
+        chat.command("/ta Alarm").await.unwrap();
+        let first_column = |markup: &InlineKeyboardMarkup| {
+            markup
+                .inline_keyboard
+                .iter()
+                .map(|row| row[0].text.clone())
+                .collect::<Vec<_>>()
+        };
+        let last_keyboard = |sent: Vec<Sent>| {
+            sent.into_iter()
+                .rev()
+                .find_map(|sent| match sent {
+                    Sent::Message {
+                        markup: Some(markup),
+                        ..
+                    }
+                    | Sent::EditMarkup { markup, .. } => Some(first_column(&markup)),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        chat.command("/t").await.unwrap();
+        let keyboard = last_keyboard(chat.bot.take());
+        assert_eq!(keyboard.len(), 9);
+        assert_eq!(keyboard[0], "Alarm");
+        assert_eq!(keyboard[8], "◀");
+
+        chat.tap(1, CallbackAction::TimersPage).await.unwrap();
+        assert_eq!(
+            last_keyboard(chat.bot.take()),
+            vec!["Clock8", "Clock9", "◀"]
+        );
+
+        chat.command("/t clock1").await.unwrap();
+        assert_eq!(last_keyboard(chat.bot.take()), vec!["Clock1"]);
+
+        chat.command("/ta Heist: Alarm 0").await.unwrap();
+        chat.command("/ta Heist: Guards").await.unwrap();
+        chat.command("/t heist:").await.unwrap();
+        assert_eq!(
+            last_keyboard(chat.bot.take()),
+            vec!["Heist: Alarm", "Heist: Guards"]
+        );
+        chat.command("/t * Heist:").await.unwrap();
+        assert_eq!(last_keyboard(chat.bot.take()), vec!["Heist: Guards"]);
+    }
+
+    #[tokio::test]
+    async fn sets_value_on_keypad() {
+        let mut chat = TestChat::new();
+        chat.command("/pa Alice").await.unwrap();
+        chat.command("/p").await.unwrap();
+        chat.tap(0, CallbackAction::ShowHarmKb).await.unwrap();
+        chat.bot.take();
+
+        let keypad = |value| Callback {
+            item_id: 1,
+            action: CallbackAction::HarmKeypad,
+            revision: 1,
+            value,
+        };
+        chat.tap_data(&keypad(0).serialize(chat.chat_id))
+            .await
+            .unwrap();
+        chat.tap_data(&keypad(1).serialize(chat.chat_id))
+            .await
+            .unwrap();
+        let title = chat
+            .bot
+            .take()
+            .into_iter()
+            .find_map(|sent| match sent {
+                Sent::EditMarkup { markup, .. } => Some(markup.inline_keyboard[0][0].text.clone()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(title, "Alice, harm: 0");
+
+        let set = Callback {
+            action: CallbackAction::SetHarm,
+            ..keypad(12)
+        };
+        chat.tap_data(&set.serialize(chat.chat_id)).await.unwrap();
+        assert_eq!(chat.tracker().await.players[0].harm, 12);
+
+        chat.tap(0, CallbackAction::NextSteps).await.unwrap();
+        assert_eq!(chat.tracker().await.settings.steps, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn renames_and_pins() {
+        let mut chat = TestChat::new();
+        chat.command("/pa Alice").await.unwrap();
+        chat.command("/pa Bob").await.unwrap();
+        chat.command("/pr Bob = Carol").await.unwrap();
+        let err = chat.command("/pr Carol = Alice").await.unwrap_err();
+        assert_eq!(err.to_string(), "Player Alice already present");
+
+        chat.tap(2, CallbackAction::PinPlayer).await.unwrap();
+        let names = |tracker: Tracker| {
+            tracker
+                .players
+                .into_iter()
+                .map(|player| player.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(chat.tracker().await), vec!["Carol", "Alice"]);
+
+        chat.bot.take();
+        chat.tap(1, CallbackAction::RenamePlayer).await.unwrap();
+        let prompt = chat
+            .bot
+            .take()
+            .into_iter()
+            .find_map(|sent| match sent {
+                Sent::ForceReply { msg_id, .. } => Some(msg_id),
+                _ => None,
+            })
+            .unwrap();
+        chat.reply(prompt, "Alicia").await.unwrap();
+        assert_eq!(names(chat.tracker().await), vec!["Carol", "Alicia"]);
+        assert!(chat.bot.messages()[0].starts_with("Player *Alice* is now *Alicia*"));
+
+        chat.command("/ta Clock").await.unwrap();
+        chat.command("/te Clock = 2/6").await.unwrap();
+        let timer = &chat.tracker().await.timers[0];
+        assert_eq!((timer.value, timer.max), (2, Some(6)));
+    }
+
+    #[tokio::test]
+    async fn keeps_dashboard_up_to_date() {
+        let mut chat = TestChat::new();
+        chat.command("/pa Alice").await.unwrap();
+        chat.command("/dash").await.unwrap();
+        let dashboard = chat.tracker().await.dashboard.unwrap().msg_id;
+        assert!(chat.bot.take().contains(&Sent::Pin { msg_id: dashboard }));
+
+        chat.command("/ta Clock").await.unwrap();
+        let edited = chat.bot.take().into_iter().any(|sent| {
+            matches!(sent, Sent::EditText { msg_id, text, .. }
+                if msg_id == dashboard && text.contains("Alice") && text.contains("Clock"))
+        });
+        assert!(edited);
+
+        // Posted again once deleted
+        chat.bot.delete_by_user(dashboard);
+        chat.command("/pa Bob").await.unwrap();
+        let reposted = chat.tracker().await.dashboard.unwrap().msg_id;
+        assert_ne!(reposted, dashboard);
+        assert!(chat.bot.take().contains(&Sent::Pin { msg_id: reposted }));
+
+        // Unknown options leave the dashboard alone
+        assert!(chat.command("/dash foo").await.is_err());
+        assert!(chat.bot.take().is_empty());
+        assert_eq!(chat.tracker().await.dashboard.unwrap().msg_id, reposted);
+
+        chat.command("/dash off").await.unwrap();
+        assert!(chat.tracker().await.dashboard.is_none());
+        assert!(chat.bot.take().contains(&Sent::Unpin { msg_id: reposted }));
+    }
+
+    #[tokio::test]
+    async fn skips_unchanged_and_forgets_deleted_lists() {
+        let mut chat = TestChat::new();
+        chat.command("/pa Alice").await.unwrap();
+        chat.command("/p").await.unwrap();
+        let list = chat.tracker().await.players_msg.unwrap();
+        chat.bot.take();
+
+        let same_harm = Callback {
+            item_id: 1,
+            action: CallbackAction::SetHarm,
+            revision: chat.tracker().await.players_revision,
+            value: 0,
+        };
+        chat.tap_data(&same_harm.serialize(chat.chat_id))
+            .await
+            .unwrap();
+        assert!(!chat.bot.take().iter().any(|sent| matches!(
+            sent,
+            Sent::EditText { msg_id, .. } if *msg_id == list.msg_id
+        )));
+
+        chat.bot.delete_by_user(list.msg_id);
+        chat.command("/pa Bob").await.unwrap();
+        assert!(chat.tracker().await.players_msg.is_none());
+    }
+
+    #[tokio::test]
+    async fn deletes_player_from_keyboard() {
+        let mut chat = TestChat::new();
+        chat.command("/pa Alice").await.unwrap();
+        chat.command("/pa Bob").await.unwrap();
+        chat.command("/p").await.unwrap();
+        chat.tap(0, CallbackAction::ShowPlayersKb).await.unwrap();
+        chat.tap(1, CallbackAction::DeletePlayer).await.unwrap();
+        chat.tap(0, CallbackAction::CancelConfirm).await.unwrap();
+        assert_eq!(chat.tracker().await.players.len(), 2);
+
+        chat.tap(1, CallbackAction::DeletePlayer).await.unwrap();
+        assert_eq!(chat.tracker().await.players.len(), 2);
+        chat.tap(0, CallbackAction::Confirm).await.unwrap();
+
+        let tracker = chat.tracker().await;
+        assert!(tracker.pending_confirm.is_none());
+        assert_eq!(tracker.players.len(), 1);
+        assert_eq!(tracker.players[0].name, "Bob");
+        assert!(chat
+            .bot
+            .messages()
+            .last()
+            .unwrap()
+            .starts_with("Player *Alice* with *0* harm and *0* stress has been removed"));
+    }
+
+    #[tokio::test]
+    async fn stale_keyboard_is_not_applied() {
+        let mut chat = TestChat::new();
+        chat.command("/pa Alice").await.unwrap();
+        let old_button = Callback {
+            item_id: 1,
+            action: CallbackAction::AddHarm,
+            revision: chat.tracker().await.players_revision,
+            value: 1,
+        }
+        .serialize(chat.chat_id);
+        // Bob gets the id Alice had
+        chat.command("/p").await.unwrap();
+        chat.tap(1, CallbackAction::DeletePlayer).await.unwrap();
+        chat.tap(0, CallbackAction::Confirm).await.unwrap();
+        chat.command("/pa Bob").await.unwrap();
+        chat.bot.take();
+
+        chat.tap_data(&old_button).await.unwrap();
+        chat.tap_data("1|AddHarm").await.unwrap();
+
+        assert_eq!(chat.tracker().await.players[0].harm, 0);
+        let outdated = Sent::CallbackAnswer {
+            text: Some("This keyboard is outdated, list the players or timers again".to_owned()),
+            show_alert: true,
+        };
+        assert_eq!(chat.bot.take(), vec![outdated.clone(), outdated]);
+    }
+
+    #[tokio::test]
+    async fn unknown_player_is_an_error() {
+        let mut chat = TestChat::new();
+        let err = chat.tap(4, CallbackAction::AddHarm).await.unwrap_err();
+
+        assert_eq!(err.to_string(), "Player id 4 not found");
+        assert!(chat.bot.messages().is_empty());
+        assert_eq!(
+            chat.bot.take(),
+            vec![Sent::CallbackAnswer {
+                text: Some("Player id 4 not found".to_owned()),
+                show_alert: true
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_redelivered_updates() {
+        let mut chat = TestChat::new();
+        // The first two store nothing, but none must be answered twice
+        let updates = [
+            chat.message_update("/odds 2"),
+            chat.message_update("/help"),
+            chat.message_update("/r1"),
+        ];
+        for update in updates.iter().chain(&updates) {
+            dispatch_update(chat.bot.clone(), update.clone(), chat.storage.clone())
+                .await
+                .unwrap();
+        }
+        let sent = chat.bot.take();
+        let dice = sent.iter().filter(|sent| matches!(sent, Sent::Dice { .. }));
+        assert_eq!(dice.count(), 1);
+        assert_eq!(sent.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn skips_redelivery_while_the_first_is_handled() {
+        let mut chat = TestChat::new();
+        let update = chat.message_update("/r2");
+        let deliver = || dispatch_update(chat.bot.clone(), update.clone(), chat.storage.clone());
+        let (first, second) = tokio::join!(deliver(), deliver());
+        first.unwrap();
+        second.unwrap();
+        assert_eq!(chat.bot.take().len(), 3);
+        assert_eq!(chat.tracker().await.rolls.recent.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn callbacks_are_answered() {
+        let mut chat = TestChat::new();
+        chat.command("/ta Clock 4").await.unwrap();
+        chat.bot.take();
+        chat.tap(1, CallbackAction::AddTimer).await.unwrap();
+        chat.tap(0, CallbackAction::NoAction).await.unwrap();
+
+        let answers = chat
+            .bot
+            .take()
+            .into_iter()
+            .filter(|sent| matches!(sent, Sent::CallbackAnswer { .. }))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            answers,
+            vec![
+                Sent::CallbackAnswer {
+                    text: Some("Clock: 5 ticks left".to_owned()),
+                    show_alert: false
+                },
+                Sent::CallbackAnswer {
+                    text: None,
+                    show_alert: false
+                }
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn quiet_chat_gets_only_popups() {
+        let mut chat = TestChat::new();
+        chat.command("/pa Alice").await.unwrap();
+        chat.command("/quiet").await.unwrap();
+        chat.bot.take();
+
+        chat.tap(1, CallbackAction::AddStress).await.unwrap();
+
+        assert!(chat.bot.messages().is_empty());
+        assert!(chat.bot.take().contains(&Sent::CallbackAnswer {
+            text: Some("Alice: 1 stress".to_owned()),
+            show_alert: false
+        }));
+        assert_eq!(chat.tracker().await.players[0].stress, 1);
+    }
+
+    #[tokio::test]
+    async fn settings_change_handler_behavior() {
+        let mut chat = TestChat::new();
+        chat.command("/settings").await.unwrap();
+        chat.tap(0, CallbackAction::AddStressCap).await.unwrap();
+        chat.tap(0, CallbackAction::SubClockSize).await.unwrap();
+        chat.tap(0, CallbackAction::ToggleDeleteFiredTimers)
+            .await
+            .unwrap();
+
+        let settings = chat.tracker().await.settings;
+        assert_eq!(settings.stress_cap, Some(9));
+        assert_eq!(settings.clock_size, 3);
+        assert!(!settings.delete_fired_timers);
+        assert!(chat
+            .bot
+            .take()
+            .iter()
+            .any(|sent| matches!(sent, Sent::EditMarkup { .. })));
+
+        chat.command("/ta Long clock").await.unwrap();
+        assert_eq!(chat.tracker().await.timers[0].name, "Long clock");
+        assert_eq!(chat.tracker().await.timers[0].value, 3);
+        for _ in 0..3 {
+            chat.tap(1, CallbackAction::SubTimer).await.unwrap();
+        }
+        assert!(chat.bot.messages().last().unwrap().contains("has fired"));
+        assert_eq!(chat.tracker().await.timers[0].value, 0);
+
+        // Kept at zero, without firing again
+        chat.tap(1, CallbackAction::SubTimer).await.unwrap();
+        let fired = chat.bot.messages();
+        let fired = fired.iter().filter(|text| text.contains("has fired"));
+        assert_eq!(fired.count(), 1);
+        assert_eq!(chat.tracker().await.timers[0].value, 0);
+    }
+
+    #[tokio::test]
+    async fn stress_cap_is_enforced() {
+        let mut chat = TestChat::new();
+        chat.command("/pa Alice").await.unwrap();
+        let mut tracker = chat.tracker().await;
+        tracker.settings.stress_cap = Some(1);
+        chat.storage
+            .context(chat.chat_id)
+            .await
+            .put(&tracker)
+            .await
+            .unwrap();
+
+        chat.tap(1, CallbackAction::AddStress).await.unwrap();
+        let err = chat.tap(1, CallbackAction::AddStress).await.unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Player Alice cannot have more than 1 stress"
+        );
+        assert_eq!(chat.tracker().await.players[0].stress, 1);
+    }
+
+    #[tokio::test]
+    async fn replies_in_chat_language() {
+        let mut chat = TestChat::new();
+        chat.command("/settings").await.unwrap();
+        chat.tap(0, CallbackAction::NextLanguage).await.unwrap();
+        chat.tap(0, CallbackAction::NextLanguage).await.unwrap();
+        chat.command("/pa Alice").await.unwrap();
+
+        assert!(chat
+            .bot
+            .messages()
+            .last()
+            .unwrap()
+            .starts_with("Игрок *Alice* добавлен \\("));
+    }
+
+    #[tokio::test]
+    async fn answers_outdated_confirm_in_chat_language() {
+        let mut chat = TestChat::new();
+        chat.command("/settings").await.unwrap();
+        chat.tap(0, CallbackAction::NextLanguage).await.unwrap();
+        chat.tap(0, CallbackAction::NextLanguage).await.unwrap();
+        chat.command("/pa Alice").await.unwrap();
+        chat.command("/p").await.unwrap();
+        chat.tap(0, CallbackAction::ShowPlayersKb).await.unwrap();
+        chat.tap(1, CallbackAction::DeletePlayer).await.unwrap();
+        chat.command("/pa Bob").await.unwrap();
+        chat.bot.take();
+
+        chat.tap(0, CallbackAction::Confirm).await.unwrap();
+        assert_eq!(chat.tracker().await.players.len(), 2);
+        assert!(chat.tracker().await.pending_confirm.is_none());
+        assert!(chat.bot.take().contains(&Sent::CallbackAnswer {
+            text: Some(
+                "Эта клавиатура устарела, выведите список игроков или таймеров заново".to_owned()
+            ),
+            show_alert: false,
+        }));
+    }
+
+    #[tokio::test]
+    async fn rolls_dice() {
+        let mut chat = TestChat::new();
+        chat.command("/r3 desperate great").await.unwrap();
+
+        let sent = chat.bot.take();
+        assert_eq!(sent.len(), 4);
+        assert!(sent[..3]
+            .iter()
+            .all(|sent| matches!(sent, Sent::Dice { .. })));
+        // The test messenger rolls 1, 2 and 3
+        assert_eq!(
+            sent[3],
+            Sent::Reply {
+                msg_id: MessageId(4),
+                reply_to: MessageId(3),
+                text: "Highest *3* — bad outcome\nDesperate, great effect: the worst outcome: \
+                       severe harm, a serious complication or a lost opportunity\\.\n\
+                       Mark xp for the desperate action\\. by @tester"
+                    .to_owned(),
+            }
+        );
+
+        assert!(chat.command("/r2 boldly").await.is_err());
+        assert!(chat.bot.take().is_empty());
+    }
+
+    #[tokio::test]
+    async fn wipes_after_confirmation() {
+        let mut chat = TestChat::new();
+        chat.command("/pa Alice").await.unwrap();
+        chat.command("/wipe").await.unwrap();
+        assert_eq!(chat.tracker().await.players.len(), 1);
+
+        // Buttons stop working after a while
+        let expire = |mut tracker: Tracker| {
+            tracker.pending_confirm.as_mut().unwrap().expires_at = 0;
+            tracker
+        };
+        let tracker = expire(chat.tracker().await);
+        chat.storage
+            .context(chat.chat_id)
+            .await
+            .put(&tracker)
+            .await
+            .unwrap();
+        chat.bot.take();
+        chat.tap(0, CallbackAction::Confirm).await.unwrap();
+        assert_eq!(chat.tracker().await.players.len(), 1);
+        assert!(chat.bot.take().contains(&Sent::CallbackAnswer {
+            text: Some("Too late, ask again".to_owned()),
+            show_alert: false,
+        }));
+
+        // And are removed by the next update once expired
+        chat.command("/wipe yes").await.unwrap();
+        let msg_id = chat.tracker().await.pending_confirm.unwrap().msg_id;
+        let tracker = expire(chat.tracker().await);
+        chat.storage
+            .context(chat.chat_id)
+            .await
+            .put(&tracker)
+            .await
+            .unwrap();
+        chat.bot.take();
+        chat.command("/pa Bob").await.unwrap();
+        assert!(chat.bot.take().contains(&Sent::EditText {
+            msg_id,
+            text: "Wipe cancelled".to_owned(),
+            markup: None,
+        }));
+        assert!(chat.tracker().await.pending_confirm.is_none());
+
+        chat.command("/wipe").await.unwrap();
+        chat.tap(0, CallbackAction::Confirm).await.unwrap();
+        assert!(chat.tracker().await.players.is_empty());
+        assert!(chat.tracker().await.pending_confirm.is_none());
+    }
+
+    #[tokio::test]
+    async fn fires_scheduled_timers() {
+        let mut chat = TestChat::new();
+        chat.command("/td Session = 30m").await.unwrap();
+        assert!(chat.bot.messages()[0].starts_with("Timer *Session* is due "));
+        crate::scheduler::fire_due_timers(&chat.bot, &chat.storage)
+            .await
+            .unwrap();
+        assert_eq!(chat.tracker().await.timers.len(), 1);
+
+        let mut tracker = chat.tracker().await;
+        tracker.timers[0].due_at = Some(1);
+        chat.storage
+            .context(chat.chat_id)
+            .await
+            .put(&tracker)
+            .await
+            .unwrap();
+        chat.bot.take();
+        crate::scheduler::fire_due_timers(&chat.bot, &chat.storage)
+            .await
+            .unwrap();
+        assert_eq!(chat.bot.messages(), ["⏰ Timer *Session* is due\\!"]);
+        // Fired timers are deleted by default
+        assert!(chat.tracker().await.timers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn schedules_sessions_with_a_poll() {
+        let mut chat = TestChat::new();
+        chat.command("/pa Tester").await.unwrap();
+        chat.command("/pa Bob").await.unwrap();
+        chat.bot.take();
+        chat.command("/schedule 2100-01-02 19:00; 2100-01-01 18:30")
+            .await
+            .unwrap();
+        assert_eq!(
+            chat.bot.messages(),
+            ["*When do we play next?*\n\n\
+              `2100\\-01\\-01 18:30`: nobody yet\n\
+              `2100\\-01\\-02 19:00`: nobody yet\n\n\
+              Waiting for: Bob, Tester"]
+        );
+
+        // The voter is linked to the player named like them
+        chat.tap(1, CallbackAction::Vote).await.unwrap();
+        let poll = chat.tracker().await.session_poll.unwrap();
+        assert_eq!(poll.options[1].votes[0].name, "Tester");
+        chat.tap(0, CallbackAction::ClosePoll).await.unwrap();
+        assert!(chat.bot.messages().contains(
+            &"Next session: *2100\\-01\\-02 19:00* with Tester, I will remind you an hour \
+              before by @tester"
+                .to_owned()
+        ));
+        let tracker = chat.tracker().await;
+        assert!(tracker.session_poll.is_none());
+        assert_eq!(tracker.timers[0].name, "Session on 2100-01-02 19:00");
+        assert!(tracker.timers[0].due_at.is_some());
+
+        chat.bot.take();
+        chat.tap(1, CallbackAction::Vote).await.unwrap();
+        assert!(matches!(
+            chat.bot.take()[..],
+            [Sent::CallbackAnswer {
+                show_alert: true,
+                ..
+            }]
+        ));
+    }
+
+    #[tokio::test]
+    async fn shows_odds() {
+        let mut chat = TestChat::new();
+        chat.command("/odds 1").await.unwrap();
+        chat.command("/odds").await.unwrap();
+        chat.command("/odds 3d6 >= 16").await.unwrap();
+        let messages = chat.bot.messages();
+        assert_eq!(
+            messages[0],
+            "*Action roll with 1 die:*\n```\n\
+             crit                       0.0%\n\
+             full    █████             16.7%\n\
+             partial ███████████       33.3%\n\
+             bad     ████████████████  50.0%\n```"
+        );
+        assert!(messages[1].contains("\n   2   2.8%  27.8%    44.4%  25.0%\n"));
+        assert!(messages[2].starts_with("*3d6*, average 10\\.50:\n```\n3  █ "));
+        assert!(messages[2].ends_with("\nChance of 3d6 \\>\\= 16: *4\\.6%*"));
+    }
+
+    #[tokio::test]
+    async fn keeps_dice_statistics() {
+        let mut chat = TestChat::new();
+        chat.command("/stats").await.unwrap();
+        chat.command("/pa Tester").await.unwrap();
+        chat.command("/r3").await.unwrap();
+        chat.command("/r2").await.unwrap();
+        chat.command("/stats").await.unwrap();
+        let messages = chat.bot.messages();
+        assert_eq!(messages[0], "*Dice this session:*\n\nNo dice rolled yet");
+        // The test messenger rolls 3, 4 and 5, replies, then rolls 1 and 2
+        assert_eq!(
+            messages[4],
+            "*Dice this session:*\n\n*Tester*: 2 rolls of 5 dice, average 3\\.00 per d6, 86% of the \
+             expected total, 0 crits in 2 action rolls\n\n\
+             *Cursed dice:*\n1\\. Tester: 86% of the expected total"
+        );
+
+        chat.command("/roll 2d6+1").await.unwrap();
+        // Bot-side rolls of the test messenger come from a fixed seed
+        assert_eq!(
+            chat.bot.messages()[5],
+            "🎲 2d6\\+1: 1 \\+ 4 \\+ 1 \\= *6* by @tester"
+        );
+        chat.command("/stats all").await.unwrap();
+        assert_eq!(
+            chat.bot.messages()[6],
+            "*Dice in this campaign:*\n\n*Tester*: 3 rolls of 7 dice, average 2\\.86 per d6, 82% of the \
+             expected total, 0 crits in 2 action rolls\n\n\
+             *Cursed dice:*\n1\\. Tester: 82% of the expected total"
+        );
+        let rolls = chat.tracker().await.rolls.recent;
+        assert_eq!(rolls.len(), 3);
+        assert_eq!(rolls[0].values, [3, 4, 5]);
+        assert_eq!(rolls[1].values, [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn rolls_fairly_from_a_committed_seed() {
+        let mut chat = TestChat::new();
+        chat.command("/session start").await.unwrap();
+        let commitment = chat.tracker().await.fair_session.unwrap().commitment;
+        assert!(chat.bot.messages()[0].contains(&commitment));
+        chat.command("/roll 3d6").await.unwrap();
+        chat.command("/session end").await.unwrap();
+        let session = chat.tracker().await.revealed_session.unwrap();
+        let [a, b, c] = session.rolls[0].values[..] else {
+            panic!("Expected three dice");
+        };
+        assert_eq!(
+            chat.bot.messages()[1],
+            format!(
+                "🎲 3d6: {a} \\+ {b} \\+ {c} \\= *{}*, fair roll \\#1 by @tester",
+                a + b + c
+            )
+        );
+        assert!(chat.bot.messages()[2].contains(&session.seed));
+        assert_eq!(
+            chat.tracker().await.rolls.recent[0].values,
+            session.rolls[0].values
+        );
+
+        chat.command(&format!("/verify {}", session.seed))
+            .await
+            .unwrap();
+        assert_eq!(
+            chat.bot.messages()[3],
+            format!("The seed matches the hash `{commitment}` and gives the only roll of the session\\.")
+        );
+        chat.command(&format!("/verify {}", "0".repeat(64)))
+            .await
+            .unwrap();
+        assert_eq!(
+            chat.bot.messages()[4],
+            format!("This is not the seed with the hash `{commitment}`\\.")
+        );
+
+        // A roll changed after the fact is caught
+        let mut tracker = chat.tracker().await;
+        tracker.revealed_session.as_mut().unwrap().rolls[0].values = vec![0, 0, 0];
+        chat.storage
+            .context(chat.chat_id)
+            .await
+            .put(&tracker)
+            .await
+            .unwrap();
+        chat.command("/verify").await.unwrap();
+        assert_eq!(
+            chat.bot.messages()[5],
+            format!(
+                "*The seed does not give these rolls:*\n\\#1 3d6: rolled 0, 0, 0, the seed gives {a}, {b}, {c}"
+            )
+        );
     }
 }
@@ -0,0 +1,261 @@
+//! Provably fair bot-side rolls, see `/session` and `/verify`.
+//!
+//! At the start of a fair session the bot picks a secret seed of 32 bytes
+//! and publishes its SHA-256 hash. Roll number `n` of the session (from 0)
+//! reads the ChaCha20 keystream whose key is the SHA-256 hash of the seed
+//! followed by `n` as a big-endian `u64`, with a zero nonce and the block
+//! counter starting at zero, as little-endian 32-bit words. Each die of the
+//! roll in turn takes words until one, `x`, is below the largest multiple of
+//! its sides `s` up to 2^32, and shows `x % s + 1`. Once the seed is
+//! revealed, anyone can check that it matches the hash and gives every roll
+//! made during the session.
+//!
+//! The seed is stored with the tracker in plain text until the session ends,
+//! so anyone who can read the bot's storage can predict the rolls.
+
+use anyhow::anyhow;
+use rand::{rngs::OsRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::dice::DiceRoll;
+
+const SEED_LEN: usize = 32;
+
+/// Randomness of bot-side rolls: unpredictable, or derived from a seed so
+/// that the rolls can be checked or repeated
+pub enum RollRng {
+    Os(OsRng),
+    Seeded(Box<ChaCha20Rng>),
+}
+
+impl RollRng {
+    pub fn os() -> Self {
+        RollRng::Os(OsRng)
+    }
+
+    /// RNG of roll number `nonce` made with `seed`
+    pub fn seeded(seed: &[u8], nonce: u64) -> Self {
+        let key = Sha256::new()
+            .chain_update(seed)
+            .chain_update(nonce.to_be_bytes())
+            .finalize();
+        RollRng::Seeded(Box::new(ChaCha20Rng::from_seed(key.into())))
+    }
+}
+
+impl RngCore for RollRng {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            RollRng::Os(rng) => rng.next_u32(),
+            RollRng::Seeded(rng) => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            RollRng::Os(rng) => rng.next_u64(),
+            RollRng::Seeded(rng) => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            RollRng::Os(rng) => rng.fill_bytes(dest),
+            RollRng::Seeded(rng) => rng.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        match self {
+            RollRng::Os(rng) => rng.try_fill_bytes(dest),
+            RollRng::Seeded(rng) => rng.try_fill_bytes(dest),
+        }
+    }
+}
+
+/// A session whose bot-side rolls come from a committed seed, stored with
+/// the tracker
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FairSession {
+    /// Hex, kept secret until the session ends
+    pub seed: String,
+    /// Hex SHA-256 hash of the seed, published at the start
+    pub commitment: String,
+    /// Rolls made so far, the nonce of each is its index
+    pub rolls: Vec<FairRoll>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FairRoll {
+    /// Dice notation, such as `4d6kh3`
+    pub dice: String,
+    pub values: Vec<u32>,
+}
+
+/// Outcome of checking a revealed seed
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verification {
+    /// The seed is not the one committed to
+    WrongSeed,
+    /// Rolls which the seed does not give, none if all check out
+    Replayed(Vec<Mismatch>),
+}
+
+/// A roll the revealed seed does not give
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mismatch {
+    /// Counted from 1
+    pub number: usize,
+    pub roll: FairRoll,
+    pub expected: Vec<u32>,
+}
+
+impl FairSession {
+    /// Starts a session with a new random seed
+    pub fn start() -> Self {
+        let mut seed = [0; SEED_LEN];
+        OsRng.fill_bytes(&mut seed);
+        Self::with_seed(&seed)
+    }
+
+    pub fn with_seed(seed: &[u8]) -> Self {
+        Self {
+            seed: to_hex(seed),
+            commitment: commitment(seed),
+            rolls: Vec::new(),
+        }
+    }
+
+    /// Rolls the next roll of the session and remembers it
+    pub fn roll(&mut self, dice: &DiceRoll) -> anyhow::Result<Vec<u32>> {
+        let seed = from_hex(&self.seed)?;
+        let values = dice.roll(&mut RollRng::seeded(&seed, self.rolls.len() as u64));
+        self.rolls.push(FairRoll {
+            dice: dice.to_string(),
+            values: values.clone(),
+        });
+        Ok(values)
+    }
+
+    /// Checks a revealed seed against the commitment, then replays every
+    /// roll with it
+    pub fn verify(&self, seed: &str) -> anyhow::Result<Verification> {
+        let seed = from_hex(seed.trim())?;
+        if commitment(&seed) != self.commitment {
+            return Ok(Verification::WrongSeed);
+        }
+        let mut mismatches = Vec::new();
+        for (nonce, roll) in self.rolls.iter().enumerate() {
+            let dice = roll.dice.parse::<DiceRoll>()?;
+            let expected = dice.roll(&mut RollRng::seeded(&seed, nonce as u64));
+            if expected != roll.values {
+                mismatches.push(Mismatch {
+                    number: nonce + 1,
+                    roll: roll.clone(),
+                    expected,
+                });
+            }
+        }
+        Ok(Verification::Replayed(mismatches))
+    }
+}
+
+/// Hex SHA-256 hash of the seed
+pub fn commitment(seed: &[u8]) -> String {
+    to_hex(&Sha256::digest(seed))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> anyhow::Result<Vec<u8>> {
+    let invalid = || anyhow!("Expected a seed of {} hex digits", SEED_LEN * 2);
+    if hex.len() != SEED_LEN * 2 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|pos| u8::from_str_radix(&hex[pos..pos + 2], 16).map_err(|_| invalid()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_rolls_from_the_revealed_seed() {
+        let mut session = FairSession::with_seed(&[0; SEED_LEN]);
+        assert_eq!(
+            session.commitment,
+            "66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925"
+        );
+        let dice = "3d6".parse().unwrap();
+        let first = session.roll(&dice).unwrap();
+        let second = session.roll(&dice).unwrap();
+        // Fixed by the seed, a change here breaks checking earlier sessions
+        assert_eq!((first, second), (vec![5, 1, 4], vec![4, 2, 5]));
+
+        let seed = session.seed.clone();
+        assert_eq!(
+            session.verify(&seed).unwrap(),
+            Verification::Replayed(Vec::new())
+        );
+        session.rolls[1].values = vec![6, 6, 6];
+        let Verification::Replayed(mismatches) = session.verify(&seed).unwrap() else {
+            panic!("Expected the seed to match");
+        };
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].number, 2);
+        assert_eq!(
+            session.verify(&"1".repeat(SEED_LEN * 2)).unwrap(),
+            Verification::WrongSeed
+        );
+        assert!(session.verify("00").is_err());
+    }
+
+    /// Gives the words it was made with
+    struct Words(Vec<u32>);
+
+    impl RngCore for Words {
+        fn next_u32(&mut self) -> u32 {
+            self.0.remove(0)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            unimplemented!()
+        }
+
+        fn fill_bytes(&mut self, _dest: &mut [u8]) {
+            unimplemented!()
+        }
+
+        fn try_fill_bytes(&mut self, _dest: &mut [u8]) -> Result<(), rand::Error> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn rolls_as_documented() {
+        // The keystream of RFC 8439, appendix A.1, test vector 1
+        let mut stream = ChaCha20Rng::from_seed([0; 32]);
+        assert_eq!(
+            [stream.next_u32(), stream.next_u32()],
+            [0xade0b876, 0x903df1a0]
+        );
+
+        // The second roll of the all-zero seed starts with a 4 and a 2
+        let mut stream = RollRng::seeded(&[0; SEED_LEN], 1);
+        let words = [stream.next_u32(), stream.next_u32()];
+        assert_eq!(words, [0xbdbad881, 0xe71db7dd]);
+        assert_eq!(words.map(|word| word % 6 + 1), [4, 2]);
+
+        // Words from 2^32 - 4 up are dropped for a d6, as 2^32 = 4 mod 6
+        let mut words = Words(vec![u32::MAX, 4294967292, 4294967291, 7]);
+        let dice = "2d6".parse::<DiceRoll>().unwrap();
+        assert_eq!(dice.roll(&mut words), [6, 2]);
+    }
+}
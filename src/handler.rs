@@ -1,119 +1,583 @@
-use std::future::Future;
+use std::{
+    collections::{BTreeMap, HashSet},
+    future::Future,
+    pin::Pin,
+    sync::Mutex,
+    time::Duration,
+};
 
 use anyhow::{anyhow, bail};
-use tracing::instrument;
+use tracing::{info, instrument, warn};
 
 use crate::{
     callback::{
-        make_manage_harm_keyboard, make_manage_players_keyboard, make_manage_stress_keyboard,
-        make_manage_timers_keyboard, make_players_keyboard, make_timers_keyboard,
+        make_confirm_keyboard, make_keypad_keyboard, make_manage_harm_keyboard,
+        make_manage_players_keyboard, make_manage_stress_keyboard, make_manage_timers_keyboard,
+        make_players_keyboard, make_poll_keyboard, make_settings_keyboard, make_timers_keyboard,
+        KeypadTarget, StaleKeyboard,
+    },
+    context::{BotContext, Storage},
+    dice::{ActionRoll, DiceRoll, Outcome, Position, Stakes},
+    fair::{FairSession, Verification},
+    i18n::{Arg, Locale},
+    inline::format_roll,
+    messenger::{MessageGone, Messenger},
+    odds::format_odds,
+    poll::{poll_options, PollOption, SessionPoll},
+    scheduler::{due_parts, format_date_time, parse_date_time, parse_due},
+    settings::SettingChange,
+    stats::{by_dice, cursed, Roll, RollSource, Tally},
+    tracker::{
+        Amount, Dashboard, Destructive, ItemKind, ListView, PendingConfirm, PendingRename,
+        PlayersKeyboard, PlayersMsg, TimersMsg, Tracker,
     },
-    context::BotContext,
-    tracker::{PlayersKeyboard, PlayersMsg, TimersMsg, Tracker},
-    utils::{debug_err, Bot, MarkdownBot},
+    utils::{debug_err, unix_time},
 };
 use teloxide::{
-    payloads::SendMessageSetters,
     prelude::*,
-    types::{ParseMode, User},
-    utils::markdown::{self, escape},
+    types::{MessageId, UpdateKind, User, UserId},
+    utils::markdown,
 };
 
-pub struct BotHandler {
-    pub bot: Bot,
-    pub markdown_bot: MarkdownBot,
+/// How long confirm buttons work. Checked on a tap, and expired buttons are
+/// removed by the next update which stores the tracker.
+const CONFIRM_TIMEOUT_SECS: u64 = 60;
+/// How long before a chosen session the chat is reminded of it
+const SESSION_REMINDER_SECS: u64 = 60 * 60;
+/// Work left for after the chat is released, see [`BotHandler::run_deferred`]
+type Deferred = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+pub struct BotHandler<M: Messenger> {
+    pub bot: M,
     pub context: BotContext,
     pub chat_id: ChatId,
     pub from: User,
+    /// Whether deferred work may outlive the update, see
+    /// [`Storage::long_lived`]
+    long_lived: bool,
+    deferred: Mutex<Vec<Deferred>>,
 }
 
-impl BotHandler {
-    pub async fn new(bot: Bot, update: &Update) -> anyhow::Result<Self> {
-        let chat_id = update.chat().ok_or(anyhow!("Chat not found"))?.id;
+impl<M: Messenger> BotHandler<M> {
+    pub async fn new(bot: M, storage: &Storage, update: &Update) -> anyhow::Result<Self> {
+        let chat = update.chat().ok_or(anyhow!("Chat not found"))?;
+        let from = update
+            .from()
+            .ok_or(anyhow!("Cannot find \\'from\\' user"))?
+            .to_owned();
+        // Taps come from the same members, so messages are enough
+        if let UpdateKind::Message(_) = update.kind {
+            let title = chat.title().or(chat.first_name()).unwrap_or_default();
+            if let Err(err) = storage.add_member(from.id, chat.id, title).await {
+                warn!("Error remembering chat member: {:#}", err);
+            }
+        }
         Ok(Self {
-            bot: bot.clone(),
-            markdown_bot: bot.parse_mode(ParseMode::MarkdownV2),
-            context: BotContext::new(chat_id).await,
-            chat_id,
-            from: update
-                .from()
-                .ok_or(anyhow!("Cannot find \\'from\\' user"))?
-                .to_owned(),
+            bot,
+            context: storage.context(chat.id).await.with_update(update.id),
+            chat_id: chat.id,
+            from,
+            long_lived: storage.long_lived(),
+            deferred: Mutex::default(),
         })
     }
 
-    #[instrument(skip(self))]
-    pub async fn handle_wipe(&self, confirm: &str) -> anyhow::Result<()> {
-        match confirm {
-            "" => {
-                self.send_response("Are you sure? If so, do `/wipe yes`".to_owned())
-                    .await
-            }
-            "yes" => {
-                self.context.put(&Tracker::new()).await?;
-                self.send_response("*Wipe successful*".to_owned()).await?;
-                Ok(())
-            }
-            str => {
-                self.send_response(format!(
-                    "Only 'yes' is accepted as confirmation, but received {}",
-                    str
-                ))
-                .await
+    /// Acts for the bot itself rather than for a user, to fire timers
+    pub async fn scheduled(bot: M, storage: &Storage, chat_id: ChatId) -> Self {
+        let from = User {
+            id: UserId(0),
+            is_bot: true,
+            first_name: "Scheduler".to_owned(),
+            last_name: None,
+            username: Some("scheduler".to_owned()),
+            language_code: None,
+            is_premium: false,
+            added_to_attachment_menu: false,
+        };
+        Self {
+            bot,
+            context: storage.context(chat_id).await.resync_schedule(),
+            chat_id,
+            from,
+            long_lived: storage.long_lived(),
+            deferred: Mutex::default(),
+        }
+    }
+
+    /// Runs `work` once the update is handled and the chat released, for
+    /// waits which should not hold up other updates of the chat
+    fn defer(&self, work: impl Future<Output = ()> + Send + 'static) {
+        self.deferred.lock().unwrap().push(Box::pin(work));
+    }
+
+    /// Releases the chat, then runs the deferred work. Called when done with
+    /// the update. Spawned in a long-lived process, and awaited on Lambda,
+    /// which freezes the process once the response is sent.
+    pub async fn run_deferred(self) {
+        let Self {
+            context,
+            long_lived,
+            deferred,
+            ..
+        } = self;
+        drop(context);
+        for work in deferred.into_inner().unwrap() {
+            if long_lived {
+                tokio::spawn(work);
+            } else {
+                work.await;
             }
         }
     }
 
     #[instrument(skip(self))]
-    pub async fn handle_roll(&self, num: usize) -> anyhow::Result<()> {
+    pub async fn handle_roll(&self, num: usize, stakes: &str) -> anyhow::Result<()> {
         if num > 5 {
             bail!("Too many dice: {}", num);
         }
+        let stakes = Stakes::parse(stakes)?;
+        let mut tracker = self.context.get().await?;
+        let locale = self.locale(&tracker);
+        let mut values = Vec::new();
+        let mut last_msg_id = None;
         for _ in 0..num {
-            self.bot.send_dice(self.chat_id).await?;
+            let (msg_id, value) = self.bot.send_dice(self.chat_id).await?;
+            values.push(value);
+            last_msg_id = Some(msg_id);
         }
+        if let Some(msg_id) = last_msg_id {
+            let roll = ActionRoll {
+                pool: num as u32,
+                dice: values.clone(),
+            };
+            let text = format_action_roll(&locale, &roll, stakes);
+            let user = self.format_user();
+            self.ignore_errors(|| self.context.log(&user, &text)).await;
+            let text = self.by_user(&locale, text);
+            // Posted once the dice stop, not to spoil the animation. On
+            // Lambda that would hold up the response, inviting Telegram to
+            // redeliver the update, so the summary is posted right away.
+            let (bot, chat_id) = (self.bot.clone(), self.chat_id);
+            let animation = if self.long_lived {
+                bot.dice_animation()
+            } else {
+                Duration::ZERO
+            };
+            self.defer(async move {
+                tokio::time::sleep(animation).await;
+                if let Err(err) = bot.send_reply(chat_id, msg_id, text).await {
+                    let err = err.context("Error posting roll summary");
+                    warn!("{:#}", err);
+                    debug_err(&err).await;
+                }
+            });
+        }
+        self.record_roll(&mut tracker, 6, values, RollSource::Telegram)
+            .await
+    }
+
+    /// Rolls dice such as `3d6` or `4d6kh3` with the bot's own RNG, or from
+    /// the seed of the running fair session
+    #[instrument(skip(self))]
+    pub async fn handle_dice_roll(&self, dice: &str) -> anyhow::Result<()> {
+        let dice = dice.parse::<DiceRoll>()?;
+        let mut tracker = self.context.get().await?;
+        let locale = self.locale(&tracker);
+        let (values, fair_number) = match tracker.fair_session.as_mut() {
+            Some(session) => (session.roll(&dice)?, Some(session.rolls.len())),
+            None => (dice.roll(&mut self.bot.roll_rng()), None),
+        };
+        let mut text = format_roll(&locale, &dice, &values);
+        if let Some(number) = fair_number {
+            text = locale.text(
+                "fair-roll",
+                &[
+                    ("roll", Arg::Markdown(text)),
+                    ("number", (number as i32).into()),
+                ],
+            );
+        }
+        // A fair roll is only shown once the session remembers it
+        self.record_roll(&mut tracker, dice.sides, values, RollSource::Bot)
+            .await?;
+        self.send_response(&locale, text).await
+    }
+
+    /// Starts a fair session publishing the commitment to its seed, or ends
+    /// it revealing the seed, see [`crate::fair`]
+    #[instrument(skip(self))]
+    pub async fn handle_session(&self, arg: &str) -> anyhow::Result<()> {
+        let mut tracker = self.context.get().await?;
+        let locale = self.locale(&tracker);
+        let text = match arg.trim() {
+            "start" => {
+                if tracker.fair_session.is_some() {
+                    bail!("A fair session is already running, end it with /session end");
+                }
+                let session = FairSession::start();
+                let text = locale.text(
+                    "fair-started",
+                    &[("commitment", (&session.commitment).into())],
+                );
+                tracker.fair_session = Some(session);
+                text
+            }
+            "end" => {
+                let session = tracker
+                    .fair_session
+                    .take()
+                    .ok_or(anyhow!("No fair session is running"))?;
+                let text = locale.text(
+                    "fair-ended",
+                    &[
+                        ("seed", (&session.seed).into()),
+                        ("commitment", (&session.commitment).into()),
+                        ("rolls", (session.rolls.len() as i32).into()),
+                    ],
+                );
+                tracker.revealed_session = Some(session);
+                text
+            }
+            other => bail!("Unknown argument {}, expected start or end", other),
+        };
+        // Stored before the commitment is published
+        self.save(&mut tracker).await?;
+        self.send_response(&locale, text).await
+    }
+
+    /// Checks the rolls of the last fair session against a revealed seed,
+    /// its own seed if none is given
+    #[instrument(skip(self))]
+    pub async fn handle_verify(&self, seed: &str) -> anyhow::Result<()> {
+        let tracker = self.context.get().await?;
+        let locale = self.locale(&tracker);
+        let session = tracker
+            .revealed_session
+            .as_ref()
+            .ok_or(anyhow!("No fair session has ended yet"))?;
+        let seed = if seed.trim().is_empty() {
+            &session.seed
+        } else {
+            seed
+        };
+        let commitment = ("commitment", (&session.commitment).into());
+        let text = match session.verify(seed)? {
+            Verification::WrongSeed => locale.text("fair-wrong-seed", &[commitment]),
+            Verification::Replayed(mismatches) if mismatches.is_empty() => locale.text(
+                "fair-verified",
+                &[commitment, ("rolls", (session.rolls.len() as i32).into())],
+            ),
+            Verification::Replayed(mismatches) => {
+                let mut text = locale.text("fair-mismatches", &[]);
+                for mismatch in mismatches {
+                    text.push('\n');
+                    text.push_str(&locale.text(
+                        "fair-mismatch",
+                        &[
+                            ("number", (mismatch.number as i32).into()),
+                            ("dice", (&mismatch.roll.dice).into()),
+                            ("values", (&join_values(&mismatch.roll.values)).into()),
+                            ("expected", (&join_values(&mismatch.expected)).into()),
+                        ],
+                    ));
+                }
+                text
+            }
+        };
+        self.bot.send_message(self.chat_id, text, None).await?;
+        Ok(())
+    }
+
+    /// Dice statistics of the session, or of the whole campaign with `all`
+    #[instrument(skip(self))]
+    pub async fn handle_stats(&self, arg: &str) -> anyhow::Result<()> {
+        let tracker = self.context.get().await?;
+        let locale = self.locale(&tracker);
+        let (title, tallies) = match arg.trim() {
+            "" => ("stats-session", &tracker.rolls.session),
+            "all" => ("stats-campaign", &tracker.rolls.campaign),
+            other => bail!("Unknown argument {}, expected nothing or all", other),
+        };
+        let text = format_stats(&locale, title, tallies);
+        self.bot.send_message(self.chat_id, text, None).await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn handle_odds(&self, query: &str) -> anyhow::Result<()> {
+        let text = format_odds(&self.chat_locale().await, query.parse()?)?;
+        self.bot.send_message(self.chat_id, text, None).await?;
         Ok(())
     }
 
     #[instrument(skip(self))]
     pub async fn handle_create_player(&self, name: &str) -> anyhow::Result<()> {
         let mut tracker = self.context.get().await?;
+        let locale = self.locale(&tracker);
         let name = name.trim();
         if name.is_empty() {
-            self.markdown_bot
-                .send_message(self.chat_id, "Player name is required")
+            self.bot
+                .send_message(self.chat_id, locale.text("player-name-required", &[]), None)
                 .await?;
             return Ok(());
         }
         tracker.create_player(name)?;
-        self.ignore_errors(|| self.update_players(&tracker, true))
+        self.refresh_list(&mut tracker, ItemKind::Player, true)
             .await;
-        self.send_response(format!("Player *{}* added", escape(name)))
-            .await?;
-        self.context.put(&tracker).await
+        self.send_response(
+            &locale,
+            locale.text("player-added", &[("name", name.into())]),
+        )
+        .await?;
+        self.save(&mut tracker).await
     }
 
     #[instrument(skip(self))]
-    pub async fn handle_create_timer(&self, name: &str, start_val: u16) -> anyhow::Result<()> {
+    pub async fn handle_create_timer(
+        &self,
+        name: &str,
+        start_val: Option<u16>,
+    ) -> anyhow::Result<()> {
         let mut tracker = self.context.get().await?;
+        let locale = self.locale(&tracker);
         let name = name.trim();
         if name.is_empty() {
-            self.markdown_bot
-                .send_message(self.chat_id, "Timer name is required")
+            self.bot
+                .send_message(self.chat_id, locale.text("timer-name-required", &[]), None)
                 .await?;
             return Ok(());
         }
+        let start_val = start_val.unwrap_or(tracker.settings.clock_size);
         tracker.create_timer(name, start_val.into())?;
-        self.ignore_errors(|| self.update_timers(&tracker, true))
+        self.refresh_list(&mut tracker, ItemKind::Timer, true).await;
+        self.send_response(
+            &locale,
+            locale.text("timer-added", &[("name", name.into())]),
+        )
+        .await?;
+        self.save(&mut tracker).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn handle_rename(
+        &self,
+        kind: ItemKind,
+        name: &str,
+        new_name: &str,
+    ) -> anyhow::Result<()> {
+        let tracker = self.context.get().await?;
+        let id = match kind {
+            ItemKind::Player => tracker.player_id(name)?,
+            ItemKind::Timer => tracker.timer_id(name)?,
+        };
+        self.rename(tracker, kind, id, new_name).await
+    }
+
+    /// Finishes a rename started from a keyboard, other replies are ignored
+    #[instrument(skip(self))]
+    pub async fn handle_reply(&self, reply_to: MessageId, text: &str) -> anyhow::Result<()> {
+        let mut tracker = self.context.get().await?;
+        let Some(pending) = tracker
+            .pending_rename
+            .take_if(|pending| pending.prompt_id == reply_to)
+        else {
+            return Ok(());
+        };
+        self.rename(tracker, pending.kind, pending.id, text).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn handle_schedule_timer(&self, name: &str, when: &str) -> anyhow::Result<()> {
+        let mut tracker = self.context.get().await?;
+        let locale = self.locale(&tracker);
+        let now = unix_time();
+        let due_at = parse_due(when, now, tracker.settings.utc_offset)?;
+        let timer = tracker.schedule_timer(name, due_at)?;
+        let when = format_due(&locale, &tracker, due_at, now);
+        self.send_response(
+            &locale,
+            locale.text(
+                "timer-scheduled",
+                &[("name", (&timer.name).into()), ("when", (&when).into())],
+            ),
+        )
+        .await?;
+        self.refresh_list(&mut tracker, ItemKind::Timer, true).await;
+        self.save(&mut tracker).await
+    }
+
+    /// Announces real-time timers which are due at `now`
+    #[instrument(skip(self))]
+    pub async fn handle_due_timers(&self, now: u64) -> anyhow::Result<()> {
+        let mut tracker = self.context.get().await?;
+        let locale = self.locale(&tracker);
+        let fired = tracker.fire_due_timers(now);
+        self.refresh_list(&mut tracker, ItemKind::Timer, true).await;
+        // Before announcing, so that a failed message does not make the
+        // timers fire again. Also when nothing fired, to correct the schedule
+        // index.
+        self.save(&mut tracker).await?;
+        for timer in fired {
+            let text = locale.text("timer-due", &[("name", (&timer.name).into())]);
+            let user = self.format_user();
+            self.ignore_errors(|| self.context.log(&user, &text)).await;
+            self.ignore_errors(|| async {
+                self.bot
+                    .send_message(self.chat_id, text.clone(), None)
+                    .await?;
+                Ok(())
+            })
             .await;
-        self.send_response(format!("Timer *{}* added", escape(name)))
+        }
+        Ok(())
+    }
+
+    /// Posts a poll on which of the proposed dates suit everyone, replacing
+    /// the open one
+    #[instrument(skip(self))]
+    pub async fn handle_schedule(&self, dates: &str) -> anyhow::Result<()> {
+        let mut tracker = self.context.get().await?;
+        let locale = self.locale(&tracker);
+        let utc_offset = tracker.settings.utc_offset;
+        let starts = dates
+            .split([';', '\n'])
+            .filter(|date| !date.trim().is_empty())
+            .map(|date| parse_date_time(date, utc_offset))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if let Some(start) = starts.iter().find(|start| **start <= unix_time()) {
+            bail!(
+                "{} has already passed",
+                format_date_time(*start, utc_offset)
+            );
+        }
+        let options = poll_options(starts)?;
+        // Buttons of the replaced poll are answered as outdated
+        if let Some(previous) = tracker.session_poll.take() {
+            let text = format_poll(&locale, &tracker, &previous.options, false);
+            self.ignore_errors(|| {
+                self.bot
+                    .edit_message_text(self.chat_id, previous.msg_id, text.clone(), None)
+            })
+            .await;
+        }
+
+        let id = rand::random();
+        let text = format_poll(&locale, &tracker, &options, true);
+        let user = self.format_user();
+        self.ignore_errors(|| self.context.log(&user, &text)).await;
+        let keyboard = make_poll_keyboard(&locale, self.chat_id, id, &options, utc_offset);
+        let msg_id = self
+            .bot
+            .send_message(self.chat_id, text, Some(keyboard))
             .await?;
-        self.context.put(&tracker).await
+        tracker.session_poll = Some(SessionPoll {
+            id,
+            msg_id,
+            options,
+        });
+        self.save(&mut tracker).await
+    }
+
+    /// Marks the current user as able to come on a proposed date, or takes
+    /// that back
+    #[instrument(skip(self))]
+    pub async fn handle_vote(&self, poll_id: u32, option: usize) -> anyhow::Result<Option<String>> {
+        let mut tracker = self.context.get().await?;
+        let locale = self.locale(&tracker);
+        let utc_offset = tracker.settings.utc_offset;
+        let name = self.player_name(&tracker);
+        let Some(poll) = tracker
+            .session_poll
+            .as_mut()
+            .filter(|poll| poll.id == poll_id)
+        else {
+            return Err(StaleKeyboard.into());
+        };
+        let added = poll.toggle_vote(option, self.from.id, &name)?;
+        let date = format_date_time(poll.options[option].starts_at, utc_offset);
+        let poll = poll.clone();
+
+        let text = format_poll(&locale, &tracker, &poll.options, true);
+        let keyboard =
+            make_poll_keyboard(&locale, self.chat_id, poll.id, &poll.options, utc_offset);
+        self.bot
+            .edit_message_text(self.chat_id, poll.msg_id, text, Some(keyboard))
+            .await?;
+        self.save(&mut tracker).await?;
+        let key = if added { "vote-added" } else { "vote-removed" };
+        Ok(Some(locale.plain(key, &[("date", (&date).into())])))
+    }
+
+    /// Announces the date most can come to, and adds a real-time timer to
+    /// remind the chat of it
+    #[instrument(skip(self))]
+    pub async fn handle_close_poll(&self, poll_id: u32) -> anyhow::Result<()> {
+        let mut tracker = self.context.get().await?;
+        let locale = self.locale(&tracker);
+        let Some(poll) = tracker.session_poll.take_if(|poll| poll.id == poll_id) else {
+            return Err(StaleKeyboard.into());
+        };
+        let text = format_poll(&locale, &tracker, &poll.options, false);
+        self.bot
+            .edit_message_text(self.chat_id, poll.msg_id, text, None)
+            .await?;
+
+        let announcement = match poll.chosen() {
+            Some(option) => {
+                let date = format_date_time(option.starts_at, tracker.settings.utc_offset);
+                let remind_at = option.starts_at.saturating_sub(SESSION_REMINDER_SECS);
+                let remind = remind_at > unix_time();
+                if remind {
+                    let name = locale.plain("session-reminder", &[("date", (&date).into())]);
+                    tracker.schedule_timer(&name, remind_at)?;
+                    self.refresh_list(&mut tracker, ItemKind::Timer, true).await;
+                }
+                let names = join_votes(option);
+                locale.text(
+                    "session-chosen",
+                    &[
+                        ("date", (&date).into()),
+                        ("names", (&names).into()),
+                        ("reminder", remind.into()),
+                    ],
+                )
+            }
+            None => locale.text("session-none", &[]),
+        };
+        self.send_response(&locale, announcement).await?;
+        self.save(&mut tracker).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn handle_edit_timer(
+        &self,
+        name: &str,
+        value: i32,
+        max: Option<i32>,
+    ) -> anyhow::Result<()> {
+        let mut tracker = self.context.get().await?;
+        let locale = self.locale(&tracker);
+        let timer = tracker.edit_timer(name, value, max)?;
+        self.send_response(
+            &locale,
+            locale.text(
+                "timer-edited",
+                &[
+                    ("name", (&timer.name).into()),
+                    ("value", timer.value.into()),
+                ],
+            ),
+        )
+        .await?;
+        self.refresh_list(&mut tracker, ItemKind::Timer, false)
+            .await;
+        self.save(&mut tracker).await
     }
 
     #[instrument(skip(self))]
-    pub async fn handle_list_players(&self) -> anyhow::Result<()> {
+    pub async fn handle_list_players(&self, filter: &str) -> anyhow::Result<()> {
         let mut tracker = self.context.get().await?;
+        let locale = self.locale(&tracker);
         if let Some(last_msg) = &tracker.players_msg {
             self.ignore_errors(|| async {
                 self.bot
@@ -126,27 +590,34 @@ impl BotHandler {
             })
             .await;
         }
-        let msg = self
-            .markdown_bot
-            .send_message(self.chat_id, self.format_players_msg(&tracker))
+        let text = format_players_msg(&locale, &tracker);
+        let msg_id = self
+            .bot
+            .send_message(self.chat_id, text.clone(), None)
             .await?;
-        let kb = self
-            .markdown_bot
-            .send_message(self.chat_id, "*Manage:*")
-            .reply_markup(make_players_keyboard())
+        let kb_id = self
+            .bot
+            .send_message(
+                self.chat_id,
+                locale.text("manage", &[]),
+                Some(make_players_keyboard(&locale, self.chat_id)),
+            )
             .await?;
 
         tracker.players_msg = Some(PlayersMsg {
-            msg_id: msg.id,
-            kb_id: kb.id,
+            msg_id,
+            kb_id,
             active_keyboard: PlayersKeyboard::None,
+            view: ListView::new(filter),
+            text,
         });
-        self.context.put(&tracker).await
+        self.save(&mut tracker).await
     }
 
     #[instrument(skip(self))]
-    pub async fn handle_list_timers(&self) -> anyhow::Result<()> {
+    pub async fn handle_list_timers(&self, filter: &str) -> anyhow::Result<()> {
         let mut tracker = self.context.get().await?;
+        let locale = self.locale(&tracker);
         if let Some(timers_msg) = &tracker.timers_msg {
             self.ignore_errors(|| async {
                 self.bot
@@ -159,109 +630,310 @@ impl BotHandler {
             })
             .await;
         }
-        let msg = self
-            .markdown_bot
-            .send_message(self.chat_id, self.format_timers_msg(&tracker))
+        let view = ListView::new(filter);
+        let text = format_timers_msg(&locale, &tracker);
+        let msg_id = self
+            .bot
+            .send_message(self.chat_id, text.clone(), None)
             .await?;
-        let kb = self
-            .markdown_bot
-            .send_message(self.chat_id, "*Manage:*")
-            .reply_markup(make_manage_timers_keyboard(&tracker.timers))
+        let kb_id = self
+            .bot
+            .send_message(
+                self.chat_id,
+                locale.text("manage", &[]),
+                Some(make_manage_timers_keyboard(
+                    &locale,
+                    self.chat_id,
+                    &tracker,
+                    &view,
+                )),
+            )
             .await?;
 
         tracker.timers_msg = Some(TimersMsg {
-            msg_id: msg.id,
-            kb_id: kb.id,
+            msg_id,
+            kb_id,
             keyboard_active: true,
+            view,
+            text,
         });
+        self.save(&mut tracker).await
+    }
+
+    /// Posts and pins the dashboard, replacing the previous one. With `off`,
+    /// the dashboard is unpinned and no longer updated.
+    #[instrument(skip(self))]
+    pub async fn handle_dashboard(&self, arg: &str) -> anyhow::Result<()> {
+        let off = match arg.trim() {
+            "" => false,
+            "off" => true,
+            arg => bail!("Unknown dashboard option: {}", arg),
+        };
+        let mut tracker = self.context.get().await?;
+        let locale = self.locale(&tracker);
+        if let Some(old) = tracker.dashboard.take() {
+            self.ignore_errors(|| self.bot.unpin_message(self.chat_id, old.msg_id))
+                .await;
+        }
+        if off {
+            self.send_response(&locale, locale.text("dashboard-off", &[]))
+                .await?;
+        } else {
+            self.post_dashboard(&mut tracker).await?;
+        }
         self.context.put(&tracker).await
     }
 
     #[instrument(skip(self))]
-    pub async fn handle_change_harm(&self, id: usize, val: i32) -> anyhow::Result<()> {
+    pub async fn handle_settings(&self) -> anyhow::Result<()> {
+        let tracker = self.context.get().await?;
+        let locale = self.locale(&tracker);
+        self.bot
+            .send_message(
+                self.chat_id,
+                locale.text("settings", &[]),
+                Some(make_settings_keyboard(
+                    &locale,
+                    self.chat_id,
+                    &tracker.settings,
+                )),
+            )
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn handle_toggle_announcements(&self) -> anyhow::Result<()> {
+        let mut tracker = self.context.get().await?;
+        let settings = &mut tracker.settings;
+        settings.announce_changes = !settings.announce_changes;
+        let key = if settings.announce_changes {
+            "announce-on"
+        } else {
+            "announce-off"
+        };
+        let locale = self.locale(&tracker);
+        self.send_response(&locale, locale.text(key, &[])).await?;
+        self.save(&mut tracker).await
+    }
+
+    // Handlers below are called from buttons and return a short plain text
+    // notification to show to the user who pressed the button
+    #[instrument(skip(self))]
+    pub async fn handle_change_harm(
+        &self,
+        id: usize,
+        revision: u32,
+        amount: Amount,
+    ) -> anyhow::Result<Option<String>> {
         let mut tracker = self.context.get().await?;
+        StaleKeyboard::check(revision, tracker.players_revision)?;
+        let locale = self.locale(&tracker);
 
+        let val = amount.delta(tracker.get_player(id)?.harm)?;
         let player = tracker.change_harm(id, val)?;
-        self.send_response(format!(
-            "Player *{}* has *{}* harm",
-            escape(&player.name),
-            escape(&player.harm.to_string())
-        ))
-        .await?;
-        self.ignore_errors(|| self.update_players(&tracker, false))
+        let args = [
+            ("name", (&player.name).into()),
+            ("harm", player.harm.into()),
+        ];
+        self.announce_change(&tracker, locale.text("player-harm", &args))
+            .await?;
+        // A value set on the keypad brings back the list
+        let update_kb = matches!(amount, Amount::To(_));
+        self.refresh_list(&mut tracker, ItemKind::Player, update_kb)
             .await;
-        self.context.put(&tracker).await
+        self.save(&mut tracker).await?;
+        Ok(Some(locale.plain("player-harm-toast", &args)))
     }
 
     #[instrument(skip(self))]
-    pub async fn handle_change_stress(&self, id: usize, val: i32) -> anyhow::Result<()> {
+    pub async fn handle_change_stress(
+        &self,
+        id: usize,
+        revision: u32,
+        amount: Amount,
+    ) -> anyhow::Result<Option<String>> {
         let mut tracker = self.context.get().await?;
+        StaleKeyboard::check(revision, tracker.players_revision)?;
+        let locale = self.locale(&tracker);
 
+        let val = amount.delta(tracker.get_player(id)?.stress)?;
         let player = tracker.change_stress(id, val)?;
-        self.send_response(format!(
-            "Player *{}* has *{}* stress",
-            escape(&player.name),
-            escape(&player.stress.to_string())
-        ))
-        .await?;
-        self.ignore_errors(|| self.update_players(&tracker, false))
+        let args = [
+            ("name", (&player.name).into()),
+            ("stress", player.stress.into()),
+        ];
+        self.announce_change(&tracker, locale.text("player-stress", &args))
+            .await?;
+        let update_kb = matches!(amount, Amount::To(_));
+        self.refresh_list(&mut tracker, ItemKind::Player, update_kb)
             .await;
-        self.context.put(&tracker).await
+        self.save(&mut tracker).await?;
+        Ok(Some(locale.plain("player-stress-toast", &args)))
     }
 
     #[instrument(skip(self))]
-    pub async fn handle_change_timer(&self, id: usize, val: i32) -> anyhow::Result<()> {
+    pub async fn handle_change_timer(
+        &self,
+        id: usize,
+        revision: u32,
+        amount: Amount,
+    ) -> anyhow::Result<Option<String>> {
         let mut tracker = self.context.get().await?;
+        StaleKeyboard::check(revision, tracker.timers_revision)?;
+        let locale = self.locale(&tracker);
 
+        let old_value = tracker.get_timer(id)?.value;
+        let val = amount.delta(old_value)?;
         let timer = tracker.change_timer(id, val)?;
-        if timer.value <= 0 {
-            tracker.delete_timer(id)?;
-            self.send_response(format!("Timer *{}* has fired\\!", escape(&timer.name)))
+        let args = [
+            ("name", (&timer.name).into()),
+            ("value", timer.value.into()),
+        ];
+        // A timer left at zero fires only once
+        let toast = if timer.value == 0 && old_value > 0 {
+            if tracker.settings.delete_fired_timers {
+                tracker.delete_timer(id)?;
+            }
+            // Always announced: the whole table needs to know
+            self.send_response(&locale, locale.text("timer-fired", &args))
                 .await?;
-            self.ignore_errors(|| self.update_timers(&tracker, true))
-                .await;
+            self.refresh_list(&mut tracker, ItemKind::Timer, true).await;
+            locale.plain("timer-fired-toast", &args)
         } else {
-            self.send_response(format!(
-                "Timer *{}* has *{}* ticks left",
-                escape(&timer.name),
-                escape(&timer.value.to_string())
-            ))
-            .await?;
-            self.ignore_errors(|| self.update_timers(&tracker, false))
+            self.announce_change(&tracker, locale.text("timer-ticks", &args))
+                .await?;
+            let update_kb = matches!(amount, Amount::To(_));
+            self.refresh_list(&mut tracker, ItemKind::Timer, update_kb)
+                .await;
+            locale.plain("timer-ticks-toast", &args)
+        };
+        self.save(&mut tracker).await?;
+        Ok(Some(toast))
+    }
+
+    /// Asks to confirm `action`, replacing the keyboard it was requested from.
+    /// A wipe is confirmed in a new message.
+    #[instrument(skip(self))]
+    pub async fn handle_request_confirm(
+        &self,
+        action: Destructive,
+        revision: u32,
+    ) -> anyhow::Result<()> {
+        let mut tracker = self.context.get().await?;
+        let revision = match action.kind() {
+            Some(kind) => {
+                StaleKeyboard::check(revision, tracker.revision(kind))?;
+                revision
+            }
+            None => 0,
+        };
+        let locale = self.locale(&tracker);
+        let question = match action {
+            Destructive::DeletePlayer(id) => locale.text(
+                "confirm-delete-player",
+                &[("name", (&tracker.get_player(id)?.name).into())],
+            ),
+            Destructive::DeleteTimer(id) => locale.text(
+                "confirm-delete-timer",
+                &[("name", (&tracker.get_timer(id)?.name).into())],
+            ),
+            Destructive::ResetStress => locale.text("confirm-reset-stress", &[]),
+            Destructive::Wipe => locale.text("confirm-wipe", &[]),
+        };
+        let timeout = [("minutes", (CONFIRM_TIMEOUT_SECS as u32 / 60).into())];
+        let question = format!("{question}\n{}", locale.text("confirm-timeout", &timeout));
+        // Only one confirmation is pending at a time
+        if let Some(previous) = tracker.pending_confirm.take() {
+            self.ignore_errors(|| self.close_confirm(&tracker, &previous))
                 .await;
         }
-        self.context.put(&tracker).await
+
+        let id = rand::random();
+        let keyboard = make_confirm_keyboard(&locale, self.chat_id, id);
+        let msg_id = match action.kind() {
+            Some(kind) => {
+                let kb_id = match kind {
+                    ItemKind::Player => tracker.players_msg.as_ref().map(|msg| msg.kb_id),
+                    ItemKind::Timer => tracker.timers_msg.as_ref().map(|msg| msg.kb_id),
+                }
+                .ok_or(anyhow!("Keyboard message not found"))?;
+                self.bot
+                    .edit_message_text(self.chat_id, kb_id, question, Some(keyboard))
+                    .await?;
+                kb_id
+            }
+            None => {
+                self.bot
+                    .send_message(self.chat_id, question, Some(keyboard))
+                    .await?
+            }
+        };
+        tracker.pending_confirm = Some(PendingConfirm {
+            id,
+            action,
+            revision,
+            msg_id,
+            expires_at: unix_time() + CONFIRM_TIMEOUT_SECS,
+        });
+        self.save(&mut tracker).await
     }
 
+    /// Makes or drops the pending change. Confirmed changes are always
+    /// announced, so that everyone sees who confirmed them.
     #[instrument(skip(self))]
-    pub async fn handle_delete_player(&self, id: usize) -> anyhow::Result<()> {
+    pub async fn handle_confirm(
+        &self,
+        confirm_id: u32,
+        confirmed: bool,
+    ) -> anyhow::Result<Option<String>> {
         let mut tracker = self.context.get().await?;
-        let timer = tracker.delete_player(id)?;
-        self.send_response(format!(
-            "Player *{}* with *{}* harm and *{}* stress has been removed",
-            escape(&timer.name),
-            escape(&timer.harm.to_string()),
-            escape(&timer.stress.to_string())
-        ))
-        .await?;
-        self.ignore_errors(|| self.update_players(&tracker, true))
-            .await;
-        self.context.put(&tracker).await
+        let locale = self.locale(&tracker);
+        let Some(pending) = tracker
+            .pending_confirm
+            .take_if(|pending| pending.id == confirm_id)
+        else {
+            return Err(StaleKeyboard.into());
+        };
+        let outdated = pending
+            .action
+            .kind()
+            .is_some_and(|kind| tracker.revision(kind) != pending.revision);
+        // Answered here rather than with a StaleKeyboard error, as the
+        // closed confirmation has to be saved
+        let toast = if outdated {
+            self.close_confirm(&tracker, &pending).await?;
+            locale.plain("keyboard-outdated", &[])
+        } else if unix_time() > pending.expires_at {
+            self.close_confirm(&tracker, &pending).await?;
+            locale.plain("confirm-expired", &[])
+        } else if !confirmed {
+            self.close_confirm(&tracker, &pending).await?;
+            locale.plain("cancelled-toast", &[])
+        } else {
+            self.apply_confirmed(&mut tracker, &pending).await?
+        };
+        self.save(&mut tracker).await?;
+        Ok(Some(toast))
     }
 
     #[instrument(skip(self))]
-    pub async fn handle_delete_timer(&self, id: usize) -> anyhow::Result<()> {
+    pub async fn handle_change_setting(
+        &self,
+        msg_id: MessageId,
+        change: SettingChange,
+    ) -> anyhow::Result<Option<String>> {
         let mut tracker = self.context.get().await?;
-        let timer = tracker.delete_timer(id)?;
-        self.send_response(format!(
-            "Timer *{}* with *{}* ticks has beed removed",
-            escape(&timer.name),
-            escape(&timer.value.to_string())
-        ))
-        .await?;
-        self.ignore_errors(|| self.update_timers(&tracker, true))
-            .await;
-        self.context.put(&tracker).await
+        tracker.settings.apply(change);
+        self.bot
+            .edit_message_reply_markup(
+                self.chat_id,
+                msg_id,
+                make_settings_keyboard(&self.locale(&tracker), self.chat_id, &tracker.settings),
+            )
+            .await?;
+        self.save(&mut tracker).await?;
+        Ok(None)
     }
 
     #[instrument(skip(self))]
@@ -269,9 +941,9 @@ impl BotHandler {
         let mut tracker = self.context.get().await?;
         if let Some(timers_msg) = tracker.timers_msg.as_mut() {
             timers_msg.keyboard_active = true;
-            self.update_timers_kb(&tracker).await?;
+            self.update_timers_kb(&tracker, false).await?;
         }
-        self.context.put(&tracker).await
+        self.save(&mut tracker).await
     }
 
     #[instrument(skip(self))]
@@ -279,9 +951,130 @@ impl BotHandler {
         let mut tracker = self.context.get().await?;
         if let Some(timers_msg) = tracker.timers_msg.as_mut() {
             timers_msg.keyboard_active = false;
-            self.update_timers_kb(&tracker).await?;
+            self.update_timers_kb(&tracker, false).await?;
         }
-        self.context.put(&tracker).await
+        self.save(&mut tracker).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn handle_toggle_pin(
+        &self,
+        kind: ItemKind,
+        id: usize,
+        revision: u32,
+    ) -> anyhow::Result<Option<String>> {
+        let mut tracker = self.context.get().await?;
+        StaleKeyboard::check(revision, tracker.revision(kind))?;
+        let locale = self.locale(&tracker);
+        let (name, pinned) = match kind {
+            ItemKind::Player => {
+                let player = tracker.toggle_pin_player(id)?;
+                (player.name, player.pinned)
+            }
+            ItemKind::Timer => {
+                let timer = tracker.toggle_pin_timer(id)?;
+                (timer.name, timer.pinned)
+            }
+        };
+        self.refresh_list(&mut tracker, kind, true).await;
+        self.save(&mut tracker).await?;
+        let key = if pinned {
+            "pinned-toast"
+        } else {
+            "unpinned-toast"
+        };
+        Ok(Some(locale.plain(key, &[("name", (&name).into())])))
+    }
+
+    /// Asks the user for a new name, see [`Self::handle_reply`]
+    #[instrument(skip(self))]
+    pub async fn handle_start_rename(
+        &self,
+        kind: ItemKind,
+        id: usize,
+        revision: u32,
+    ) -> anyhow::Result<()> {
+        let mut tracker = self.context.get().await?;
+        StaleKeyboard::check(revision, tracker.revision(kind))?;
+        let locale = self.locale(&tracker);
+        let name = match kind {
+            ItemKind::Player => tracker.get_player(id)?.name.clone(),
+            ItemKind::Timer => tracker.get_timer(id)?.name.clone(),
+        };
+        let prompt = locale.text(
+            "rename-prompt",
+            &[
+                (
+                    "user",
+                    Arg::Markdown(markdown::user_mention_or_link(&self.from)),
+                ),
+                ("name", (&name).into()),
+            ],
+        );
+        let prompt_id = self.bot.send_force_reply(self.chat_id, prompt).await?;
+        tracker.pending_rename = Some(PendingRename {
+            prompt_id,
+            kind,
+            id,
+        });
+        // Back from the keypad
+        self.refresh_list(&mut tracker, kind, true).await;
+        self.save(&mut tracker).await
+    }
+
+    /// Replaces the player or timer list with a keypad for a new value
+    #[instrument(skip(self))]
+    pub async fn handle_keypad(
+        &self,
+        target: KeypadTarget,
+        id: usize,
+        revision: u32,
+        entered: i32,
+    ) -> anyhow::Result<()> {
+        let tracker = self.context.get().await?;
+        let (kb_id, current) = match target {
+            KeypadTarget::Harm | KeypadTarget::Stress => (
+                tracker.players_msg.as_ref().map(|msg| msg.kb_id),
+                tracker.players_revision,
+            ),
+            KeypadTarget::Timer => (
+                tracker.timers_msg.as_ref().map(|msg| msg.kb_id),
+                tracker.timers_revision,
+            ),
+        };
+        StaleKeyboard::check(revision, current)?;
+        let kb_id = kb_id.ok_or(anyhow!("Keyboard message not found"))?;
+        let keypad = make_keypad_keyboard(
+            &self.locale(&tracker),
+            self.chat_id,
+            &tracker,
+            target,
+            id,
+            entered,
+        )?;
+        self.bot
+            .edit_message_reply_markup(self.chat_id, kb_id, keypad)
+            .await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn handle_timers_page(&self, page: usize) -> anyhow::Result<()> {
+        let mut tracker = self.context.get().await?;
+        if let Some(timers_msg) = tracker.timers_msg.as_mut() {
+            timers_msg.view.page = page;
+            self.update_timers_kb(&tracker, false).await?;
+        }
+        self.save(&mut tracker).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn handle_players_page(&self, page: usize) -> anyhow::Result<()> {
+        let mut tracker = self.context.get().await?;
+        if let Some(players_msg) = tracker.players_msg.as_mut() {
+            players_msg.view.page = page;
+            self.update_players_kb(&tracker, false).await?;
+        }
+        self.save(&mut tracker).await
     }
 
     #[instrument(skip(self))]
@@ -291,7 +1084,7 @@ impl BotHandler {
             players_msg.active_keyboard = PlayersKeyboard::ManagePlayers;
             self.update_players_kb(&tracker, true).await?;
         }
-        self.context.put(&tracker).await
+        self.save(&mut tracker).await
     }
 
     #[instrument(skip(self))]
@@ -301,7 +1094,7 @@ impl BotHandler {
             players_msg.active_keyboard = PlayersKeyboard::Harm;
             self.update_players_kb(&tracker, true).await?;
         }
-        self.context.put(&tracker).await
+        self.save(&mut tracker).await
     }
 
     #[instrument(skip(self))]
@@ -311,7 +1104,7 @@ impl BotHandler {
             players_msg.active_keyboard = PlayersKeyboard::Stress;
             self.update_players_kb(&tracker, true).await?;
         }
-        self.context.put(&tracker).await
+        self.save(&mut tracker).await
     }
 
     #[instrument(skip(self))]
@@ -322,20 +1115,272 @@ impl BotHandler {
             self.update_players_kb(&tracker, true).await?;
         }
 
-        self.context.put(&tracker).await
+        self.save(&mut tracker).await
+    }
+
+    async fn apply_confirmed(
+        &self,
+        tracker: &mut Tracker,
+        pending: &PendingConfirm,
+    ) -> anyhow::Result<String> {
+        let locale = self.locale(tracker);
+        let toast = match pending.action {
+            Destructive::DeletePlayer(id) => {
+                let player = tracker.delete_player(id)?;
+                let args = [
+                    ("name", (&player.name).into()),
+                    ("harm", player.harm.into()),
+                    ("stress", player.stress.into()),
+                ];
+                self.send_response(&locale, locale.text("player-removed", &args))
+                    .await?;
+                locale.plain("removed-toast", &args)
+            }
+            Destructive::DeleteTimer(id) => {
+                let timer = tracker.delete_timer(id)?;
+                let args = [
+                    ("name", (&timer.name).into()),
+                    ("value", timer.value.into()),
+                ];
+                self.send_response(&locale, locale.text("timer-removed", &args))
+                    .await?;
+                locale.plain("removed-toast", &args)
+            }
+            Destructive::ResetStress => {
+                tracker.reset_stress();
+                self.send_response(&locale, locale.text("stress-reset", &[]))
+                    .await?;
+                locale.plain("stress-reset", &[])
+            }
+            Destructive::Wipe => {
+                tracker.wipe();
+                // The question is replaced by the outcome
+                let text = locale.text("wipe-done", &[]);
+                let user = self.format_user();
+                self.ignore_errors(|| self.context.log(&user, &text)).await;
+                self.bot
+                    .edit_message_text(
+                        self.chat_id,
+                        pending.msg_id,
+                        self.by_user(&locale, text),
+                        None,
+                    )
+                    .await?;
+                return Ok(locale.plain("wipe-done", &[]));
+            }
+        };
+        self.close_confirm(tracker, pending).await?;
+        if let Some(kind) = pending.action.kind() {
+            self.refresh_list(tracker, kind, false).await;
+        }
+        Ok(toast)
+    }
+
+    /// Puts back the keyboard replaced by the confirm buttons. The question
+    /// to wipe has no keyboard to go back to and is marked as cancelled.
+    async fn close_confirm(
+        &self,
+        tracker: &Tracker,
+        pending: &PendingConfirm,
+    ) -> anyhow::Result<()> {
+        match pending.action.kind() {
+            Some(ItemKind::Player) => self.update_players_kb(tracker, true).await,
+            Some(ItemKind::Timer) => self.update_timers_kb(tracker, true).await,
+            None => {
+                let text = self.locale(tracker).text("wipe-cancelled", &[]);
+                self.bot
+                    .edit_message_text(self.chat_id, pending.msg_id, text, None)
+                    .await
+            }
+        }
+    }
+
+    async fn rename(
+        &self,
+        mut tracker: Tracker,
+        kind: ItemKind,
+        id: usize,
+        new_name: &str,
+    ) -> anyhow::Result<()> {
+        let locale = self.locale(&tracker);
+        let new_name = new_name.trim();
+        let (key, old) = match kind {
+            ItemKind::Player => ("player-renamed", tracker.rename_player(id, new_name)?),
+            ItemKind::Timer => ("timer-renamed", tracker.rename_timer(id, new_name)?),
+        };
+        let args = [("old", (&old).into()), ("name", new_name.into())];
+        self.send_response(&locale, locale.text(key, &args)).await?;
+        self.refresh_list(&mut tracker, kind, true).await;
+        self.save(&mut tracker).await
+    }
+
+    /// Adds dice rolled by the current user to the chat's roll history and
+    /// stores the tracker
+    async fn record_roll(
+        &self,
+        tracker: &mut Tracker,
+        sides: u32,
+        values: Vec<u32>,
+        source: RollSource,
+    ) -> anyhow::Result<()> {
+        let name = self.player_name(tracker);
+        let roll = Roll {
+            user_id: self.from.id,
+            sides,
+            values,
+            at: unix_time(),
+            source,
+        };
+        tracker.rolls.record(&name, roll);
+        self.save(tracker).await
+    }
+
+    /// Stores the tracker after bringing the dashboard up to date and
+    /// closing an expired confirmation
+    async fn save(&self, tracker: &mut Tracker) -> anyhow::Result<()> {
+        let expired = tracker
+            .pending_confirm
+            .take_if(|pending| unix_time() > pending.expires_at);
+        if let Some(pending) = expired {
+            self.ignore_errors(|| self.close_confirm(tracker, &pending))
+                .await;
+        }
+        if let Err(err) = self.refresh_dashboard(tracker).await {
+            warn!("Error updating dashboard: {:#}", err);
+        }
+        self.context.put(tracker).await
+    }
+
+    /// Edits the dashboard if its text changed, and posts it again if it
+    /// was deleted
+    async fn refresh_dashboard(&self, tracker: &mut Tracker) -> anyhow::Result<()> {
+        let Some(dashboard) = tracker.dashboard.as_ref() else {
+            return Ok(());
+        };
+        let text = format_dashboard(&self.locale(tracker), tracker);
+        if dashboard.text == text {
+            return Ok(());
+        }
+        match self
+            .bot
+            .edit_message_text(self.chat_id, dashboard.msg_id, text.clone(), None)
+            .await
+        {
+            Ok(()) => {
+                tracker.dashboard = Some(Dashboard {
+                    msg_id: dashboard.msg_id,
+                    text,
+                });
+                Ok(())
+            }
+            Err(err) if err.is::<MessageGone>() => {
+                info!("Dashboard was deleted, posting it again");
+                self.post_dashboard(tracker).await
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn post_dashboard(&self, tracker: &mut Tracker) -> anyhow::Result<()> {
+        let text = format_dashboard(&self.locale(tracker), tracker);
+        let msg_id = self
+            .bot
+            .send_message(self.chat_id, text.clone(), None)
+            .await?;
+        // Pinning needs admin rights, the dashboard is updated either way
+        self.ignore_errors(|| self.bot.pin_message(self.chat_id, msg_id))
+            .await;
+        tracker.dashboard = Some(Dashboard { msg_id, text });
+        Ok(())
+    }
+
+    /// Updates the list message of players or timers, and its keyboard if
+    /// `update_kb` is set. A list whose messages were deleted is forgotten.
+    async fn refresh_list(&self, tracker: &mut Tracker, kind: ItemKind, update_kb: bool) {
+        let result = match kind {
+            ItemKind::Player => self.update_players(tracker, update_kb).await,
+            ItemKind::Timer => self.update_timers(tracker, update_kb).await,
+        };
+        match result {
+            Ok(()) => {}
+            Err(err) if err.is::<MessageGone>() => {
+                info!("{:?} list was deleted", kind);
+                match kind {
+                    ItemKind::Player => tracker.players_msg = None,
+                    ItemKind::Timer => tracker.timers_msg = None,
+                }
+            }
+            Err(err) => debug_err(&err).await,
+        }
+    }
+
+    /// Posts a change made with a button, unless the chat only wants popups.
+    /// The change is logged either way.
+    async fn announce_change(&self, tracker: &Tracker, text: String) -> anyhow::Result<()> {
+        if tracker.settings.announce_changes {
+            return self.send_response(&self.locale(tracker), text).await;
+        }
+        let user = self.format_user();
+        self.ignore_errors(|| self.context.log(&user, &text)).await;
+        Ok(())
     }
 
     #[instrument(skip(self))]
-    async fn send_response(&self, text: String) -> anyhow::Result<()> {
-        self.markdown_bot
-            .send_message(
-                self.chat_id,
-                format!("{} by {}", text, markdown::user_mention_or_link(&self.from)),
-            )
+    async fn send_response(&self, locale: &Locale, text: String) -> anyhow::Result<()> {
+        let user = self.format_user();
+        self.ignore_errors(|| self.context.log(&user, &text)).await;
+        self.bot
+            .send_message(self.chat_id, self.by_user(locale, text), None)
             .await?;
         Ok(())
     }
 
+    /// Signs `text` with a mention of the current user
+    fn by_user(&self, locale: &Locale, text: String) -> String {
+        let mention = markdown::user_mention_or_link(&self.from);
+        locale.text(
+            "by-user",
+            &[
+                ("text", Arg::Markdown(text)),
+                ("user", Arg::Markdown(mention)),
+            ],
+        )
+    }
+
+    /// Like [`Self::locale`], for replies made without a tracker at hand
+    pub async fn chat_locale(&self) -> Locale {
+        match self.context.get().await {
+            Ok(tracker) => self.locale(&tracker),
+            Err(_) => Locale::new(None, &self.from),
+        }
+    }
+
+    /// Language for replies: the chat language, or that of the current user
+    pub fn locale(&self, tracker: &Tracker) -> Locale {
+        Locale::new(tracker.settings.language, &self.from)
+    }
+
+    /// The tracked player named like the current user, or the user's name
+    fn player_name(&self, tracker: &Tracker) -> String {
+        let full_name = self.from.full_name();
+        let names = [
+            Some(full_name.as_str()),
+            Some(self.from.first_name.as_str()),
+            self.from.username.as_deref(),
+        ];
+        tracker
+            .players
+            .iter()
+            .find(|player| {
+                let player_name = player.name.to_lowercase();
+                names
+                    .iter()
+                    .flatten()
+                    .any(|name| name.to_lowercase() == player_name)
+            })
+            .map_or(full_name.clone(), |player| player.name.clone())
+    }
+
     pub fn format_user(&self) -> String {
         format!(
             "{}({})",
@@ -345,15 +1390,15 @@ impl BotHandler {
     }
 
     #[instrument(skip(self, tracker))]
-    async fn update_players(&self, tracker: &Tracker, update_kb: bool) -> anyhow::Result<()> {
-        if let Some(last_msg) = tracker.players_msg.as_ref() {
-            self.markdown_bot
-                .edit_message_text(
-                    self.chat_id,
-                    last_msg.msg_id,
-                    self.format_players_msg(tracker),
-                )
-                .await?;
+    async fn update_players(&self, tracker: &mut Tracker, update_kb: bool) -> anyhow::Result<()> {
+        let text = format_players_msg(&self.locale(tracker), tracker);
+        if let Some(last_msg) = tracker.players_msg.as_mut() {
+            if last_msg.text != text {
+                self.bot
+                    .edit_message_text(self.chat_id, last_msg.msg_id, text.clone(), None)
+                    .await?;
+                last_msg.text = text;
+            }
             if update_kb {
                 self.update_players_kb(tracker, false).await?;
             }
@@ -368,29 +1413,34 @@ impl BotHandler {
         update_message: bool,
     ) -> anyhow::Result<()> {
         if let Some(last_msg) = tracker.players_msg.as_ref() {
-            let (new_kb, manage_name) = match last_msg.active_keyboard {
-                PlayersKeyboard::Harm => (make_manage_harm_keyboard(&tracker.players), " harm"),
-                PlayersKeyboard::Stress => {
-                    (make_manage_stress_keyboard(&tracker.players), " stress")
-                }
-                PlayersKeyboard::ManagePlayers => {
-                    (make_manage_players_keyboard(&tracker.players), " players")
-                }
-                PlayersKeyboard::None => (make_players_keyboard(), ""),
+            let locale = self.locale(tracker);
+            let (new_kb, title) = match last_msg.active_keyboard {
+                PlayersKeyboard::Harm => (
+                    make_manage_harm_keyboard(&locale, self.chat_id, tracker, &last_msg.view),
+                    "manage-harm",
+                ),
+                PlayersKeyboard::Stress => (
+                    make_manage_stress_keyboard(&locale, self.chat_id, tracker, &last_msg.view),
+                    "manage-stress",
+                ),
+                PlayersKeyboard::ManagePlayers => (
+                    make_manage_players_keyboard(&locale, self.chat_id, tracker, &last_msg.view),
+                    "manage-players",
+                ),
+                PlayersKeyboard::None => (make_players_keyboard(&locale, self.chat_id), "manage"),
             };
             if update_message {
-                self.markdown_bot
+                self.bot
                     .edit_message_text(
                         self.chat_id,
                         last_msg.kb_id,
-                        format!("*Manage{manage_name}:*"),
+                        locale.text(title, &[]),
+                        Some(new_kb),
                     )
-                    .reply_markup(new_kb)
                     .await?;
             } else {
-                self.markdown_bot
-                    .edit_message_reply_markup(self.chat_id, last_msg.kb_id)
-                    .reply_markup(new_kb)
+                self.bot
+                    .edit_message_reply_markup(self.chat_id, last_msg.kb_id, new_kb)
                     .await?;
             }
         }
@@ -398,78 +1448,290 @@ impl BotHandler {
     }
 
     #[instrument(skip(self, tracker))]
-    async fn update_timers(&self, tracker: &Tracker, update_kb: bool) -> anyhow::Result<()> {
-        if let Some(last_msg) = tracker.timers_msg.as_ref() {
-            self.markdown_bot
-                .edit_message_text(
-                    self.chat_id,
-                    last_msg.msg_id,
-                    self.format_timers_msg(tracker),
-                )
-                .await?;
+    async fn update_timers(&self, tracker: &mut Tracker, update_kb: bool) -> anyhow::Result<()> {
+        let text = format_timers_msg(&self.locale(tracker), tracker);
+        if let Some(last_msg) = tracker.timers_msg.as_mut() {
+            if last_msg.text != text {
+                self.bot
+                    .edit_message_text(self.chat_id, last_msg.msg_id, text.clone(), None)
+                    .await?;
+                last_msg.text = text;
+            }
             if update_kb {
-                self.update_timers_kb(tracker).await?;
+                self.update_timers_kb(tracker, false).await?;
             }
         }
         Ok(())
     }
 
     #[instrument(skip(self, tracker))]
-    async fn update_timers_kb(&self, tracker: &Tracker) -> anyhow::Result<()> {
+    async fn update_timers_kb(
+        &self,
+        tracker: &Tracker,
+        update_message: bool,
+    ) -> anyhow::Result<()> {
         if let Some(last_msg) = tracker.timers_msg.as_ref() {
+            let locale = self.locale(tracker);
             let kb = if last_msg.keyboard_active {
-                make_manage_timers_keyboard(&tracker.timers)
+                make_manage_timers_keyboard(&locale, self.chat_id, tracker, &last_msg.view)
             } else {
-                make_timers_keyboard()
+                make_timers_keyboard(&locale, self.chat_id)
             };
-            self.markdown_bot
-                .edit_message_reply_markup(self.chat_id, last_msg.kb_id)
-                .reply_markup(kb)
-                .await?;
+            if update_message {
+                self.bot
+                    .edit_message_text(
+                        self.chat_id,
+                        last_msg.kb_id,
+                        locale.text("manage", &[]),
+                        Some(kb),
+                    )
+                    .await?;
+            } else {
+                self.bot
+                    .edit_message_reply_markup(self.chat_id, last_msg.kb_id, kb)
+                    .await?;
+            }
         }
         Ok(())
     }
 
-    fn format_players_msg(&self, tracker: &Tracker) -> String {
-        let mut out = String::new();
-        out.push_str("*Players:*\n\n");
-        for player in tracker.players.iter() {
-            out.push_str(
-                format!(
-                    "*{}*: *{}* harm, *{}* stress\n",
-                    escape(&player.name),
-                    escape(&player.harm.to_string()),
-                    escape(&player.stress.to_string())
-                )
-                .as_str(),
-            );
+    async fn ignore_errors<Fut, F>(&self, f: F)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = anyhow::Result<()>>,
+    {
+        match f().await {
+            Ok(()) => {}
+            // Deleted by a user, nothing to report
+            Err(err) if err.is::<MessageGone>() => info!("{}", err),
+            Err(err) => debug_err(&err).await,
         }
-        out
     }
+}
 
-    fn format_timers_msg(&self, tracker: &Tracker) -> String {
-        let mut out = String::new();
-        out.push_str("*Timers:*\n\n");
-        for timer in tracker.timers.iter() {
-            out.push_str(
-                format!(
-                    "*{}*: *{}* ticks left\n",
-                    escape(&timer.name),
-                    escape(&timer.value.to_string()),
-                )
-                .as_str(),
-            );
+fn format_players_msg(locale: &Locale, tracker: &Tracker) -> String {
+    let mut out = locale.text("players-title", &[]);
+    out.push_str("\n\n");
+    for player in tracker.players.iter() {
+        let args = [
+            ("name", (&player.name).into()),
+            ("harm", player.harm.into()),
+            ("stress", player.stress.into()),
+        ];
+        out.push_str(&locale.text("player-line", &args));
+        out.push('\n');
+    }
+    out
+}
+
+fn format_dashboard(locale: &Locale, tracker: &Tracker) -> String {
+    format!(
+        "{}\n{}",
+        format_players_msg(locale, tracker),
+        format_timers_msg(locale, tracker)
+    )
+}
+
+/// Who can come to each proposed date, and which tracked players have not
+/// answered yet
+fn format_poll(locale: &Locale, tracker: &Tracker, options: &[PollOption], open: bool) -> String {
+    let mut out = locale.text(if open { "poll-title" } else { "poll-closed" }, &[]);
+    out.push_str("\n\n");
+    for option in options {
+        let date = format_date_time(option.starts_at, tracker.settings.utc_offset);
+        let names = join_votes(option);
+        out.push_str(&locale.text(
+            "poll-option",
+            &[
+                ("date", (&date).into()),
+                ("count", (option.votes.len() as i32).into()),
+                ("names", (&names).into()),
+            ],
+        ));
+        out.push('\n');
+    }
+    let voted = options
+        .iter()
+        .flat_map(|option| &option.votes)
+        .map(|vote| vote.name.as_str())
+        .collect::<HashSet<_>>();
+    let waiting = tracker
+        .players
+        .iter()
+        .filter(|player| !voted.contains(player.name.as_str()))
+        .map(|player| player.name.as_str())
+        .collect::<Vec<_>>();
+    if open && !waiting.is_empty() {
+        let names = waiting.join(", ");
+        out.push('\n');
+        out.push_str(&locale.text("poll-waiting", &[("names", (&names).into())]));
+    }
+    out
+}
+
+fn join_values(values: &[u32]) -> String {
+    let values = values.iter().map(u32::to_string);
+    values.collect::<Vec<_>>().join(", ")
+}
+
+fn join_votes(option: &PollOption) -> String {
+    let names = option.votes.iter().map(|vote| vote.name.as_str());
+    names.collect::<Vec<_>>().join(", ")
+}
+
+/// The outcome of an action roll, and what it means in the given position
+fn format_action_roll(locale: &Locale, roll: &ActionRoll, stakes: Option<Stakes>) -> String {
+    let outcome = roll.outcome();
+    let outcome_text = locale.plain(outcome.key(), &[]);
+    let mut text = if outcome == Outcome::Critical {
+        let sixes = roll.dice.iter().filter(|die| **die == 6).count();
+        locale.text(
+            "roll-sixes",
+            &[
+                ("sixes", (sixes as i32).into()),
+                ("outcome", (&outcome_text).into()),
+            ],
+        )
+    } else {
+        locale.text(
+            "roll-highest",
+            &[
+                ("result", roll.result().into()),
+                ("outcome", (&outcome_text).into()),
+            ],
+        )
+    };
+    if let Some(stakes) = stakes {
+        text.push('\n');
+        text.push_str(&locale.text(
+            "roll-stakes",
+            &[
+                (
+                    "position",
+                    Arg::Text(locale.plain(stakes.position.key(), &[])),
+                ),
+                (
+                    "effect",
+                    Arg::Text(locale.plain(stakes.effect(outcome).key(), &[])),
+                ),
+                (
+                    "consequence",
+                    Arg::Text(locale.plain(stakes.consequence_key(outcome), &[])),
+                ),
+            ],
+        ));
+        if stakes.position == Position::Desperate {
+            text.push('\n');
+            text.push_str(&locale.text("roll-mark-xp", &[]));
         }
-        out
     }
+    text
+}
 
-    async fn ignore_errors<Fut, F>(&self, f: F)
-    where
-        F: Fn() -> Fut,
-        Fut: Future<Output = anyhow::Result<()>>,
-    {
-        if let Err(err) = f().await {
-            debug_err(&err).await;
+/// A line per user with the most dice first, then the cursed dice
+fn format_stats(locale: &Locale, title: &str, tallies: &BTreeMap<u64, Tally>) -> String {
+    let mut out = locale.text(title, &[]);
+    out.push_str("\n\n");
+    if tallies.is_empty() {
+        out.push_str(&locale.text("stats-none", &[]));
+        return out;
+    }
+    for tally in by_dice(tallies) {
+        let averages = tally
+            .by_sides
+            .iter()
+            .map(|(sides, die)| {
+                locale.text(
+                    "stats-average",
+                    &[
+                        ("average", (&format!("{:.2}", die.average())).into()),
+                        ("sides", (*sides).into()),
+                    ],
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        out.push_str(&locale.text(
+            "stats-line",
+            &[
+                ("name", (&tally.name).into()),
+                ("rolls", tally.rolls.into()),
+                ("dice", tally.dice.into()),
+                ("averages", Arg::Markdown(averages)),
+                ("luck", (&format!("{:.0}%", tally.luck() * 100.0)).into()),
+            ],
+        ));
+        if tally.action_rolls > 0 {
+            out.push_str(", ");
+            out.push_str(&locale.text(
+                "stats-crits",
+                &[
+                    ("crits", tally.action_crits.into()),
+                    ("action_rolls", tally.action_rolls.into()),
+                ],
+            ));
+        }
+        out.push('\n');
+    }
+    let cursed = cursed(tallies);
+    if !cursed.is_empty() {
+        out.push('\n');
+        out.push_str(&locale.text("stats-cursed", &[]));
+        for (place, tally) in cursed.iter().enumerate() {
+            out.push('\n');
+            out.push_str(&locale.text(
+                "stats-cursed-line",
+                &[
+                    ("place", (place as i32 + 1).into()),
+                    ("name", (&tally.name).into()),
+                    ("luck", (&format!("{:.0}%", tally.luck() * 100.0)).into()),
+                ],
+            ));
         }
     }
+    out
+}
+
+/// When a real-time timer is due, in the time zone of the chat
+fn format_due(locale: &Locale, tracker: &Tracker, due_at: u64, now: u64) -> String {
+    let (time, days) = due_parts(due_at, now, tracker.settings.utc_offset);
+    locale.plain(
+        "due-at",
+        &[("time", (&time).into()), ("days", (days as i32).into())],
+    )
+}
+
+pub fn format_timers_msg(locale: &Locale, tracker: &Tracker) -> String {
+    let mut out = locale.text("timers-title", &[]);
+    out.push_str("\n\n");
+    let now = unix_time();
+    for timer in tracker.timers.iter() {
+        let line = match (timer.due_at, timer.max) {
+            (Some(due_at), _) => locale.text(
+                "timer-line-due",
+                &[
+                    ("name", (&timer.name).into()),
+                    ("when", (&format_due(locale, tracker, due_at, now)).into()),
+                ],
+            ),
+            (None, Some(max)) => locale.text(
+                "timer-line-max",
+                &[
+                    ("name", (&timer.name).into()),
+                    ("value", timer.value.into()),
+                    ("max", max.into()),
+                ],
+            ),
+            (None, None) => locale.text(
+                "timer-line",
+                &[
+                    ("name", (&timer.name).into()),
+                    ("value", timer.value.into()),
+                ],
+            ),
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
 }
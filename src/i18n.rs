@@ -0,0 +1,211 @@
+//! Message catalog, one Fluent file per language in `locales/`. Messages are
+//! plain text apart from `*bold*` and `` `code` `` markers, and are escaped
+//! for MarkdownV2 after formatting.
+
+use std::sync::LazyLock;
+
+use fluent_bundle::{concurrent::FluentBundle, FluentArgs, FluentResource};
+use teloxide::{types::User, utils::markdown::escape};
+use tracing::warn;
+
+use crate::settings::Language;
+
+type Bundle = FluentBundle<FluentResource>;
+
+static EN: LazyLock<Bundle> = LazyLock::new(|| bundle("en", include_str!("../locales/en.ftl")));
+static RU: LazyLock<Bundle> = LazyLock::new(|| bundle("ru", include_str!("../locales/ru.ftl")));
+
+/// Markers kept as formatting when escaping a message
+const FORMATTING: &[char] = &['*', '`'];
+
+fn bundle(lang: &str, source: &str) -> Bundle {
+    let resource = FluentResource::try_new(source.to_owned())
+        .unwrap_or_else(|(_, errors)| panic!("Invalid {lang} catalog: {errors:?}"));
+    let mut bundle = Bundle::new_concurrent(vec![lang.parse().expect("Invalid language id")]);
+    // Direction isolation marks around arguments would show up in Telegram
+    bundle.set_use_isolating(false);
+    bundle
+        .add_resource(resource)
+        .unwrap_or_else(|errors| panic!("Duplicate {lang} messages: {errors:?}"));
+    bundle
+}
+
+/// A message argument
+pub enum Arg {
+    /// User input or other text, escaped as needed
+    Text(String),
+    /// Numbers take part in plural selection
+    Number(i64),
+    /// Already formatted MarkdownV2, inserted as is
+    Markdown(String),
+}
+
+impl From<&str> for Arg {
+    fn from(value: &str) -> Self {
+        Arg::Text(value.to_owned())
+    }
+}
+
+impl From<&String> for Arg {
+    fn from(value: &String) -> Self {
+        Arg::Text(value.clone())
+    }
+}
+
+impl From<i32> for Arg {
+    fn from(value: i32) -> Self {
+        Arg::Number(value.into())
+    }
+}
+
+impl From<u32> for Arg {
+    fn from(value: u32) -> Self {
+        Arg::Number(value.into())
+    }
+}
+
+impl From<u16> for Arg {
+    fn from(value: u16) -> Self {
+        Arg::Number(value.into())
+    }
+}
+
+impl From<bool> for Arg {
+    fn from(value: bool) -> Self {
+        Arg::Number(value.into())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Locale(pub Language);
+
+impl Locale {
+    /// The chat language if set, otherwise the language of `user`
+    pub fn new(chat_language: Option<Language>, user: &User) -> Self {
+        let language = chat_language
+            .or_else(|| user.language_code.as_deref().and_then(Language::from_code))
+            .unwrap_or(Language::En);
+        Self(language)
+    }
+
+    /// Formats a message as MarkdownV2
+    pub fn text(&self, key: &str, args: &[(&str, Arg)]) -> String {
+        self.format(key, args, true)
+    }
+
+    /// Formats a message as plain text, e.g. for buttons and popups
+    pub fn plain(&self, key: &str, args: &[(&str, Arg)]) -> String {
+        self.format(key, args, false)
+    }
+
+    fn format(&self, key: &str, args: &[(&str, Arg)], markdown: bool) -> String {
+        let bundle = match self.0 {
+            Language::En => &*EN,
+            Language::Ru => &*RU,
+        };
+        let Some(pattern) = [bundle, &*EN]
+            .into_iter()
+            .find_map(|bundle| bundle.get_message(key)?.value().map(|p| (bundle, p)))
+        else {
+            warn!("Missing message {}", key);
+            return key.to_owned();
+        };
+
+        // Text arguments are formatted as placeholders and substituted after
+        // escaping, so that they are escaped exactly once and their `*`s are
+        // not taken for formatting
+        let mut fluent_args = FluentArgs::new();
+        for (i, (name, arg)) in args.iter().enumerate() {
+            match arg {
+                Arg::Number(value) => fluent_args.set(*name, *value),
+                Arg::Text(_) | Arg::Markdown(_) => fluent_args.set(*name, placeholder(i)),
+            }
+        }
+        let (bundle, pattern) = pattern;
+        let mut errors = vec![];
+        let formatted = bundle.format_pattern(pattern, Some(&fluent_args), &mut errors);
+        if !errors.is_empty() {
+            warn!("Error formatting message {}: {:?}", key, errors);
+        }
+
+        let mut out = if markdown {
+            escape_text(&formatted)
+        } else {
+            formatted.replace(FORMATTING, "")
+        };
+        for (i, (_, arg)) in args.iter().enumerate() {
+            let value = match arg {
+                Arg::Text(text) if markdown => escape(text),
+                Arg::Text(text) | Arg::Markdown(text) => text.clone(),
+                Arg::Number(_) => continue,
+            };
+            out = out.replace(&placeholder(i), &value);
+        }
+        out
+    }
+}
+
+fn placeholder(i: usize) -> String {
+    format!("\u{E000}{i}\u{E001}")
+}
+
+/// Escapes everything except formatting markers
+fn escape_text(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for part in text.split_inclusive(FORMATTING) {
+        match part.strip_suffix(FORMATTING) {
+            Some(plain) => {
+                out.push_str(&escape(plain));
+                out.push_str(&part[plain.len()..]);
+            }
+            None => out.push_str(&escape(part)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_after_formatting() {
+        let locale = Locale(Language::En);
+        let text = locale.text("timer-fired", &[("name", "*Clock* (v2)".into())]);
+        assert_eq!(text, "Timer *\\*Clock\\* \\(v2\\)* has fired\\!");
+
+        let plain = locale.plain("timer-fired-toast", &[("name", "*Clock*".into())]);
+        assert_eq!(plain, "*Clock* has fired!");
+    }
+
+    #[test]
+    fn russian_plurals() {
+        let locale = Locale(Language::Ru);
+        let ticks = |value: i32| {
+            locale.plain(
+                "timer-ticks-toast",
+                &[("name", "Часы".into()), ("value", value.into())],
+            )
+        };
+        assert_eq!(ticks(1), "Часы: остался 1 тик");
+        assert_eq!(ticks(3), "Часы: осталось 3 тика");
+        assert_eq!(ticks(5), "Часы: осталось 5 тиков");
+    }
+
+    #[test]
+    fn catalogs_have_the_same_messages() {
+        let keys = |source: &str| {
+            let mut keys = source
+                .lines()
+                .filter_map(|line| line.split_once(" = ").map(|(key, _)| key.to_owned()))
+                .filter(|key| !key.starts_with([' ', '#']))
+                .collect::<Vec<_>>();
+            keys.sort();
+            keys
+        };
+        assert_eq!(
+            keys(include_str!("../locales/en.ftl")),
+            keys(include_str!("../locales/ru.ftl"))
+        );
+    }
+}
@@ -1,65 +1,224 @@
-// use teloxide::{
-//     requests::Requester,
-//     types::{
-//         InlineKeyboardButton, InlineKeyboardMarkup, InlineQuery,
-//         InlineQueryResultArticle
-//     },
-// };
-// use tracing::{info, instrument};
-
-// use crate::utils::{Bot};
-
-// #[instrument(skip(bot))]
-// pub async fn handle_inline(
-//     bot: Bot,
-//     inline: InlineQuery,
-// ) -> anyhow::Result<()> {
-//     info!("Handling update");
-//     // let dice1 = InlineQueryResultGif::new(
-//     //     "0",
-//     //     "https://dnd-bot-dice.s3.amazonaws.com/2dice.gif".parse()?,
-//     //     "https://dnd-bot-dice.s3.amazonaws.com/1dice.gif".parse()?,
-//     // ).gif_width(150).gif_height(150);
-//     // let dice2 = InlineQueryResultGif::new(
-//     //     "1",
-//     //     "https://dnd-bot-dice.s3.amazonaws.com/2dice.gif".parse()?,
-//     //     "https://dnd-bot-dice.s3.amazonaws.com/2dice.gif".parse()?,
-//     // );
-//     // let dice3 = InlineQueryResultGif::new(
-//     //     "2",
-//     //     "https://dnd-bot-dice.s3.amazonaws.com/3dice.gif".parse()?,
-//     //     "https://dnd-bot-dice.s3.amazonaws.com/3dice.gif".parse()?,
-//     // );
-//     // info!("Sending {:?}", dice1);
-//         let choose_debian_version = InlineQueryResultArticle::new(
-//         "0",
-//         "Chose debian version",
-//         InputMessageContent::Text(InputMessageContentText::new("Debian versions:")),
-//     )
-//     .reply_markup(make_keyboard());
-
-//     bot.answer_inline_query(inline.id, vec![choose_debian_version.into()]).await?;
-//     // bot.answer_inline_query(inline.id, vec![dice1.into(), dice2.into(), dice3.into()])
-//         // .await?;
-//     Ok(())
-// }
-
-// fn make_keyboard() -> InlineKeyboardMarkup {
-//     let mut keyboard: Vec<Vec<InlineKeyboardButton>> = vec![];
-
-//     let debian_versions = [
-//         "Buzz", "Rex", "Bo", "Hamm", "Slink", "Potato", "Woody", "Sarge", "Etch", "Lenny",
-//         "Squeeze", "Wheezy", "Jessie", "Stretch", "Buster", "Bullseye",
-//     ];
-
-//     for versions in debian_versions.chunks(3) {
-//         let row = versions
-//             .iter()
-//             .map(|&version| InlineKeyboardButton::callback(version.to_owned(), version.to_owned()))
-//             .collect();
-
-//         keyboard.push(row);
-//     }
-
-//     InlineKeyboardMarkup::new(keyboard)
-// }
+use teloxide::types::{
+    InlineQuery, InlineQueryResult, InlineQueryResultArticle, InputMessageContent,
+    InputMessageContentText, ParseMode,
+};
+use tracing::{info, instrument};
+
+use crate::{
+    context::Storage,
+    dice::{ActionRoll, DiceRoll},
+    handler::format_timers_msg,
+    i18n::Locale,
+    messenger::Messenger,
+};
+
+/// Every roll query has to be rolled anew
+const ROLL_CACHE_TIME: u32 = 0;
+/// Clocks change rarely, and each answer reads every chat of the user
+const CLOCKS_CACHE_TIME: u32 = 10;
+
+/// Handles `@bot 3d6`, `@bot action 2` and `@bot clocks`.
+#[instrument(skip(bot, storage, inline), fields(query = %inline.query))]
+pub async fn handle_inline<M: Messenger>(
+    bot: &M,
+    storage: &Storage,
+    inline: &InlineQuery,
+) -> anyhow::Result<()> {
+    info!("Handling inline query");
+    let locale = Locale::new(None, &inline.from);
+    let query = inline.query.trim().to_lowercase();
+    let words = query.split_whitespace().collect::<Vec<_>>();
+
+    let (results, cache_time) = match words.as_slice() {
+        ["clocks"] => (clocks(storage, inline, &locale).await?, CLOCKS_CACHE_TIME),
+        ["action", pool] => (action_roll(bot, pool, &locale), ROLL_CACHE_TIME),
+        [dice] => (dice_roll(bot, dice, &locale), ROLL_CACHE_TIME),
+        _ => (Vec::new(), ROLL_CACHE_TIME),
+    };
+    bot.answer_inline_query(inline.id.clone(), results, cache_time)
+        .await
+}
+
+fn dice_roll<M: Messenger>(bot: &M, query: &str, locale: &Locale) -> Vec<InlineQueryResult> {
+    let Ok(dice) = query.parse::<DiceRoll>() else {
+        return Vec::new();
+    };
+    // Not tied to a chat, so never part of a fair session
+    let values = dice.roll(&mut bot.roll_rng());
+    let text = format_roll(locale, &dice, &values);
+    let title = locale.plain("inline-roll-title", &[("dice", (&dice.to_string()).into())]);
+    vec![article("roll", title, locale, text)]
+}
+
+/// The dice rolled and their total
+pub fn format_roll(locale: &Locale, dice: &DiceRoll, values: &[u32]) -> String {
+    // Kept dice are picked from the list rather than all added up
+    let separator = if dice.keep.is_some() { ", " } else { " + " };
+    let mut shown = join(values, separator);
+    if dice.modifier != 0 {
+        let sign = if dice.modifier < 0 { '-' } else { '+' };
+        shown = format!("{shown} {sign} {}", dice.modifier.abs());
+    }
+    locale.text(
+        "inline-roll",
+        &[
+            ("dice", (&dice.to_string()).into()),
+            ("values", (&shown).into()),
+            ("total", dice.total(values).into()),
+        ],
+    )
+}
+
+fn action_roll<M: Messenger>(bot: &M, pool: &str, locale: &Locale) -> Vec<InlineQueryResult> {
+    let Some(roll) = pool
+        .parse()
+        .ok()
+        .and_then(|pool| ActionRoll::new(pool).ok())
+    else {
+        return Vec::new();
+    };
+    let roll = roll.roll(&mut bot.roll_rng());
+    let text = locale.text(
+        "inline-action",
+        &[
+            ("pool", roll.pool.into()),
+            ("values", (&join(&roll.dice, ", ")).into()),
+            ("result", roll.result().into()),
+            ("outcome", (&locale.plain(roll.outcome().key(), &[])).into()),
+        ],
+    );
+    let title = locale.plain("inline-action-title", &[("pool", roll.pool.into())]);
+    vec![article("action", title, locale, text)]
+}
+
+async fn clocks(
+    storage: &Storage,
+    inline: &InlineQuery,
+    locale: &Locale,
+) -> anyhow::Result<Vec<InlineQueryResult>> {
+    let mut results = Vec::new();
+    for (chat_id, title) in storage.member_chats(inline.from.id).await? {
+        let tracker = storage.context(chat_id).await.get().await?;
+        if tracker.timers.is_empty() {
+            continue;
+        }
+        let description = tracker
+            .timers
+            .iter()
+            .map(|timer| format!("{}: {}", timer.name, timer.value))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let content = InputMessageContentText::new(format_timers_msg(locale, &tracker))
+            .parse_mode(ParseMode::MarkdownV2);
+        results.push(
+            InlineQueryResultArticle::new(
+                chat_id.to_string(),
+                title,
+                InputMessageContent::Text(content),
+            )
+            .description(description)
+            .into(),
+        );
+    }
+    Ok(results)
+}
+
+/// An article whose result is only revealed once it is sent
+fn article(id: &str, title: String, locale: &Locale, text: String) -> InlineQueryResult {
+    let content = InputMessageContentText::new(text).parse_mode(ParseMode::MarkdownV2);
+    InlineQueryResultArticle::new(id, title, InputMessageContent::Text(content))
+        .description(locale.plain("inline-roll-description", &[]))
+        .into()
+}
+
+fn join(values: &[u32], separator: &str) -> String {
+    values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::{InlineQueryResult, InputMessageContent};
+
+    use crate::testing::{Sent, TestChat};
+
+    fn titles(sent: Vec<Sent>) -> (Vec<String>, u32) {
+        match sent.as_slice() {
+            [Sent::InlineAnswer {
+                results,
+                cache_time,
+            }] => {
+                let titles = results
+                    .iter()
+                    .map(|result| match result {
+                        InlineQueryResult::Article(article) => article.title.clone(),
+                        _ => panic!("Unexpected result {result:?}"),
+                    })
+                    .collect();
+                (titles, *cache_time)
+            }
+            _ => panic!("Unexpected answer {sent:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn rolls_are_not_cached() {
+        let mut chat = TestChat::new();
+        chat.inline("3d6").await.unwrap();
+        assert_eq!(titles(chat.bot.take()), (vec!["Roll 3d6".to_owned()], 0));
+
+        chat.inline("action 2").await.unwrap();
+        assert_eq!(
+            titles(chat.bot.take()),
+            (vec!["Action roll with 2 dice".to_owned()], 0)
+        );
+
+        chat.inline("3d1").await.unwrap();
+        assert_eq!(titles(chat.bot.take()), (vec![], 0));
+    }
+
+    #[tokio::test]
+    async fn rolls_with_the_bot_rng() {
+        let mut chat = TestChat::new();
+        chat.inline("2d6-1").await.unwrap();
+        chat.inline("action 3").await.unwrap();
+        let texts = chat
+            .bot
+            .take()
+            .into_iter()
+            .map(|sent| match sent {
+                Sent::InlineAnswer { results, .. } => match &results[..] {
+                    [InlineQueryResult::Article(article)] => match &article.input_message_content {
+                        InputMessageContent::Text(content) => content.message_text.clone(),
+                        content => panic!("Unexpected content {content:?}"),
+                    },
+                    _ => panic!("Unexpected results {results:?}"),
+                },
+                _ => panic!("Unexpected answer {sent:?}"),
+            })
+            .collect::<Vec<_>>();
+        // Fixed by the seed of the test messenger
+        assert_eq!(
+            texts,
+            [
+                "🎲 2d6\\-1: 1 \\+ 4 \\- 1 \\= *4*",
+                "🎲 Action roll with 3 dice: 2, 1, 6 → *6*, full success"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn shows_clocks_of_known_chats() {
+        let mut chat = TestChat::new();
+        chat.inline("clocks").await.unwrap();
+        assert_eq!(titles(chat.bot.take()).0, Vec::<String>::new());
+
+        chat.command("/ta Heist 6").await.unwrap();
+        chat.bot.take();
+        chat.inline("clocks").await.unwrap();
+        assert_eq!(titles(chat.bot.take()).0, vec!["Table".to_owned()]);
+    }
+}
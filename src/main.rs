@@ -1,34 +1,120 @@
-use std::env;
+use std::{env, time::Instant};
 
 use ::tracing::{info, instrument};
 use anyhow::anyhow;
+use context::Storage;
 use dispatcher::dispatch_update;
 use dotenv::dotenv;
 use lambda_http::{run, service_fn, tracing, Body, Error, Request, RequestPayloadExt, Response};
+use lambda_runtime::LambdaEvent;
 use teloxide::prelude::*;
-use utils::{authorize, error_response, init_bot, success_response, Bot};
+use utils::{error_response, init_bot, success_response, Bot};
+use webhook::{handle_webhook, set_webhook, WEBHOOK_ADDR_ENV_VAR};
 
+mod cache;
 mod callback;
 mod context;
+mod dice;
 mod dispatcher;
+mod fair;
 mod handler;
+mod i18n;
 mod inline;
+mod messenger;
+mod odds;
+mod poll;
+mod scheduler;
+mod settings;
+mod sqlite;
+mod stats;
+#[cfg(test)]
+mod testing;
 mod tracker;
 mod utils;
+mod webhook;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     match env::var("ON_LAMBDA").as_deref() {
         Ok("1") => run_on_lambda(init_bot().await).await,
+        Ok("scheduler") => run_scheduler_on_lambda(init_bot().await).await,
         _ => {
             dotenv().ok();
-            run_locally(init_bot().await).await
+            init_local_tracing();
+            let args = env::args().collect::<Vec<_>>();
+            match (
+                args.get(1).map(String::as_str),
+                env::var(WEBHOOK_ADDR_ENV_VAR),
+            ) {
+                (Some("set-webhook"), _) => {
+                    let url = args
+                        .get(2)
+                        .ok_or(anyhow!("Usage: dnd_bot set-webhook <url>"))?;
+                    set_webhook(&init_bot().await, url).await
+                }
+                (_, Ok(addr)) => run_webhook(init_bot().await, &addr).await,
+                _ => run_locally(init_bot().await).await,
+            }
         }
     }
 }
 
 #[instrument(skip(bot))]
 async fn run_on_lambda(bot: Bot) -> anyhow::Result<()> {
+    init_lambda_tracing();
+
+    info!("Starting serverless bot...");
+
+    // Built once per Lambda execution environment and reused by warm invocations
+    let storage = Storage::from_env().await?;
+
+    run(service_fn(|req| {
+        handle_lambda_request(bot.clone(), storage.clone(), req)
+    }))
+    .await
+    .map_err(|err| anyhow!("{:?}", err))
+}
+
+#[instrument(skip(bot, storage, request))]
+async fn handle_lambda_request(
+    bot: Bot,
+    storage: Storage,
+    request: Request,
+) -> Result<Response<Body>, Error> {
+    let start = Instant::now();
+    let payload = request.payload().map_err(|e| anyhow!("{e}"));
+    let ret = match handle_webhook(bot, storage, request.headers(), payload).await {
+        Ok(()) => success_response(),
+        Err((code, message)) => error_response(code, message),
+    };
+    info!("Request handled in {:?}", start.elapsed());
+    ret
+}
+
+/// Fires due timers on each invocation, e.g. by an EventBridge schedule
+#[instrument(skip(bot))]
+async fn run_scheduler_on_lambda(bot: Bot) -> anyhow::Result<()> {
+    init_lambda_tracing();
+
+    info!("Starting serverless scheduler...");
+
+    let storage = Storage::from_env().await?;
+
+    lambda_runtime::run(lambda_runtime::service_fn(
+        |_: LambdaEvent<serde_json::Value>| {
+            let (bot, storage) = (bot.clone(), storage.clone());
+            async move {
+                scheduler::fire_due_timers(&bot, &storage)
+                    .await
+                    .map_err(|err| Error::from(format!("{:#}", err)))
+            }
+        },
+    ))
+    .await
+    .map_err(|err| anyhow!("{:?}", err))
+}
+
+fn init_lambda_tracing() {
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::INFO)
         .json()
@@ -37,46 +123,33 @@ async fn run_on_lambda(bot: Bot) -> anyhow::Result<()> {
         .without_time()
         .with_target(false)
         .init();
-
-    info!("Starting serverless bot...");
-
-    run(service_fn(|req| handle_lambda_request(bot.clone(), req)))
-        .await
-        .map_err(|err| anyhow!("{:?}", err))
 }
 
-#[instrument(skip(bot, request))]
-async fn handle_lambda_request(bot: Bot, request: Request) -> Result<Response<Body>, Error> {
-    if let Err(e) = authorize(&request) {
-        return error_response(401, format!("Unauthorized: {e}"));
-    }
+fn init_local_tracing() {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .pretty()
+        .init();
+}
 
-    let update: Update = match request.payload() {
-        Ok(Some(payload)) => payload,
-        Ok(None) => {
-            return error_response(400, "Empty payload");
-        }
-        Err(e) => {
-            return error_response(400, format!("Invalid payload: {e}"));
-        }
-    };
+#[instrument(skip(bot))]
+async fn run_webhook(bot: Bot, addr: &str) -> anyhow::Result<()> {
+    info!("Starting local webhook bot...");
 
-    match dispatch_update(bot, update).await {
-        Ok(()) => success_response(),
-        Err(e) => error_response(400, format!("Error: {e}")),
-    }
+    let storage = Storage::from_env().await?.with_cache();
+    tokio::spawn(scheduler::run(bot.clone(), storage.clone()));
+    webhook::serve(bot, storage, addr).await
 }
 
 #[instrument(skip(bot))]
 async fn run_locally(bot: Bot) -> anyhow::Result<()> {
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
-        .pretty()
-        .init();
-
     info!("Starting local bot...");
 
-    Dispatcher::builder(bot, dptree::endpoint(dispatch_update))
+    let storage = Storage::from_env().await?.with_cache();
+    tokio::spawn(scheduler::run(bot.clone(), storage.clone()));
+
+    Dispatcher::builder(bot, dptree::endpoint(dispatch_update::<Bot>))
+        .dependencies(dptree::deps![storage])
         .enable_ctrlc_handler()
         .build()
         .dispatch()
@@ -0,0 +1,267 @@
+use std::{fmt, future::Future, time::Duration};
+
+use anyhow::anyhow;
+use teloxide::{
+    payloads::{
+        AnswerCallbackQuerySetters, AnswerInlineQuerySetters, EditMessageReplyMarkupSetters,
+        EditMessageTextSetters, PinChatMessageSetters, SendMessageSetters, UnpinChatMessageSetters,
+    },
+    prelude::*,
+    types::{
+        ForceReply, InlineKeyboardMarkup, InlineQueryResult, MessageId, ParseMode, ReplyMarkup,
+        ReplyParameters,
+    },
+    ApiError, RequestError,
+};
+
+use crate::{fair::RollRng, utils::Bot};
+
+/// Returned when editing or deleting a message which no longer exists, e.g.
+/// because a user deleted it
+#[derive(Debug)]
+pub struct MessageGone;
+
+impl fmt::Display for MessageGone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Message to edit not found")
+    }
+}
+
+impl std::error::Error for MessageGone {}
+
+/// The subset of the Telegram Bot API used by the handlers. All texts are
+/// MarkdownV2. Edits and deletes fail with [`MessageGone`] if the message was
+/// deleted, and edits which leave a message as it was succeed.
+pub trait Messenger: Clone + Send + Sync + 'static {
+    fn send_message(
+        &self,
+        chat_id: ChatId,
+        text: String,
+        markup: Option<InlineKeyboardMarkup>,
+    ) -> impl Future<Output = anyhow::Result<MessageId>> + Send;
+
+    fn edit_message_text(
+        &self,
+        chat_id: ChatId,
+        msg_id: MessageId,
+        text: String,
+        markup: Option<InlineKeyboardMarkup>,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    fn edit_message_reply_markup(
+        &self,
+        chat_id: ChatId,
+        msg_id: MessageId,
+        markup: InlineKeyboardMarkup,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    fn delete_message(
+        &self,
+        chat_id: ChatId,
+        msg_id: MessageId,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    /// Pins without notifying the chat members. Needs the bot to be an admin
+    /// in groups.
+    fn pin_message(
+        &self,
+        chat_id: ChatId,
+        msg_id: MessageId,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    fn unpin_message(
+        &self,
+        chat_id: ChatId,
+        msg_id: MessageId,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    /// Sends a message whose recipients are asked to reply to it. Only users
+    /// mentioned in `text` are asked.
+    fn send_force_reply(
+        &self,
+        chat_id: ChatId,
+        text: String,
+    ) -> impl Future<Output = anyhow::Result<MessageId>> + Send;
+
+    /// Sends a message quoting `reply_to`
+    fn send_reply(
+        &self,
+        chat_id: ChatId,
+        reply_to: MessageId,
+        text: String,
+    ) -> impl Future<Output = anyhow::Result<MessageId>> + Send;
+
+    /// Sends an animated die, returns the message and the value rolled
+    fn send_dice(
+        &self,
+        chat_id: ChatId,
+    ) -> impl Future<Output = anyhow::Result<(MessageId, u32)>> + Send;
+
+    /// How long a sent die animates before it shows the value
+    fn dice_animation(&self) -> Duration;
+
+    /// Randomness of the bot's own rolls outside fair sessions
+    fn roll_rng(&self) -> RollRng;
+
+    fn answer_callback_query(
+        &self,
+        callback_id: String,
+        text: Option<String>,
+        show_alert: bool,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    /// Results are personal, Telegram reuses them for `cache_time` seconds
+    /// only for the same user and query.
+    fn answer_inline_query(
+        &self,
+        query_id: String,
+        results: Vec<InlineQueryResult>,
+        cache_time: u32,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    fn username(&self) -> impl Future<Output = anyhow::Result<String>> + Send;
+}
+
+impl Messenger for Bot {
+    async fn send_message(
+        &self,
+        chat_id: ChatId,
+        text: String,
+        markup: Option<InlineKeyboardMarkup>,
+    ) -> anyhow::Result<MessageId> {
+        let mut request =
+            Requester::send_message(self, chat_id, text).parse_mode(ParseMode::MarkdownV2);
+        if let Some(markup) = markup {
+            request = request.reply_markup(markup);
+        }
+        Ok(request.await?.id)
+    }
+
+    async fn edit_message_text(
+        &self,
+        chat_id: ChatId,
+        msg_id: MessageId,
+        text: String,
+        markup: Option<InlineKeyboardMarkup>,
+    ) -> anyhow::Result<()> {
+        let mut request = Requester::edit_message_text(self, chat_id, msg_id, text)
+            .parse_mode(ParseMode::MarkdownV2);
+        if let Some(markup) = markup {
+            request = request.reply_markup(markup);
+        }
+        check_edit(request.await)
+    }
+
+    async fn edit_message_reply_markup(
+        &self,
+        chat_id: ChatId,
+        msg_id: MessageId,
+        markup: InlineKeyboardMarkup,
+    ) -> anyhow::Result<()> {
+        check_edit(
+            Requester::edit_message_reply_markup(self, chat_id, msg_id)
+                .reply_markup(markup)
+                .await,
+        )
+    }
+
+    async fn delete_message(&self, chat_id: ChatId, msg_id: MessageId) -> anyhow::Result<()> {
+        match Requester::delete_message(self, chat_id, msg_id).await {
+            Ok(_) => Ok(()),
+            Err(RequestError::Api(ApiError::MessageToDeleteNotFound)) => Err(MessageGone.into()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn pin_message(&self, chat_id: ChatId, msg_id: MessageId) -> anyhow::Result<()> {
+        Requester::pin_chat_message(self, chat_id, msg_id)
+            .disable_notification(true)
+            .await?;
+        Ok(())
+    }
+
+    async fn unpin_message(&self, chat_id: ChatId, msg_id: MessageId) -> anyhow::Result<()> {
+        Requester::unpin_chat_message(self, chat_id)
+            .message_id(msg_id)
+            .await?;
+        Ok(())
+    }
+
+    async fn send_force_reply(&self, chat_id: ChatId, text: String) -> anyhow::Result<MessageId> {
+        let message = Requester::send_message(self, chat_id, text)
+            .parse_mode(ParseMode::MarkdownV2)
+            .reply_markup(ReplyMarkup::ForceReply(ForceReply::new().selective()))
+            .await?;
+        Ok(message.id)
+    }
+
+    async fn send_reply(
+        &self,
+        chat_id: ChatId,
+        reply_to: MessageId,
+        text: String,
+    ) -> anyhow::Result<MessageId> {
+        let message = Requester::send_message(self, chat_id, text)
+            .parse_mode(ParseMode::MarkdownV2)
+            .reply_parameters(ReplyParameters::new(reply_to))
+            .await?;
+        Ok(message.id)
+    }
+
+    async fn send_dice(&self, chat_id: ChatId) -> anyhow::Result<(MessageId, u32)> {
+        let message = Requester::send_dice(self, chat_id).await?;
+        let dice = message
+            .dice()
+            .ok_or(anyhow!("Sent dice message has no dice"))?;
+        Ok((message.id, dice.value.into()))
+    }
+
+    fn dice_animation(&self) -> Duration {
+        Duration::from_secs(4)
+    }
+
+    fn roll_rng(&self) -> RollRng {
+        RollRng::os()
+    }
+
+    async fn answer_callback_query(
+        &self,
+        callback_id: String,
+        text: Option<String>,
+        show_alert: bool,
+    ) -> anyhow::Result<()> {
+        let mut request = Requester::answer_callback_query(self, callback_id);
+        if let Some(text) = text {
+            request = request.text(text).show_alert(show_alert);
+        }
+        request.await?;
+        Ok(())
+    }
+
+    async fn answer_inline_query(
+        &self,
+        query_id: String,
+        results: Vec<InlineQueryResult>,
+        cache_time: u32,
+    ) -> anyhow::Result<()> {
+        Requester::answer_inline_query(self, query_id, results)
+            .cache_time(cache_time)
+            .is_personal(true)
+            .await?;
+        Ok(())
+    }
+
+    async fn username(&self) -> anyhow::Result<String> {
+        Ok(self.get_me().await?.username().to_owned())
+    }
+}
+
+fn check_edit<T>(result: Result<T, RequestError>) -> anyhow::Result<()> {
+    match result {
+        Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => Ok(()),
+        Err(RequestError::Api(ApiError::MessageToEditNotFound | ApiError::MessageIdInvalid)) => {
+            Err(MessageGone.into())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
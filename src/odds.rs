@@ -0,0 +1,263 @@
+//! Exact odds of action rolls and dice expressions, see `/odds`.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, bail};
+use teloxide::utils::markdown;
+
+use crate::{
+    dice::{ActionRoll, DiceRoll, Distribution, Outcome},
+    i18n::{Arg, Locale},
+};
+
+/// Largest pool in the table of all pools
+const MAX_TABLE_POOL: u32 = 6;
+/// Characters of the longest bar
+const BAR_WIDTH: usize = 16;
+/// Totals are grouped into ranges above this many
+const MAX_CHART_ROWS: usize = 20;
+/// Totals at either end less likely than this are left out of charts
+const MIN_CHART_CHANCE: f64 = 0.0005;
+
+/// What `/odds` is asked for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OddsQuery {
+    /// Action rolls of every pool size
+    ActionPools,
+    Action(u32),
+    /// Totals of the dice, and the chance of a total passing the check
+    Dice(DiceRoll, Option<Check>),
+}
+
+impl FromStr for OddsQuery {
+    type Err = anyhow::Error;
+
+    /// Parses ``, `2`, `3d6` or `4d6kh3 >= 15`
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Ok(OddsQuery::ActionPools);
+        }
+        if let Ok(pool) = s.parse() {
+            return Ok(OddsQuery::Action(pool));
+        }
+        match s.find(['<', '>', '=']) {
+            Some(pos) => Ok(OddsQuery::Dice(s[..pos].parse()?, Some(s[pos..].parse()?))),
+            None => Ok(OddsQuery::Dice(s.parse()?, None)),
+        }
+    }
+}
+
+/// Comparison of a total with a target, such as `>= 15`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Check {
+    pub comparison: Comparison,
+    pub target: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Check {
+    pub fn passes(&self, total: i32) -> bool {
+        match self.comparison {
+            Comparison::Less => total < self.target,
+            Comparison::LessOrEqual => total <= self.target,
+            Comparison::Equal => total == self.target,
+            Comparison::GreaterOrEqual => total >= self.target,
+            Comparison::Greater => total > self.target,
+        }
+    }
+}
+
+impl FromStr for Check {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        let split = s.find(|c| !matches!(c, '<' | '>' | '=')).unwrap_or(s.len());
+        let comparison = match &s[..split] {
+            "<" => Comparison::Less,
+            "<=" => Comparison::LessOrEqual,
+            "=" | "==" => Comparison::Equal,
+            ">=" => Comparison::GreaterOrEqual,
+            ">" => Comparison::Greater,
+            other => bail!("Unknown comparison: {}", other),
+        };
+        let target = s[split..]
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("Expected a number after {}", &s[..split]))?;
+        Ok(Self { comparison, target })
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let comparison = match self.comparison {
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Equal => "=",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::Greater => ">",
+        };
+        write!(f, "{} {}", comparison, self.target)
+    }
+}
+
+/// The odds as a table or bar chart in a code block
+pub fn format_odds(locale: &Locale, query: OddsQuery) -> anyhow::Result<String> {
+    let text = match query {
+        OddsQuery::ActionPools => format!(
+            "{}\n{}",
+            locale.text("odds-action-title", &[]),
+            markdown::code_block(&action_table(locale)?)
+        ),
+        OddsQuery::Action(pool) => {
+            let odds = ActionRoll::odds(pool)?;
+            let rows = Outcome::ALL
+                .iter()
+                .zip(odds)
+                .map(|(outcome, chance)| (locale.plain(outcome.short_key(), &[]), chance))
+                .collect::<Vec<_>>();
+            format!(
+                "{}\n{}",
+                locale.text("odds-pool-title", &[("pool", pool.into())]),
+                markdown::code_block(&chart(&rows))
+            )
+        }
+        OddsQuery::Dice(dice, check) => {
+            let distribution = dice.distribution()?;
+            let dice_name = dice.to_string();
+            let title = locale.text(
+                "odds-dice-title",
+                &[
+                    ("dice", (&dice_name).into()),
+                    ("average", (&format!("{:.2}", distribution.mean())).into()),
+                ],
+            );
+            let mut text = format!(
+                "{}\n{}",
+                title,
+                markdown::code_block(&chart(&total_rows(&distribution)))
+            );
+            if let Some(check) = check {
+                let chance = distribution.chance(|total| check.passes(total));
+                text.push('\n');
+                text.push_str(&locale.text(
+                    "odds-check",
+                    &[
+                        ("dice", (&dice_name).into()),
+                        ("check", (&check.to_string()).into()),
+                        ("chance", Arg::Text(percent(chance))),
+                    ],
+                ));
+            }
+            text
+        }
+    };
+    Ok(text)
+}
+
+/// Outcome chances of every pool, a row per pool
+fn action_table(locale: &Locale) -> anyhow::Result<String> {
+    let header = std::iter::once(locale.plain("odds-dice", &[]))
+        .chain(
+            Outcome::ALL
+                .iter()
+                .map(|outcome| locale.plain(outcome.short_key(), &[])),
+        )
+        .collect::<Vec<_>>();
+    let mut rows = vec![header];
+    for pool in 0..=MAX_TABLE_POOL {
+        let odds = ActionRoll::odds(pool)?;
+        rows.push(
+            std::iter::once(pool.to_string())
+                .chain(odds.into_iter().map(percent))
+                .collect(),
+        );
+    }
+    // Each column as wide as its widest cell, numbers aligned right
+    let widths = (0..rows[0].len())
+        .map(|column| rows.iter().map(|row| row[column].chars().count()).max())
+        .collect::<Option<Vec<_>>>()
+        .unwrap_or_default();
+    let lines = rows.iter().map(|row| {
+        let cells = row.iter().zip(&widths);
+        let cells = cells.map(|(cell, width)| format!("{cell:>width$}"));
+        cells.collect::<Vec<_>>().join("  ")
+    });
+    Ok(lines.collect::<Vec<_>>().join("\n"))
+}
+
+/// Chances of the likely totals, grouped into ranges if there are many
+fn total_rows(distribution: &Distribution) -> Vec<(String, f64)> {
+    let totals = distribution.totals().collect::<Vec<_>>();
+    let likely = |(_, chance): &(i32, f64)| *chance >= MIN_CHART_CHANCE;
+    let first = totals.iter().position(likely).unwrap_or(0);
+    let last = totals.iter().rposition(likely).unwrap_or(totals.len() - 1);
+    let totals = &totals[first..=last];
+    let group = totals.len().div_ceil(MAX_CHART_ROWS);
+    totals
+        .chunks(group)
+        .map(|chunk| {
+            let (low, high) = (chunk[0].0, chunk[chunk.len() - 1].0);
+            let label = if low == high {
+                low.to_string()
+            } else {
+                format!("{low}–{high}")
+            };
+            (label, chunk.iter().map(|(_, chance)| chance).sum())
+        })
+        .collect()
+}
+
+/// A line per row: label, bar scaled to the likeliest row, and percentage
+fn chart(rows: &[(String, f64)]) -> String {
+    let label_width = rows
+        .iter()
+        .map(|(label, _)| label.chars().count())
+        .max()
+        .unwrap_or(0);
+    let max = rows.iter().map(|(_, chance)| *chance).fold(0.0, f64::max);
+    let lines = rows.iter().map(|(label, chance)| {
+        let bar = "█".repeat((chance / max * BAR_WIDTH as f64).round() as usize);
+        let percent = percent(*chance);
+        format!("{label:<label_width$} {bar:<BAR_WIDTH$} {percent:>6}")
+    });
+    lines.collect::<Vec<_>>().join("\n")
+}
+
+fn percent(chance: f64) -> String {
+    if chance > 0.0 && chance < 0.0005 {
+        return "<0.1%".to_owned();
+    }
+    format!("{:.1}%", chance * 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_queries() {
+        assert_eq!("".parse::<OddsQuery>().unwrap(), OddsQuery::ActionPools);
+        assert_eq!(" 3 ".parse::<OddsQuery>().unwrap(), OddsQuery::Action(3));
+        let OddsQuery::Dice(dice, Some(check)) = "4d6kh3>= 15".parse().unwrap() else {
+            panic!("Expected dice with a check");
+        };
+        assert_eq!(
+            (dice.to_string(), check.to_string()),
+            ("4d6kh3".to_owned(), ">= 15".to_owned())
+        );
+        assert!(check.passes(15) && !check.passes(14));
+        assert!("2d6 => 7".parse::<OddsQuery>().is_err());
+        assert!("2d6 > x".parse::<OddsQuery>().is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! Polls on when to play next, see `/schedule`.
+
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+use teloxide::types::{MessageId, UserId};
+
+/// Keeps the poll keyboard short enough to read
+const MAX_POLL_OPTIONS: usize = 8;
+
+/// Proposed session dates and who can come to each, stored with the tracker
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SessionPoll {
+    /// Passed in the buttons, so that those of an earlier poll do nothing
+    pub id: u32,
+    pub msg_id: MessageId,
+    pub options: Vec<PollOption>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PollOption {
+    /// Unix time the session would start at
+    pub starts_at: u64,
+    pub votes: Vec<Vote>,
+}
+
+/// A user who can come
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Vote {
+    pub user_id: UserId,
+    /// Name of the user's player if one is tracked, the user's name otherwise
+    pub name: String,
+}
+
+/// Options without votes for the given start times, in order
+pub fn poll_options(mut starts: Vec<u64>) -> anyhow::Result<Vec<PollOption>> {
+    starts.sort_unstable();
+    starts.dedup();
+    if starts.is_empty() {
+        bail!("Expected dates such as 2024-05-04 19:00, separated by ;");
+    }
+    if starts.len() > MAX_POLL_OPTIONS {
+        bail!("A poll can have at most {} dates", MAX_POLL_OPTIONS);
+    }
+    Ok(starts
+        .into_iter()
+        .map(|starts_at| PollOption {
+            starts_at,
+            votes: Vec::new(),
+        })
+        .collect())
+}
+
+impl SessionPoll {
+    /// Adds or takes back the vote of `user_id`, returns whether it was added
+    pub fn toggle_vote(
+        &mut self,
+        option: usize,
+        user_id: UserId,
+        name: &str,
+    ) -> anyhow::Result<bool> {
+        let option = self
+            .options
+            .get_mut(option)
+            .ok_or(anyhow!("Poll option {} not found", option))?;
+        if let Some(pos) = option.votes.iter().position(|vote| vote.user_id == user_id) {
+            option.votes.remove(pos);
+            return Ok(false);
+        }
+        option.votes.push(Vote {
+            user_id,
+            name: name.to_owned(),
+        });
+        Ok(true)
+    }
+
+    /// The date most can come to, the earliest of those on a tie. None if
+    /// nobody voted.
+    pub fn chosen(&self) -> Option<&PollOption> {
+        self.options
+            .iter()
+            .filter(|option| !option.votes.is_empty())
+            .min_by_key(|option| (std::cmp::Reverse(option.votes.len()), option.starts_at))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_votes_win_and_ties_go_to_the_earliest() {
+        let mut poll = SessionPoll {
+            id: 1,
+            msg_id: MessageId(1),
+            options: poll_options(vec![300, 100, 200, 100]).unwrap(),
+        };
+        assert_eq!(poll.options.len(), 3);
+        assert_eq!(poll.chosen(), None);
+
+        assert!(poll.toggle_vote(2, UserId(1), "Alice").unwrap());
+        assert!(poll.toggle_vote(1, UserId(2), "Bob").unwrap());
+        assert_eq!(poll.chosen().unwrap().starts_at, 200);
+        assert!(poll.toggle_vote(2, UserId(2), "Bob").unwrap());
+        assert_eq!(poll.chosen().unwrap().starts_at, 300);
+
+        // Voting again takes the vote back
+        assert!(!poll.toggle_vote(2, UserId(2), "Bob").unwrap());
+        assert_eq!(poll.chosen().unwrap().starts_at, 200);
+        assert!(poll.toggle_vote(3, UserId(1), "Alice").is_err());
+    }
+}
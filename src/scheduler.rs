@@ -0,0 +1,230 @@
+//! Real-time timers. Due timers are fired by a loop next to the local bot,
+//! and on Lambda by a scheduled EventBridge invocation.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    context::Storage,
+    handler::BotHandler,
+    messenger::Messenger,
+    utils::{debug_err, unix_time},
+};
+
+/// How often the local loop looks for due timers
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+const DAY_SECS: u64 = 24 * 60 * 60;
+/// Timers can be due at most this far ahead
+const MAX_DELAY_SECS: u64 = 30 * DAY_SECS;
+
+/// Parses a delay such as `30m` or `1h30m`, or a time of day such as `20:00`,
+/// into the Unix time it ends at. Times of day are `utc_offset` minutes from
+/// UTC and refer to the next time that hour comes.
+pub fn parse_due(input: &str, now: u64, utc_offset: i32) -> anyhow::Result<u64> {
+    let input = input.trim().to_lowercase();
+    let invalid = || anyhow!("Expected a delay such as 30m or 1h30m, or a time such as 20:00");
+    let delay = match input.split_once(':') {
+        Some((hours, minutes)) => {
+            let hours: u64 = hours.parse().map_err(|_| invalid())?;
+            let minutes: u64 = minutes.parse().map_err(|_| invalid())?;
+            if hours > 23 || minutes > 59 {
+                return Err(invalid());
+            }
+            let since_midnight = local_time(now, utc_offset) % DAY_SECS;
+            match (hours * 3600 + minutes * 60 + DAY_SECS - since_midnight) % DAY_SECS {
+                0 => DAY_SECS,
+                delay => delay,
+            }
+        }
+        None => parse_delay(&input).ok_or_else(invalid)?,
+    };
+    if delay == 0 || delay > MAX_DELAY_SECS {
+        bail!("Timers can be due from 1 second to 30 days ahead");
+    }
+    Ok(now + delay)
+}
+
+/// Sums parts such as `1h` and `30m`
+fn parse_delay(input: &str) -> Option<u64> {
+    let mut total = 0u64;
+    let mut rest = input;
+    while !rest.is_empty() {
+        let unit_pos = rest.find(|c: char| !c.is_ascii_digit())?;
+        let value: u64 = rest[..unit_pos].parse().ok()?;
+        let unit = match rest[unit_pos..].chars().next()? {
+            'd' => DAY_SECS,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        total = total.checked_add(value.checked_mul(unit)?)?;
+        rest = &rest[unit_pos + 1..];
+    }
+    Some(total)
+}
+
+/// Parses a date and time such as `2024-05-04 19:00`, `utc_offset` minutes
+/// from UTC, into Unix time
+pub fn parse_date_time(input: &str, utc_offset: i32) -> anyhow::Result<u64> {
+    let invalid = || anyhow!("Expected a date and time such as 2024-05-04 19:00");
+    let (date, time) = input.trim().split_once(' ').ok_or_else(invalid)?;
+    let numbers = |s: &str, sep| -> anyhow::Result<Vec<u64>> {
+        s.trim()
+            .split(sep)
+            .map(|part| part.parse().map_err(|_| invalid()))
+            .collect()
+    };
+    let (date, time) = (numbers(date, '-')?, numbers(time, ':')?);
+    let ([year, month, day], [hours, minutes]) = (date.as_slice(), time.as_slice()) else {
+        return Err(invalid());
+    };
+    if !(1970..=9999).contains(year)
+        || !(1..=12).contains(month)
+        || *day == 0
+        || *hours > 23
+        || *minutes > 59
+    {
+        return Err(invalid());
+    }
+    let days = days_from_civil(*year, *month, *day);
+    // Days past the end of the month
+    if civil_from_days(days) != (*year, *month, *day) {
+        return Err(invalid());
+    }
+    let local = days * DAY_SECS + hours * 3600 + minutes * 60;
+    Ok(local.saturating_add_signed(-i64::from(utc_offset) * 60))
+}
+
+/// `2024-05-04 19:00`, `utc_offset` minutes from UTC
+pub fn format_date_time(time: u64, utc_offset: i32) -> String {
+    let local = local_time(time, utc_offset);
+    let (year, month, day) = civil_from_days(local / DAY_SECS);
+    let minutes = local % DAY_SECS / 60;
+    format!(
+        "{year}-{month:02}-{day:02} {:02}:{:02}",
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+/// Days since the Unix epoch of a date in the Gregorian calendar, from 1970.
+/// Days past the end of the month are carried over.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    // Years start in March, so that the leap day is the last one
+    let year = if month <= 2 { year - 1 } else { year };
+    let (era, year_of_era) = (year / 400, year % 400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    (era * 146_097 + day_of_era).saturating_sub(719_468)
+}
+
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let (era, day_of_era) = (days / 146_097, days % 146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = (shifted_month + 2) % 12 + 1;
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Time of day of `due_at` as `HH:MM`, and in how many days it is
+pub fn due_parts(due_at: u64, now: u64, utc_offset: i32) -> (String, u64) {
+    let local = local_time(due_at, utc_offset);
+    let minutes = local % DAY_SECS / 60;
+    let days = (local / DAY_SECS).saturating_sub(local_time(now, utc_offset) / DAY_SECS);
+    (format!("{:02}:{:02}", minutes / 60, minutes % 60), days)
+}
+
+/// `UTC`, `UTC+3` or `UTC-9:30`
+pub fn format_utc_offset(utc_offset: i32) -> String {
+    let sign = if utc_offset < 0 { '-' } else { '+' };
+    let (hours, minutes) = (utc_offset.abs() / 60, utc_offset.abs() % 60);
+    match (hours, minutes) {
+        (0, 0) => "UTC".to_owned(),
+        (hours, 0) => format!("UTC{sign}{hours}"),
+        (hours, minutes) => format!("UTC{sign}{hours}:{minutes:02}"),
+    }
+}
+
+fn local_time(time: u64, utc_offset: i32) -> u64 {
+    time.saturating_add_signed(i64::from(utc_offset) * 60)
+}
+
+/// Fires the due timers of every chat
+#[instrument(skip_all)]
+pub async fn fire_due_timers<M: Messenger + Clone>(
+    bot: &M,
+    storage: &Storage,
+) -> anyhow::Result<()> {
+    let now = unix_time();
+    for chat_id in storage.due_chats(now).await? {
+        let handler = BotHandler::scheduled(bot.clone(), storage, chat_id).await;
+        if let Err(err) = handler.handle_due_timers(now).await {
+            let err = err.context(format!("Error firing timers of chat {}", chat_id));
+            warn!("{:#}", err);
+            debug_err(&err).await;
+        }
+    }
+    Ok(())
+}
+
+/// Fires due timers until the process exits
+pub async fn run<M: Messenger + Clone>(bot: M, storage: Storage) {
+    info!("Starting scheduler...");
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = fire_due_timers(&bot, &storage).await {
+            warn!("Error firing timers: {:#}", err);
+            debug_err(&err).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2023-11-14 22:13:20 UTC
+    const NOW: u64 = 1_700_000_000;
+
+    #[test]
+    fn parses_delays_and_times_of_day() {
+        assert_eq!(parse_due("30m", NOW, 0).unwrap(), NOW + 30 * 60);
+        assert_eq!(parse_due("1h30m", NOW, 0).unwrap(), NOW + 90 * 60);
+        assert!(parse_due("30", NOW, 0).is_err());
+        assert!(parse_due("31d", NOW, 0).is_err());
+
+        let due = parse_due("23:00", NOW, 0).unwrap();
+        assert_eq!(due, NOW + 46 * 60 + 40);
+        assert_eq!(due_parts(due, NOW, 0), ("23:00".to_owned(), 0));
+        // Already the next day at UTC+3
+        let due = parse_due("02:00", NOW, 180).unwrap();
+        assert_eq!(due, NOW + 46 * 60 + 40);
+        assert_eq!(due_parts(due, NOW, 180), ("02:00".to_owned(), 0));
+        let due = parse_due("20:00", NOW, 0).unwrap();
+        assert_eq!(due_parts(due, NOW, 0), ("20:00".to_owned(), 1));
+        assert!(parse_due("24:00", NOW, 0).is_err());
+    }
+
+    #[test]
+    fn parses_dates() {
+        assert_eq!(parse_date_time("2023-11-14 22:13", 0).unwrap(), NOW - 20);
+        assert_eq!(parse_date_time("2023-11-15 01:13", 180).unwrap(), NOW - 20);
+        assert_eq!(format_date_time(NOW, 180), "2023-11-15 01:13");
+        assert_eq!(format_date_time(0, 0), "1970-01-01 00:00");
+        let leap_day = parse_date_time("2024-02-29 12:00", 0).unwrap();
+        assert_eq!(format_date_time(leap_day, 0), "2024-02-29 12:00");
+        assert!(parse_date_time("2023-02-29 12:00", 0).is_err());
+        assert!(parse_date_time("2023-11-14", 0).is_err());
+        assert!(parse_date_time("2023-11-14 25:00", 0).is_err());
+        assert!(parse_date_time("18446744073709551615-01-01 00:00", 0).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+use strum::{AsRefStr, EnumIter, EnumString};
+
+/// Stress cap set when the cap is first enabled from `/settings`
+const DEFAULT_STRESS_CAP: i32 = 9;
+const DEFAULT_CLOCK_SIZE: u16 = 4;
+const DEFAULT_PAGE_SIZE: u16 = 8;
+/// Keeps keyboards well below Telegram's 100 buttons
+const MAX_PAGE_SIZE: u16 = 20;
+/// Choices for the `+`/`-` buttons, cycled through from `/settings`
+const STEP_PRESETS: &[&[u16]] = &[&[1], &[1, 2], &[1, 3], &[1, 2, 3]];
+/// Range of UTC offsets in minutes, from UTC-12 to UTC+14
+const UTC_OFFSETS: std::ops::RangeInclusive<i32> = -720..=840;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, EnumString, AsRefStr, EnumIter)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Language {
+    En,
+    Ru,
+}
+
+impl Language {
+    /// Parses an IETF language tag such as `ru-RU`, as sent by Telegram
+    pub fn from_code(code: &str) -> Option<Self> {
+        let primary = code.split(['-', '_']).next()?;
+        primary.to_lowercase().parse().ok()
+    }
+}
+
+/// Per-chat preferences, stored with the tracker
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ChatSettings {
+    /// Post a chat message for every button change. When off, changes are
+    /// only shown as a popup to whoever pressed the button.
+    pub announce_changes: bool,
+    /// Highest stress a player can have, unlimited if not set
+    pub stress_cap: Option<i32>,
+    /// Start value of timers added without one
+    pub clock_size: u16,
+    /// Remove timers from the list once they fire
+    pub delete_fired_timers: bool,
+    /// Chat language, the language of each user if not set
+    pub language: Option<Language>,
+    /// Players or timers per keyboard page
+    pub page_size: u16,
+    /// Amounts of the `+`/`-` buttons next to each player or timer
+    pub steps: Vec<u16>,
+    /// Time zone of times of day given to real-time timers, in minutes
+    pub utc_offset: i32,
+}
+
+impl Default for ChatSettings {
+    fn default() -> Self {
+        Self {
+            announce_changes: true,
+            stress_cap: None,
+            clock_size: DEFAULT_CLOCK_SIZE,
+            delete_fired_timers: true,
+            language: None,
+            page_size: DEFAULT_PAGE_SIZE,
+            steps: STEP_PRESETS[0].to_vec(),
+            utc_offset: 0,
+        }
+    }
+}
+
+/// A single edit made from the `/settings` keyboard
+#[derive(Clone, Copy, Debug)]
+pub enum SettingChange {
+    ToggleAnnounceChanges,
+    StressCap(i32),
+    ClockSize(i32),
+    ToggleDeleteFiredTimers,
+    NextLanguage,
+    PageSize(i32),
+    NextSteps,
+    UtcOffset(i32),
+}
+
+impl ChatSettings {
+    pub fn apply(&mut self, change: SettingChange) {
+        match change {
+            SettingChange::ToggleAnnounceChanges => self.announce_changes = !self.announce_changes,
+            SettingChange::StressCap(delta) => {
+                self.stress_cap = match self.stress_cap {
+                    None if delta > 0 => Some(DEFAULT_STRESS_CAP),
+                    None => None,
+                    Some(cap) => Some(cap.saturating_add(delta)).filter(|cap| *cap > 0),
+                }
+            }
+            SettingChange::ClockSize(delta) => {
+                let size = i32::from(self.clock_size).saturating_add(delta);
+                self.clock_size = size.clamp(1, u16::MAX.into()) as u16;
+            }
+            SettingChange::ToggleDeleteFiredTimers => {
+                self.delete_fired_timers = !self.delete_fired_timers
+            }
+            SettingChange::NextLanguage => {
+                self.language = match self.language {
+                    None => Some(Language::En),
+                    Some(Language::En) => Some(Language::Ru),
+                    Some(Language::Ru) => None,
+                }
+            }
+            SettingChange::PageSize(delta) => {
+                let size = i32::from(self.page_size).saturating_add(delta);
+                self.page_size = size.clamp(1, MAX_PAGE_SIZE.into()) as u16;
+            }
+            SettingChange::NextSteps => {
+                let current = STEP_PRESETS
+                    .iter()
+                    .position(|steps| *steps == self.steps.as_slice());
+                let next = current.map_or(0, |pos| (pos + 1) % STEP_PRESETS.len());
+                self.steps = STEP_PRESETS[next].to_vec();
+            }
+            SettingChange::UtcOffset(delta) => {
+                let offset = self.utc_offset.saturating_add(delta);
+                self.utc_offset = offset.clamp(*UTC_OFFSETS.start(), *UTC_OFFSETS.end());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stress_cap_starts_at_default_and_turns_off_below_one() {
+        let mut settings = ChatSettings::default();
+        settings.apply(SettingChange::StressCap(-1));
+        assert_eq!(settings.stress_cap, None);
+
+        settings.apply(SettingChange::StressCap(1));
+        assert_eq!(settings.stress_cap, Some(DEFAULT_STRESS_CAP));
+
+        settings.stress_cap = Some(1);
+        settings.apply(SettingChange::StressCap(-1));
+        assert_eq!(settings.stress_cap, None);
+    }
+
+    #[test]
+    fn clock_size_stays_positive() {
+        let mut settings = ChatSettings {
+            clock_size: 1,
+            ..Default::default()
+        };
+        settings.apply(SettingChange::ClockSize(-1));
+        assert_eq!(settings.clock_size, 1);
+    }
+}
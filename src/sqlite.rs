@@ -0,0 +1,608 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Context};
+use rusqlite::{params, Connection, OptionalExtension};
+use teloxide::types::{ChatId, UpdateId, UserId};
+use tracing::{info, instrument};
+
+use crate::{
+    stats::{Roll, RollHistory, RollSource},
+    tracker::{Player, Timer, Tracker},
+};
+
+/// Telegram stops redelivering an update after a day
+const PROCESSED_UPDATE_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// Schema migrations, applied in order. The index of the last applied
+/// migration plus one is kept in `PRAGMA user_version`.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    "CREATE TABLE trackers (
+        chat_id INTEGER PRIMARY KEY,
+        state TEXT NOT NULL
+    );
+    CREATE TABLE players (
+        chat_id INTEGER NOT NULL,
+        id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        harm INTEGER NOT NULL,
+        stress INTEGER NOT NULL,
+        PRIMARY KEY (chat_id, id)
+    );
+    CREATE TABLE timers (
+        chat_id INTEGER NOT NULL,
+        id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        value INTEGER NOT NULL,
+        PRIMARY KEY (chat_id, id)
+    );
+    CREATE TABLE logs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        chat_id INTEGER NOT NULL,
+        created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+        user TEXT NOT NULL,
+        message TEXT NOT NULL
+    );
+    CREATE INDEX logs_chat_id ON logs (chat_id, created_at);",
+    // 2: chats each user has been seen in
+    "CREATE TABLE members (
+        user_id INTEGER NOT NULL,
+        chat_id INTEGER NOT NULL,
+        title TEXT NOT NULL,
+        PRIMARY KEY (user_id, chat_id)
+    );",
+    // 3: pinning and timer max
+    "ALTER TABLE players ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE timers ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE timers ADD COLUMN max INTEGER;",
+    // 4: real-time timers
+    "ALTER TABLE timers ADD COLUMN due_at INTEGER;
+    CREATE INDEX timers_due_at ON timers (due_at) WHERE due_at IS NOT NULL;",
+    // 5: handled updates, claimed before handling them
+    "CREATE TABLE processed_updates (
+        chat_id INTEGER NOT NULL,
+        update_id INTEGER NOT NULL,
+        created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+        PRIMARY KEY (chat_id, update_id)
+    );",
+    // 6: roll history out of the state document
+    "CREATE TABLE rolls (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        chat_id INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        sides INTEGER NOT NULL,
+        dice TEXT NOT NULL,
+        at INTEGER NOT NULL,
+        source TEXT NOT NULL
+    );
+    CREATE INDEX rolls_chat_id ON rolls (chat_id, id);
+    CREATE TABLE roll_tallies (
+        chat_id INTEGER NOT NULL,
+        session INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        tally TEXT NOT NULL,
+        PRIMARY KEY (chat_id, session, user_id)
+    );
+    INSERT INTO rolls (chat_id, user_id, sides, dice, at, source)
+    SELECT trackers.chat_id, roll.value ->> 'user_id', roll.value ->> 'sides',
+        roll.value -> 'values', roll.value ->> 'at', lower(roll.value ->> 'source')
+    FROM trackers, json_each(trackers.state, '$.rolls.recent') AS roll
+    ORDER BY trackers.chat_id, roll.key;
+    INSERT INTO roll_tallies (chat_id, session, user_id, tally)
+    SELECT trackers.chat_id, 1, tally.key, tally.value
+    FROM trackers, json_each(trackers.state, '$.rolls.session') AS tally;
+    INSERT INTO roll_tallies (chat_id, session, user_id, tally)
+    SELECT trackers.chat_id, 0, tally.key, tally.value
+    FROM trackers, json_each(trackers.state, '$.rolls.campaign') AS tally;
+    UPDATE trackers SET state = json_remove(state, '$.rolls');",
+];
+
+#[derive(Clone)]
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    #[instrument]
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        let mut conn =
+            Connection::open(path).with_context(|| format!("Error opening SQLite db {path}"))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    pub async fn get(&self, chat_id: ChatId) -> anyhow::Result<Tracker> {
+        self.with_conn(move |conn| load_tracker(conn, chat_id))
+            .await
+            .with_context(|| "Error fetching from SQLite")
+    }
+
+    /// Writes what changed since `stored`, the tracker as last read or
+    /// written by this process. Reads it first if not known.
+    pub async fn put(
+        &self,
+        chat_id: ChatId,
+        stored: Option<Tracker>,
+        tracker: &Tracker,
+    ) -> anyhow::Result<()> {
+        let tracker = tracker.clone();
+        self.with_conn(move |conn| {
+            let stored = match stored {
+                Some(stored) => stored,
+                None => load_tracker(conn, chat_id)?,
+            };
+            store_tracker(conn, chat_id, &stored, &tracker)
+        })
+        .await
+        .with_context(|| "Error writing to SQLite")
+    }
+
+    /// Records the update as handled. Returns false if it already was.
+    pub async fn claim_update(&self, chat_id: ChatId, update_id: UpdateId) -> anyhow::Result<bool> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "DELETE FROM processed_updates
+                 WHERE chat_id = ?1 AND created_at < strftime('%s', 'now') - ?2",
+                params![chat_id.0, PROCESSED_UPDATE_TTL_SECS],
+            )?;
+            let inserted = conn.execute(
+                "INSERT INTO processed_updates (chat_id, update_id) VALUES (?1, ?2)
+                 ON CONFLICT DO NOTHING",
+                params![chat_id.0, update_id.0],
+            )?;
+            Ok(inserted == 1)
+        })
+        .await
+        .with_context(|| "Error claiming update in SQLite")
+    }
+
+    pub async fn log(&self, chat_id: ChatId, user: &str, message: &str) -> anyhow::Result<()> {
+        let (user, message) = (user.to_owned(), message.to_owned());
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO logs (chat_id, user, message) VALUES (?1, ?2, ?3)",
+                params![chat_id.0, user, message],
+            )?;
+            Ok(())
+        })
+        .await
+        .with_context(|| "Error writing log to SQLite")
+    }
+
+    pub async fn due_chats(&self, now: u64) -> anyhow::Result<Vec<ChatId>> {
+        self.with_conn(move |conn| {
+            let mut stmt =
+                conn.prepare("SELECT DISTINCT chat_id FROM timers WHERE due_at <= ?1")?;
+            let chats = stmt
+                .query_map(params![now], |row| Ok(ChatId(row.get(0)?)))?
+                .collect::<Result<_, _>>()?;
+            Ok(chats)
+        })
+        .await
+        .with_context(|| "Error fetching due chats from SQLite")
+    }
+
+    pub async fn add_member(
+        &self,
+        user_id: UserId,
+        chat_id: ChatId,
+        title: &str,
+    ) -> anyhow::Result<()> {
+        let title = title.to_owned();
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO members (user_id, chat_id, title) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (user_id, chat_id) DO UPDATE SET title = excluded.title",
+                params![user_id.0, chat_id.0, title],
+            )?;
+            Ok(())
+        })
+        .await
+        .with_context(|| "Error writing member to SQLite")
+    }
+
+    pub async fn member_chats(&self, user_id: UserId) -> anyhow::Result<Vec<(ChatId, String)>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn
+                .prepare("SELECT chat_id, title FROM members WHERE user_id = ?1 ORDER BY title")?;
+            let chats = stmt
+                .query_map(params![user_id.0], |row| {
+                    Ok((ChatId(row.get(0)?), row.get(1)?))
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(chats)
+        })
+        .await
+        .with_context(|| "Error fetching member chats from SQLite")
+    }
+
+    async fn with_conn<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> anyhow::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn
+                .lock()
+                .map_err(|_| anyhow!("SQLite connection mutex poisoned"))?;
+            f(&mut conn)
+        })
+        .await?
+    }
+}
+
+fn migrate(conn: &mut Connection) -> anyhow::Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (idx, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        info!("Applying SQLite migration {}", idx + 1);
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)
+            .with_context(|| format!("Error applying migration {}", idx + 1))?;
+        tx.pragma_update(None, "user_version", idx + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn load_tracker(conn: &Connection, chat_id: ChatId) -> anyhow::Result<Tracker> {
+    let state: Option<String> = conn
+        .query_row(
+            "SELECT state FROM trackers WHERE chat_id = ?1",
+            params![chat_id.0],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let mut tracker = match state {
+        Some(state) => serde_json::from_str(&state)?,
+        None => Tracker::new(),
+    };
+
+    let mut stmt =
+        conn.prepare("SELECT id, name, harm, stress, pinned FROM players WHERE chat_id = ?1")?;
+    tracker.players = stmt
+        .query_map(params![chat_id.0], |row| {
+            Ok(Player {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                harm: row.get(2)?,
+                stress: row.get(3)?,
+                pinned: row.get(4)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+
+    let mut stmt =
+        conn.prepare("SELECT id, name, value, max, pinned, due_at FROM timers WHERE chat_id = ?1")?;
+    tracker.timers = stmt
+        .query_map(params![chat_id.0], |row| {
+            Ok(Timer {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                value: row.get(2)?,
+                max: row.get(3)?,
+                pinned: row.get(4)?,
+                due_at: row.get(5)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    tracker.sort();
+
+    let mut stmt = conn.prepare(
+        "SELECT user_id, sides, dice, at, source FROM rolls WHERE chat_id = ?1 ORDER BY id",
+    )?;
+    tracker.rolls.recent = stmt
+        .query_map(params![chat_id.0], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get::<_, String>(2)?,
+                row.get(3)?,
+                row.get::<_, String>(4)?,
+            ))
+        })?
+        .map(|row| {
+            let (user_id, sides, dice, at, source) = row?;
+            Ok(Roll {
+                user_id: UserId(user_id),
+                sides,
+                values: serde_json::from_str(&dice)?,
+                at,
+                source: parse_source(&source)?,
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    let mut stmt =
+        conn.prepare("SELECT session, user_id, tally FROM roll_tallies WHERE chat_id = ?1")?;
+    let tallies = stmt
+        .query_map(params![chat_id.0], |row| {
+            Ok((
+                row.get::<_, bool>(0)?,
+                row.get(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (session, user_id, tally) in tallies {
+        let tallies = match session {
+            true => &mut tracker.rolls.session,
+            false => &mut tracker.rolls.campaign,
+        };
+        tallies.insert(user_id, serde_json::from_str(&tally)?);
+    }
+
+    Ok(tracker)
+}
+
+/// Everything kept in `trackers.state`, which is all but what has its own
+/// tables
+fn state_json(tracker: &Tracker) -> anyhow::Result<String> {
+    Ok(serde_json::to_string(&Tracker {
+        players: Vec::new(),
+        timers: Vec::new(),
+        rolls: RollHistory::default(),
+        ..tracker.clone()
+    })?)
+}
+
+fn source_name(source: RollSource) -> &'static str {
+    match source {
+        RollSource::Telegram => "telegram",
+        RollSource::Bot => "bot",
+    }
+}
+
+fn parse_source(name: &str) -> anyhow::Result<RollSource> {
+    match name {
+        "telegram" => Ok(RollSource::Telegram),
+        "bot" => Ok(RollSource::Bot),
+        _ => Err(anyhow!("Unknown roll source {name}")),
+    }
+}
+
+/// Writes the rows of `tracker` which differ from `stored`. Players, timers
+/// and rolls live in their own tables, everything else is stored as a JSON
+/// document in `trackers.state`.
+fn store_tracker(
+    conn: &mut Connection,
+    chat_id: ChatId,
+    stored: &Tracker,
+    tracker: &Tracker,
+) -> anyhow::Result<()> {
+    let state = state_json(tracker)?;
+    let tx = conn.transaction()?;
+    if state != state_json(stored)? {
+        tx.execute(
+            "INSERT INTO trackers (chat_id, state) VALUES (?1, ?2)
+             ON CONFLICT (chat_id) DO UPDATE SET state = excluded.state",
+            params![chat_id.0, state],
+        )?;
+    }
+
+    let stored_players = stored
+        .players
+        .iter()
+        .map(|player| (player.id, player))
+        .collect::<HashMap<_, _>>();
+    {
+        let mut upsert = tx.prepare(
+            "INSERT INTO players (chat_id, id, name, harm, stress, pinned)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT (chat_id, id) DO UPDATE SET name = excluded.name,
+                harm = excluded.harm, stress = excluded.stress, pinned = excluded.pinned",
+        )?;
+        for player in tracker.players.iter() {
+            if stored_players.get(&player.id) != Some(&player) {
+                upsert.execute(params![
+                    chat_id.0,
+                    player.id,
+                    player.name,
+                    player.harm,
+                    player.stress,
+                    player.pinned
+                ])?;
+            }
+        }
+        let mut delete = tx.prepare("DELETE FROM players WHERE chat_id = ?1 AND id = ?2")?;
+        for id in stored_players.keys() {
+            if !tracker.players.iter().any(|player| player.id == *id) {
+                delete.execute(params![chat_id.0, id])?;
+            }
+        }
+    }
+
+    let stored_timers = stored
+        .timers
+        .iter()
+        .map(|timer| (timer.id, timer))
+        .collect::<HashMap<_, _>>();
+    {
+        let mut upsert = tx.prepare(
+            "INSERT INTO timers (chat_id, id, name, value, max, pinned, due_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT (chat_id, id) DO UPDATE SET name = excluded.name,
+                value = excluded.value, max = excluded.max, pinned = excluded.pinned,
+                due_at = excluded.due_at",
+        )?;
+        for timer in tracker.timers.iter() {
+            if stored_timers.get(&timer.id) != Some(&timer) {
+                upsert.execute(params![
+                    chat_id.0,
+                    timer.id,
+                    timer.name,
+                    timer.value,
+                    timer.max,
+                    timer.pinned,
+                    timer.due_at
+                ])?;
+            }
+        }
+        let mut delete = tx.prepare("DELETE FROM timers WHERE chat_id = ?1 AND id = ?2")?;
+        for id in stored_timers.keys() {
+            if !tracker.timers.iter().any(|timer| timer.id == *id) {
+                delete.execute(params![chat_id.0, id])?;
+            }
+        }
+    }
+
+    store_rolls(&tx, chat_id, &stored.rolls, &tracker.rolls)?;
+    tx.commit()?;
+    Ok(())
+}
+
+fn store_rolls(
+    tx: &rusqlite::Transaction,
+    chat_id: ChatId,
+    stored: &RollHistory,
+    history: &RollHistory,
+) -> anyhow::Result<()> {
+    // Rolls are added at the end and dropped from the start, so the stored
+    // rolls which are kept come first in the new history
+    let dropped = (0..=stored.recent.len())
+        .find(|&dropped| {
+            let kept = stored.recent.range(dropped..);
+            let len = kept.len();
+            len <= history.recent.len() && kept.eq(history.recent.range(..len))
+        })
+        .unwrap_or_default();
+    tx.execute(
+        "DELETE FROM rolls WHERE id IN
+            (SELECT id FROM rolls WHERE chat_id = ?1 ORDER BY id LIMIT ?2)",
+        params![chat_id.0, dropped],
+    )?;
+    let mut insert = tx.prepare(
+        "INSERT INTO rolls (chat_id, user_id, sides, dice, at, source)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    for roll in history.recent.range(stored.recent.len() - dropped..) {
+        insert.execute(params![
+            chat_id.0,
+            roll.user_id.0,
+            roll.sides,
+            serde_json::to_string(&roll.values)?,
+            roll.at,
+            source_name(roll.source)
+        ])?;
+    }
+
+    let mut upsert = tx.prepare(
+        "INSERT INTO roll_tallies (chat_id, session, user_id, tally) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (chat_id, session, user_id) DO UPDATE SET tally = excluded.tally",
+    )?;
+    let mut delete = tx
+        .prepare("DELETE FROM roll_tallies WHERE chat_id = ?1 AND session = ?2 AND user_id = ?3")?;
+    let scopes = [
+        (true, &stored.session, &history.session),
+        (false, &stored.campaign, &history.campaign),
+    ];
+    for (session, stored, tallies) in scopes {
+        for (user_id, tally) in tallies {
+            if stored.get(user_id) != Some(tally) {
+                upsert.execute(params![
+                    chat_id.0,
+                    session,
+                    user_id,
+                    serde_json::to_string(tally)?
+                ])?;
+            }
+        }
+        for user_id in stored.keys().filter(|id| !tallies.contains_key(id)) {
+            delete.execute(params![chat_id.0, session, user_id])?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rows written since the connection was opened
+    fn changes(conn: &Connection) -> i64 {
+        conn.query_row("SELECT total_changes()", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    fn roll(user_id: u64, values: &[u32], at: u64) -> Roll {
+        Roll {
+            user_id: UserId(user_id),
+            sides: 6,
+            values: values.to_vec(),
+            at,
+            source: RollSource::Telegram,
+        }
+    }
+
+    #[test]
+    fn writes_only_changed_rows() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        let chat_id = ChatId(1);
+        let mut tracker = Tracker::new();
+        for name in ["Alice", "Bob", "Carol"] {
+            tracker.create_player(name).unwrap();
+        }
+        tracker.create_timer("Heist", 4).unwrap();
+        store_tracker(&mut conn, chat_id, &Tracker::new(), &tracker).unwrap();
+
+        let before = changes(&conn);
+        store_tracker(&mut conn, chat_id, &tracker.clone(), &tracker).unwrap();
+        assert_eq!(changes(&conn), before);
+
+        let stored = tracker.clone();
+        let bob = tracker.players[1].id;
+        tracker.change_stress(bob, 2).unwrap();
+        let carol = tracker.players[2].id;
+        tracker.delete_player(carol).unwrap();
+        store_tracker(&mut conn, chat_id, &stored, &tracker).unwrap();
+        // Bob's row, Carol's row and the state with the new revision
+        assert_eq!(changes(&conn), before + 3);
+        assert_eq!(load_tracker(&conn, chat_id).unwrap(), tracker);
+
+        // A roll adds its row and updates the tallies, leaving the state
+        let before = changes(&conn);
+        let stored = tracker.clone();
+        tracker.rolls.record("Alice", roll(7, &[6, 2], 100));
+        store_tracker(&mut conn, chat_id, &stored, &tracker).unwrap();
+        assert_eq!(changes(&conn), before + 3);
+
+        // Old rolls are dropped from the table along with the history
+        for at in 0..150 {
+            let stored = tracker.clone();
+            tracker.rolls.record("Bob", roll(8, &[3], 200 + at));
+            store_tracker(&mut conn, chat_id, &stored, &tracker).unwrap();
+        }
+        assert_eq!(load_tracker(&conn, chat_id).unwrap(), tracker);
+    }
+
+    #[test]
+    fn moves_rolls_out_of_the_state() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let tx = conn.transaction().unwrap();
+        tx.execute_batch(&MIGRATIONS[..5].join(";")).unwrap();
+        tx.pragma_update(None, "user_version", 5).unwrap();
+        tx.commit().unwrap();
+        let mut tracker = Tracker::new();
+        tracker.rolls.record("Alice", roll(7, &[6, 2], 100));
+        tracker.rolls.record("Bob", roll(8, &[1], 200));
+        conn.execute(
+            "INSERT INTO trackers (chat_id, state) VALUES (1, ?1)",
+            params![serde_json::to_string(&tracker).unwrap()],
+        )
+        .unwrap();
+
+        migrate(&mut conn).unwrap();
+        assert_eq!(load_tracker(&conn, ChatId(1)).unwrap(), tracker);
+        let state: String = conn
+            .query_row("SELECT state FROM trackers", [], |row| row.get(0))
+            .unwrap();
+        assert!(!state.contains("rolls"));
+    }
+}
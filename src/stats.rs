@@ -0,0 +1,193 @@
+//! Roll history and dice statistics of a chat, see `/stats`.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+use teloxide::types::UserId;
+
+use crate::dice::{ActionRoll, Outcome};
+
+/// Rolls kept in the history of a chat
+const HISTORY_LEN: usize = 100;
+/// A roll after this long without rolls starts a new session
+const SESSION_GAP_SECS: u64 = 6 * 60 * 60;
+/// Fewer dice say nothing about luck
+const MIN_CURSED_DICE: u32 = 5;
+const CURSED_LEADERBOARD_LEN: usize = 3;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum RollSource {
+    /// Animated dice rolled by Telegram
+    Telegram,
+    /// Rolled by the bot itself
+    Bot,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Roll {
+    pub user_id: UserId,
+    pub sides: u32,
+    pub values: Vec<u32>,
+    /// Unix time of the roll
+    pub at: u64,
+    pub source: RollSource,
+}
+
+/// Dice of one size rolled by a user
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct DieTally {
+    pub dice: u32,
+    pub total: u64,
+}
+
+impl DieTally {
+    pub fn average(&self) -> f64 {
+        self.total as f64 / f64::from(self.dice.max(1))
+    }
+}
+
+/// Dice rolled by one user
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Tally {
+    /// Player or user name at the last roll
+    pub name: String,
+    #[serde(default)]
+    pub rolls: u32,
+    pub dice: u32,
+    pub total: u64,
+    /// Sum of the sides of the dice, for the expected total
+    pub sides: u64,
+    /// By the number of sides, for the average of each die size
+    #[serde(default)]
+    pub by_sides: BTreeMap<u32, DieTally>,
+    /// Action rolls with Telegram dice, and those of them which were crits:
+    /// two or more sixes. Dice showing their highest face were once stored
+    /// as `crits`, so that name is not reused.
+    #[serde(default)]
+    pub action_rolls: u32,
+    #[serde(default)]
+    pub action_crits: u32,
+}
+
+impl Tally {
+    fn add(&mut self, name: &str, roll: &Roll) {
+        name.clone_into(&mut self.name);
+        self.rolls += 1;
+        let die = self.by_sides.entry(roll.sides).or_default();
+        for value in &roll.values {
+            self.dice += 1;
+            self.total += u64::from(*value);
+            self.sides += u64::from(roll.sides);
+            die.dice += 1;
+            die.total += u64::from(*value);
+        }
+        if roll.source == RollSource::Telegram {
+            let action = ActionRoll {
+                pool: roll.values.len() as u32,
+                dice: roll.values.clone(),
+            };
+            self.action_rolls += 1;
+            self.action_crits += u32::from(action.outcome() == Outcome::Critical);
+        }
+    }
+
+    /// Total rolled as a share of the expected total, below 1 when unlucky.
+    /// Unlike the average, comparable across dice with different sides.
+    pub fn luck(&self) -> f64 {
+        let expected = (self.sides + u64::from(self.dice)) as f64 / 2.0;
+        self.total as f64 / expected.max(1.0)
+    }
+}
+
+/// Recent rolls of a chat, and the dice of each user in the session and in
+/// the whole campaign. Stored with the tracker.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RollHistory {
+    /// Oldest first
+    pub recent: VecDeque<Roll>,
+    /// By user id
+    pub session: BTreeMap<u64, Tally>,
+    pub campaign: BTreeMap<u64, Tally>,
+}
+
+impl RollHistory {
+    /// Adds a roll made by `name`. A roll long after the previous one starts
+    /// a new session.
+    pub fn record(&mut self, name: &str, roll: Roll) {
+        if self
+            .recent
+            .back()
+            .is_some_and(|last| roll.at > last.at + SESSION_GAP_SECS)
+        {
+            self.session.clear();
+        }
+        for tallies in [&mut self.session, &mut self.campaign] {
+            tallies.entry(roll.user_id.0).or_default().add(name, &roll);
+        }
+        self.recent.push_back(roll);
+        if self.recent.len() > HISTORY_LEN {
+            self.recent.pop_front();
+        }
+    }
+}
+
+/// Tallies with the most dice first
+pub fn by_dice(tallies: &BTreeMap<u64, Tally>) -> Vec<&Tally> {
+    let mut tallies = tallies.values().collect::<Vec<_>>();
+    tallies.sort_by(|a, b| b.dice.cmp(&a.dice).then_with(|| a.name.cmp(&b.name)));
+    tallies
+}
+
+/// The unluckiest users who rolled enough dice, unluckiest first
+pub fn cursed(tallies: &BTreeMap<u64, Tally>) -> Vec<&Tally> {
+    let mut cursed = tallies
+        .values()
+        .filter(|tally| tally.dice >= MIN_CURSED_DICE && tally.luck() < 1.0)
+        .collect::<Vec<_>>();
+    cursed.sort_by(|a, b| a.luck().total_cmp(&b.luck()));
+    cursed.truncate(CURSED_LEADERBOARD_LEN);
+    cursed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roll(user_id: u64, values: &[u32], at: u64) -> Roll {
+        Roll {
+            user_id: UserId(user_id),
+            sides: 6,
+            values: values.to_vec(),
+            at,
+            source: RollSource::Telegram,
+        }
+    }
+
+    #[test]
+    fn tallies_sessions_and_campaign() {
+        let mut history = RollHistory::default();
+        history.record("Alice", roll(1, &[1, 2, 1], 100));
+        history.record("Bob", roll(2, &[6, 6, 5, 4, 6], 200));
+        history.record("Alice", roll(1, &[2, 1], 300));
+
+        let alice = &history.session[&1];
+        assert_eq!((alice.rolls, alice.dice, alice.total), (2, 5, 7));
+        assert_eq!(alice.luck(), 7.0 / 17.5);
+        assert_eq!(alice.by_sides[&6].average(), 1.4);
+        // Only Bob's roll of three sixes was a crit
+        let bob = &history.campaign[&2];
+        assert_eq!((bob.action_rolls, bob.action_crits), (1, 1));
+        assert_eq!(alice.action_crits, 0);
+        let names =
+            |tallies: Vec<&Tally>| tallies.iter().map(|t| t.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(by_dice(&history.session)), ["Alice", "Bob"]);
+        assert_eq!(names(cursed(&history.session)), ["Alice"]);
+
+        // A new session starts after a long break
+        history.record("Bob", roll(2, &[3], 300 + SESSION_GAP_SECS + 1));
+        assert_eq!(history.session.len(), 1);
+        assert_eq!(history.session[&2].dice, 1);
+        assert_eq!(history.campaign[&2].dice, 6);
+        assert_eq!(history.recent.len(), 4);
+    }
+}
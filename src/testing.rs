@@ -0,0 +1,421 @@
+//! Test doubles for driving the handlers without Telegram.
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde_json::json;
+use teloxide::types::{
+    ChatId, InlineKeyboardMarkup, InlineQueryResult, MessageId, Update, UpdateKind,
+};
+
+use crate::{
+    callback::{Callback, CallbackAction},
+    context::Storage,
+    dispatcher::{dispatch_callback, dispatch_command},
+    fair::RollRng,
+    handler::BotHandler,
+    inline::handle_inline,
+    messenger::{MessageGone, Messenger},
+    tracker::Tracker,
+};
+
+pub const BOT_USERNAME: &str = "dnd_bot";
+pub const USER_ID: u64 = 100;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sent {
+    Message {
+        msg_id: MessageId,
+        text: String,
+        markup: Option<InlineKeyboardMarkup>,
+    },
+    EditText {
+        msg_id: MessageId,
+        text: String,
+        markup: Option<InlineKeyboardMarkup>,
+    },
+    EditMarkup {
+        msg_id: MessageId,
+        markup: InlineKeyboardMarkup,
+    },
+    Delete {
+        msg_id: MessageId,
+    },
+    Pin {
+        msg_id: MessageId,
+    },
+    Unpin {
+        msg_id: MessageId,
+    },
+    ForceReply {
+        msg_id: MessageId,
+        text: String,
+    },
+    Reply {
+        msg_id: MessageId,
+        reply_to: MessageId,
+        text: String,
+    },
+    Dice {
+        msg_id: MessageId,
+        value: u32,
+    },
+    CallbackAnswer {
+        text: Option<String>,
+        show_alert: bool,
+    },
+    InlineAnswer {
+        results: Vec<InlineQueryResult>,
+        cache_time: u32,
+    },
+}
+
+#[derive(Default)]
+struct Recorded {
+    sent: Vec<Sent>,
+    last_msg_id: i32,
+    deleted: HashSet<MessageId>,
+    rolls: u64,
+}
+
+/// [`Messenger`] which records every call and hands out increasing message ids.
+#[derive(Clone, Default)]
+pub struct RecordingMessenger {
+    recorded: Arc<Mutex<Recorded>>,
+}
+
+impl RecordingMessenger {
+    /// Returns and forgets everything sent so far.
+    pub fn take(&self) -> Vec<Sent> {
+        std::mem::take(&mut self.recorded.lock().unwrap().sent)
+    }
+
+    /// Texts of new messages sent so far, without forgetting them.
+    pub fn messages(&self) -> Vec<String> {
+        self.recorded
+            .lock()
+            .unwrap()
+            .sent
+            .iter()
+            .filter_map(|sent| match sent {
+                Sent::Message { text, .. } | Sent::Reply { text, .. } => Some(text.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Deletes a message as a user would, without the bot knowing.
+    pub fn delete_by_user(&self, msg_id: MessageId) {
+        self.recorded.lock().unwrap().deleted.insert(msg_id);
+    }
+
+    fn check_exists(&self, msg_id: MessageId) -> anyhow::Result<()> {
+        if self.recorded.lock().unwrap().deleted.contains(&msg_id) {
+            return Err(MessageGone.into());
+        }
+        Ok(())
+    }
+
+    fn record(&self, f: impl FnOnce(MessageId) -> Sent) -> MessageId {
+        let mut recorded = self.recorded.lock().unwrap();
+        recorded.last_msg_id += 1;
+        let msg_id = MessageId(recorded.last_msg_id);
+        recorded.sent.push(f(msg_id));
+        msg_id
+    }
+}
+
+impl Messenger for RecordingMessenger {
+    async fn send_message(
+        &self,
+        _chat_id: ChatId,
+        text: String,
+        markup: Option<InlineKeyboardMarkup>,
+    ) -> anyhow::Result<MessageId> {
+        Ok(self.record(|msg_id| Sent::Message {
+            msg_id,
+            text,
+            markup,
+        }))
+    }
+
+    async fn edit_message_text(
+        &self,
+        _chat_id: ChatId,
+        msg_id: MessageId,
+        text: String,
+        markup: Option<InlineKeyboardMarkup>,
+    ) -> anyhow::Result<()> {
+        self.check_exists(msg_id)?;
+        self.record(|_| Sent::EditText {
+            msg_id,
+            text,
+            markup,
+        });
+        Ok(())
+    }
+
+    async fn edit_message_reply_markup(
+        &self,
+        _chat_id: ChatId,
+        msg_id: MessageId,
+        markup: InlineKeyboardMarkup,
+    ) -> anyhow::Result<()> {
+        self.check_exists(msg_id)?;
+        self.record(|_| Sent::EditMarkup { msg_id, markup });
+        Ok(())
+    }
+
+    async fn delete_message(&self, _chat_id: ChatId, msg_id: MessageId) -> anyhow::Result<()> {
+        self.check_exists(msg_id)?;
+        self.recorded.lock().unwrap().deleted.insert(msg_id);
+        self.record(|_| Sent::Delete { msg_id });
+        Ok(())
+    }
+
+    async fn pin_message(&self, _chat_id: ChatId, msg_id: MessageId) -> anyhow::Result<()> {
+        self.record(|_| Sent::Pin { msg_id });
+        Ok(())
+    }
+
+    async fn unpin_message(&self, _chat_id: ChatId, msg_id: MessageId) -> anyhow::Result<()> {
+        self.record(|_| Sent::Unpin { msg_id });
+        Ok(())
+    }
+
+    async fn send_force_reply(&self, _chat_id: ChatId, text: String) -> anyhow::Result<MessageId> {
+        Ok(self.record(|msg_id| Sent::ForceReply { msg_id, text }))
+    }
+
+    async fn send_reply(
+        &self,
+        _chat_id: ChatId,
+        reply_to: MessageId,
+        text: String,
+    ) -> anyhow::Result<MessageId> {
+        Ok(self.record(|msg_id| Sent::Reply {
+            msg_id,
+            reply_to,
+            text,
+        }))
+    }
+
+    /// Dice show 1 to 6 in turn, going by the message id
+    async fn send_dice(&self, _chat_id: ChatId) -> anyhow::Result<(MessageId, u32)> {
+        let mut value = 0;
+        let msg_id = self.record(|msg_id| {
+            value = (msg_id.0 - 1) as u32 % 6 + 1;
+            Sent::Dice { msg_id, value }
+        });
+        Ok((msg_id, value))
+    }
+
+    fn dice_animation(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Seeded anew for every roll, so that tests know the values
+    fn roll_rng(&self) -> RollRng {
+        let mut recorded = self.recorded.lock().unwrap();
+        recorded.rolls += 1;
+        RollRng::seeded(b"test", recorded.rolls)
+    }
+
+    async fn answer_callback_query(
+        &self,
+        _callback_id: String,
+        text: Option<String>,
+        show_alert: bool,
+    ) -> anyhow::Result<()> {
+        self.record(|_| Sent::CallbackAnswer { text, show_alert });
+        Ok(())
+    }
+
+    async fn answer_inline_query(
+        &self,
+        _query_id: String,
+        results: Vec<InlineQueryResult>,
+        cache_time: u32,
+    ) -> anyhow::Result<()> {
+        self.record(|_| Sent::InlineAnswer {
+            results,
+            cache_time,
+        });
+        Ok(())
+    }
+
+    async fn username(&self) -> anyhow::Result<String> {
+        Ok(BOT_USERNAME.to_owned())
+    }
+}
+
+/// A group chat backed by an in-memory store and a [`RecordingMessenger`].
+pub struct TestChat {
+    pub bot: RecordingMessenger,
+    pub storage: Storage,
+    pub chat_id: ChatId,
+    next_update_id: u32,
+}
+
+impl TestChat {
+    pub fn new() -> Self {
+        Self {
+            bot: RecordingMessenger::default(),
+            storage: Storage::sqlite(":memory:").unwrap(),
+            chat_id: ChatId(-1001),
+            next_update_id: 1,
+        }
+    }
+
+    /// Sends `text` as a message from the test user.
+    pub async fn command(&mut self, text: &str) -> anyhow::Result<()> {
+        let update = self.message_update(text);
+        let handler = BotHandler::new(self.bot.clone(), &self.storage, &update).await?;
+        let ret = match &update.kind {
+            UpdateKind::Message(msg) => dispatch_command(&handler, msg).await,
+            _ => unreachable!(),
+        };
+        handler.run_deferred().await;
+        ret
+    }
+
+    /// Sends `text` as a reply to the bot message `reply_to`.
+    pub async fn reply(&mut self, reply_to: MessageId, text: &str) -> anyhow::Result<()> {
+        let update_id = self.next_update_id();
+        let mut message = self.message_json(update_id as i32, text);
+        message["reply_to_message"] = self.message_json(reply_to.0, "prompt");
+        let update = parse_update(json!({ "update_id": update_id, "message": message }));
+        let handler = BotHandler::new(self.bot.clone(), &self.storage, &update).await?;
+        let ret = match &update.kind {
+            UpdateKind::Message(msg) => dispatch_command(&handler, msg).await,
+            _ => unreachable!(),
+        };
+        handler.run_deferred().await;
+        ret
+    }
+
+    /// Presses a button of a keyboard made for the current tracker.
+    pub async fn tap(&mut self, item_id: usize, action: CallbackAction) -> anyhow::Result<()> {
+        let tracker = self.tracker().await;
+        let revision = match action {
+            CallbackAction::AddTimer
+            | CallbackAction::SubTimer
+            | CallbackAction::DeleteTimer
+            | CallbackAction::PinTimer
+            | CallbackAction::RenameTimer => tracker.timers_revision,
+            CallbackAction::AddHarm
+            | CallbackAction::SubHarm
+            | CallbackAction::AddStress
+            | CallbackAction::SubStress
+            | CallbackAction::DeletePlayer
+            | CallbackAction::PinPlayer
+            | CallbackAction::RenamePlayer
+            | CallbackAction::ResetStress => tracker.players_revision,
+            CallbackAction::Confirm | CallbackAction::CancelConfirm => {
+                tracker.pending_confirm.map_or(0, |pending| pending.id)
+            }
+            CallbackAction::Vote | CallbackAction::ClosePoll => {
+                tracker.session_poll.map_or(0, |poll| poll.id)
+            }
+            _ => 0,
+        };
+        let callback = Callback {
+            item_id,
+            action,
+            revision,
+            value: 1,
+        };
+        self.tap_data(&callback.serialize(self.chat_id)).await
+    }
+
+    /// Presses an inline button carrying `data`.
+    pub async fn tap_data(&mut self, data: &str) -> anyhow::Result<()> {
+        let update = self.callback_update(data);
+        let handler = BotHandler::new(self.bot.clone(), &self.storage, &update).await?;
+        let ret = match &update.kind {
+            UpdateKind::CallbackQuery(cb) => dispatch_callback(&handler, cb).await,
+            _ => unreachable!(),
+        };
+        handler.run_deferred().await;
+        ret
+    }
+
+    /// Types `@bot query` in any chat.
+    pub async fn inline(&mut self, query: &str) -> anyhow::Result<()> {
+        let update_id = self.next_update_id();
+        let update = parse_update(json!({
+            "update_id": update_id,
+            "inline_query": {
+                "id": format!("iq{update_id}"),
+                "from": user_json(),
+                "query": query,
+                "offset": "",
+            },
+        }));
+        match &update.kind {
+            UpdateKind::InlineQuery(inline) => {
+                handle_inline(&self.bot, &self.storage, inline).await
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub async fn tracker(&self) -> Tracker {
+        self.storage
+            .context(self.chat_id)
+            .await
+            .get()
+            .await
+            .unwrap()
+    }
+
+    pub fn message_update(&mut self, text: &str) -> Update {
+        let update_id = self.next_update_id();
+        parse_update(json!({
+            "update_id": update_id,
+            "message": self.message_json(update_id as i32, text),
+        }))
+    }
+
+    pub fn callback_update(&mut self, data: &str) -> Update {
+        let update_id = self.next_update_id();
+        parse_update(json!({
+            "update_id": update_id,
+            "callback_query": {
+                "id": format!("cb{update_id}"),
+                "from": user_json(),
+                "chat_instance": "instance",
+                "data": data,
+                "message": self.message_json(update_id as i32, "*Manage:*"),
+            },
+        }))
+    }
+
+    fn next_update_id(&mut self) -> u32 {
+        self.next_update_id += 1;
+        self.next_update_id
+    }
+
+    fn message_json(&self, msg_id: i32, text: &str) -> serde_json::Value {
+        json!({
+            "message_id": msg_id,
+            "date": 1_700_000_000,
+            "chat": { "id": self.chat_id.0, "type": "group", "title": "Table" },
+            "from": user_json(),
+            "text": text,
+        })
+    }
+}
+
+/// Updates are parsed from text as Telegram sends them: teloxide's
+/// `UpdateKind` does not deserialize from a `serde_json::Value`.
+fn parse_update(value: serde_json::Value) -> Update {
+    serde_json::from_str(&value.to_string()).unwrap()
+}
+
+fn user_json() -> serde_json::Value {
+    json!({ "id": USER_ID, "is_bot": false, "first_name": "Tester", "username": "tester" })
+}
@@ -2,21 +2,33 @@ use anyhow::{anyhow, bail};
 use serde::{Deserialize, Serialize};
 use teloxide::types::MessageId;
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+use crate::{fair::FairSession, poll::SessionPoll, settings::ChatSettings, stats::RollHistory};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Timer {
-    // Should be first for sorting purposes
     pub name: String,
     pub id: usize,
     pub value: i32,
+    /// Highest value the timer can be ticked up to, unlimited if not set
+    #[serde(default)]
+    pub max: Option<i32>,
+    /// Listed before timers which are not pinned
+    #[serde(default)]
+    pub pinned: bool,
+    /// Unix time at which a real-time timer fires, see [`crate::scheduler`]
+    #[serde(default)]
+    pub due_at: Option<u64>,
 }
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Player {
-    // Should be first for sorting purposes
     pub name: String,
     pub id: usize,
     pub harm: i32,
     pub stress: i32,
+    /// Listed before players who are not pinned
+    #[serde(default)]
+    pub pinned: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -24,22 +36,115 @@ struct Timers(Vec<Timer>);
 #[derive(Serialize, Deserialize)]
 struct Players(Vec<Player>);
 
-#[derive(Serialize, Deserialize, Default, Clone)]
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
 pub struct Tracker {
     pub timers: Vec<Timer>,
     pub players: Vec<Player>,
     pub timers_msg: Option<TimersMsg>,
     pub players_msg: Option<PlayersMsg>,
+    #[serde(default)]
+    pub settings: ChatSettings,
+    /// Bumped whenever players are added or removed. Ids are reused, so
+    /// buttons made for another revision may point at the wrong player.
+    #[serde(default)]
+    pub players_revision: u32,
+    /// Same as `players_revision`, for timers
+    #[serde(default)]
+    pub timers_revision: u32,
+    #[serde(default)]
+    pub pending_rename: Option<PendingRename>,
+    #[serde(default)]
+    pub pending_confirm: Option<PendingConfirm>,
+    /// Pinned message listing players and timers, see `/dash`
+    #[serde(default)]
+    pub dashboard: Option<Dashboard>,
+    /// Due time of the chat in the S3 schedule index, see
+    /// [`crate::context::Storage::due_chats`]
+    #[serde(default)]
+    pub indexed_due: Option<u64>,
+    /// Open poll on when to play next, see `/schedule`
+    #[serde(default)]
+    pub session_poll: Option<SessionPoll>,
+    /// Dice rolled in the chat, see `/stats`
+    #[serde(default)]
+    pub rolls: RollHistory,
+    /// Running provably fair session, see `/session`
+    #[serde(default)]
+    pub fair_session: Option<FairSession>,
+    /// Last fair session, kept for `/verify` once its seed is revealed
+    #[serde(default)]
+    pub revealed_session: Option<FairSession>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Dashboard {
+    pub msg_id: MessageId,
+    /// Last text shown, the message is only edited when it changes
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ItemKind {
+    Player,
+    Timer,
+}
+
+/// Rename started from a keyboard, done by replying to the prompt
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingRename {
+    pub prompt_id: MessageId,
+    pub kind: ItemKind,
+    pub id: usize,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+/// Changes which are only made after a confirm button is pressed
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Destructive {
+    DeletePlayer(usize),
+    DeleteTimer(usize),
+    ResetStress,
+    Wipe,
+}
+
+impl Destructive {
+    /// List the change applies to, if any
+    pub fn kind(self) -> Option<ItemKind> {
+        match self {
+            Destructive::DeletePlayer(_) | Destructive::ResetStress => Some(ItemKind::Player),
+            Destructive::DeleteTimer(_) => Some(ItemKind::Timer),
+            Destructive::Wipe => None,
+        }
+    }
+}
+
+/// Confirm and cancel buttons shown in place of a keyboard
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingConfirm {
+    /// Passed in the buttons, so that those of an earlier request do nothing
+    pub id: u32,
+    pub action: Destructive,
+    /// Revision of the list when the change was requested
+    pub revision: u32,
+    /// Message showing the buttons
+    pub msg_id: MessageId,
+    /// Unix time after which the buttons only close the confirmation. They
+    /// are removed when the tracker is next stored.
+    pub expires_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct TimersMsg {
     pub msg_id: MessageId,
     pub kb_id: MessageId,
     pub keyboard_active: bool,
+    #[serde(default)]
+    pub view: ListView,
+    /// Last text shown, the message is only edited when it changes
+    #[serde(default)]
+    pub text: String,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum PlayersKeyboard {
     Harm,
     Stress,
@@ -47,11 +152,87 @@ pub enum PlayersKeyboard {
     None,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct PlayersMsg {
     pub msg_id: MessageId,
     pub kb_id: MessageId,
     pub active_keyboard: PlayersKeyboard,
+    #[serde(default)]
+    pub view: ListView,
+    #[serde(default)]
+    pub text: String,
+}
+
+/// A change made from a keyboard
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Amount {
+    By(i32),
+    To(i32),
+}
+
+impl Amount {
+    /// Difference to add to `current`
+    pub fn delta(self, current: i32) -> anyhow::Result<i32> {
+        match self {
+            Amount::By(delta) => Ok(delta),
+            Amount::To(value) => value
+                .checked_sub(current)
+                .ok_or(anyhow!("Value {} out of range", value)),
+        }
+    }
+}
+
+/// Part of a list shown on a keyboard
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ListView {
+    /// Zero based, clamped to the last page when shown
+    pub page: usize,
+    /// Only items whose name contains this, ignoring case. Lets a table
+    /// manage a group of clocks or players named alike.
+    pub filter: Option<String>,
+    /// Only items in this group, ignoring case. Clocks are grouped by naming
+    /// them `Group: Clock`.
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Only players with harm or stress, or clocks still running
+    #[serde(default)]
+    pub active: bool,
+}
+
+impl ListView {
+    /// Parses `[*] [filter]`, where `*` asks for active items only and a
+    /// filter ending with `:` names a group
+    pub fn new(filter: &str) -> Self {
+        let filter = filter.trim();
+        let (active, filter) = match filter.strip_prefix('*') {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, filter),
+        };
+        let filter = filter.to_lowercase();
+        let (group, filter) = match filter.strip_suffix(':') {
+            Some(group) => (Some(group.trim().to_owned()), None),
+            None => (None, (!filter.is_empty()).then_some(filter)),
+        };
+        Self {
+            page: 0,
+            filter,
+            group,
+            active,
+        }
+    }
+
+    pub fn matches(&self, name: &str, active: bool) -> bool {
+        let name = name.to_lowercase();
+        (active || !self.active)
+            && self.group.as_ref().is_none_or(|group| {
+                name.split_once(':')
+                    .is_some_and(|(prefix, _)| prefix.trim() == group)
+            })
+            && self
+                .filter
+                .as_ref()
+                .is_none_or(|filter| name.contains(filter))
+    }
 }
 
 impl Tracker {
@@ -61,15 +242,25 @@ impl Tracker {
         }
     }
 
-    pub fn create_timer(&mut self, name: &str, start_value: i32) -> anyhow::Result<Timer> {
-        if self
-            .timers
-            .iter()
-            .find(|timer| timer.name.eq(name))
-            .is_some()
-        {
-            bail!("Timer {} already present", name);
+    /// Revision of the players or the timers
+    pub fn revision(&self, kind: ItemKind) -> u32 {
+        match kind {
+            ItemKind::Player => self.players_revision,
+            ItemKind::Timer => self.timers_revision,
         }
+    }
+
+    /// Pinned players and timers first, then by name
+    pub fn sort(&mut self) {
+        self.players
+            .sort_by(|a, b| (!a.pinned, &a.name).cmp(&(!b.pinned, &b.name)));
+        self.timers
+            .sort_by(|a, b| (!a.pinned, &a.name).cmp(&(!b.pinned, &b.name)));
+    }
+
+    pub fn create_timer(&mut self, name: &str, start_value: i32) -> anyhow::Result<Timer> {
+        self.check_timer_name(name)?;
+        check_timer_ticks(name, start_value, None)?;
         let next_id = self
             .timers
             .iter()
@@ -77,27 +268,24 @@ impl Tracker {
             .max()
             .unwrap_or(0)
             .checked_add(1)
-            .unwrap();
+            .ok_or(anyhow!("No timer ids left"))?;
 
         let timer = Timer {
             id: next_id,
             name: name.to_owned(),
             value: start_value,
+            max: None,
+            pinned: false,
+            due_at: None,
         };
         self.timers.push(timer.clone());
-        self.timers.sort();
+        self.sort();
+        self.timers_revision = self.timers_revision.wrapping_add(1);
         Ok(timer)
     }
 
     pub fn create_player(&mut self, name: &str) -> anyhow::Result<Player> {
-        if self
-            .players
-            .iter()
-            .find(|player| player.name.eq(name))
-            .is_some()
-        {
-            bail!("Player {} already present", name);
-        }
+        self.check_player_name(name)?;
         let next_id = self
             .players
             .iter()
@@ -105,16 +293,18 @@ impl Tracker {
             .max()
             .unwrap_or(0)
             .checked_add(1)
-            .unwrap();
+            .ok_or(anyhow!("No player ids left"))?;
 
         let player = Player {
             id: next_id,
             name: name.to_owned(),
             harm: 0,
             stress: 0,
+            pinned: false,
         };
         self.players.push(player.clone());
-        self.players.sort();
+        self.sort();
+        self.players_revision = self.players_revision.wrapping_add(1);
         Ok(player)
     }
 
@@ -126,21 +316,193 @@ impl Tracker {
         self.players.iter_mut().find(|player| player.id == id).ok_or(anyhow!("Player id {} not found", id))
     }
 
+    /// Changes the timer by `val` ticks, stopping at zero
     pub fn change_timer(&mut self, id: usize, val: i32) -> anyhow::Result<Timer> {
         let timer = self.get_timer(id)?;
-        timer.value = timer.value.checked_add(val).expect("Overflow");
+        let value = timer
+            .value
+            .checked_add(val)
+            .ok_or(anyhow!("Timer {} value out of range", timer.name))?;
+        if let Some(max) = timer.max.filter(|max| val > 0 && value > *max) {
+            bail!("Timer {} cannot have more than {} ticks", timer.name, max);
+        }
+        timer.value = value.max(0);
         Ok(timer.clone())
     }
 
+    /// Makes the named timer fire at `due_at`, adding it if needed
+    pub fn schedule_timer(&mut self, name: &str, due_at: u64) -> anyhow::Result<Timer> {
+        let id = match self.timer_id(name) {
+            Ok(id) => id,
+            Err(_) => self.create_timer(name, 1)?.id,
+        };
+        let timer = self.get_timer(id)?;
+        timer.due_at = Some(due_at);
+        Ok(timer.clone())
+    }
+
+    /// When the next real-time timer fires
+    pub fn next_due(&self) -> Option<u64> {
+        self.timers.iter().filter_map(|timer| timer.due_at).min()
+    }
+
+    /// Fires real-time timers due at `now`. They are left at zero ticks like
+    /// other fired timers, or deleted if the chat asks for that.
+    pub fn fire_due_timers(&mut self, now: u64) -> Vec<Timer> {
+        let mut fired = Vec::new();
+        for timer in self.timers.iter_mut() {
+            if timer.due_at.is_some_and(|due_at| due_at <= now) {
+                timer.due_at = None;
+                timer.value = 0;
+                fired.push(timer.clone());
+            }
+        }
+        if self.settings.delete_fired_timers && !fired.is_empty() {
+            self.timers
+                .retain(|timer| !fired.iter().any(|fired| fired.id == timer.id));
+            self.timers_revision = self.timers_revision.wrapping_add(1);
+        }
+        fired
+    }
+
+    /// Sets the value of the named timer, and its max if given
+    pub fn edit_timer(
+        &mut self,
+        name: &str,
+        value: i32,
+        max: Option<i32>,
+    ) -> anyhow::Result<Timer> {
+        let timer = self
+            .timers
+            .iter_mut()
+            .find(|timer| timer.name == name)
+            .ok_or(anyhow!("Timer {} not found", name))?;
+        let max = max.or(timer.max);
+        check_timer_ticks(&timer.name, value, max)?;
+        timer.value = value;
+        timer.max = max;
+        Ok(timer.clone())
+    }
+
+    /// Returns the old name
+    pub fn rename_player(&mut self, id: usize, name: &str) -> anyhow::Result<String> {
+        self.check_player_name(name)?;
+        let player = self.get_player(id)?;
+        let old = std::mem::replace(&mut player.name, name.to_owned());
+        self.sort();
+        Ok(old)
+    }
+
+    /// Returns the old name
+    pub fn rename_timer(&mut self, id: usize, name: &str) -> anyhow::Result<String> {
+        self.check_timer_name(name)?;
+        let timer = self.get_timer(id)?;
+        let old = std::mem::replace(&mut timer.name, name.to_owned());
+        self.sort();
+        Ok(old)
+    }
+
+    /// Clears the stress of every player
+    pub fn reset_stress(&mut self) {
+        for player in self.players.iter_mut() {
+            player.stress = 0;
+        }
+    }
+
+    /// Starts a new game, keeping the chat settings
+    pub fn wipe(&mut self) {
+        *self = Tracker {
+            settings: self.settings.clone(),
+            dashboard: self.dashboard.take(),
+            session_poll: self.session_poll.take(),
+            rolls: std::mem::take(&mut self.rolls),
+            fair_session: self.fair_session.take(),
+            revealed_session: self.revealed_session.take(),
+            // Still in the S3 schedule index until the tracker is stored
+            indexed_due: self.indexed_due,
+            // Keyboards of the wiped game must not match the new one
+            players_revision: self.players_revision.wrapping_add(1),
+            timers_revision: self.timers_revision.wrapping_add(1),
+            ..Tracker::new()
+        };
+    }
+
+    pub fn toggle_pin_player(&mut self, id: usize) -> anyhow::Result<Player> {
+        let player = self.get_player(id)?;
+        player.pinned = !player.pinned;
+        let player = player.clone();
+        self.sort();
+        Ok(player)
+    }
+
+    pub fn toggle_pin_timer(&mut self, id: usize) -> anyhow::Result<Timer> {
+        let timer = self.get_timer(id)?;
+        timer.pinned = !timer.pinned;
+        let timer = timer.clone();
+        self.sort();
+        Ok(timer)
+    }
+
+    pub fn player_id(&self, name: &str) -> anyhow::Result<usize> {
+        self.players
+            .iter()
+            .find(|player| player.name == name)
+            .map(|player| player.id)
+            .ok_or(anyhow!("Player {} not found", name))
+    }
+
+    pub fn timer_id(&self, name: &str) -> anyhow::Result<usize> {
+        self.timers
+            .iter()
+            .find(|timer| timer.name == name)
+            .map(|timer| timer.id)
+            .ok_or(anyhow!("Timer {} not found", name))
+    }
+
+    fn check_player_name(&self, name: &str) -> anyhow::Result<()> {
+        if name.trim().is_empty() {
+            bail!("Player name cannot be empty");
+        }
+        if self.players.iter().any(|player| player.name == name) {
+            bail!("Player {} already present", name);
+        }
+        Ok(())
+    }
+
+    fn check_timer_name(&self, name: &str) -> anyhow::Result<()> {
+        if name.trim().is_empty() {
+            bail!("Timer name cannot be empty");
+        }
+        if self.timers.iter().any(|timer| timer.name == name) {
+            bail!("Timer {} already present", name);
+        }
+        Ok(())
+    }
+
     pub fn change_harm(&mut self, id: usize, val: i32) -> anyhow::Result<Player> {
         let player = self.get_player(id)?;
-        player.harm = player.harm.checked_add(val).expect("Overflow");
+        player.harm = player
+            .harm
+            .checked_add(val)
+            .ok_or(anyhow!("Player {} harm out of range", player.name))?;
         Ok(player.clone())
     }
 
     pub fn change_stress(&mut self, id: usize, val: i32) -> anyhow::Result<Player> {
+        let stress_cap = self.settings.stress_cap;
         let player = self.get_player(id)?;
-        player.stress = player.stress.checked_add(val).expect("Overflow");
+        let stress = player
+            .stress
+            .checked_add(val)
+            .ok_or(anyhow!("Player {} stress out of range", player.name))?;
+        if let Some(cap) = stress_cap.filter(|cap| val > 0 && stress > *cap) {
+            bail!(
+                "Player {} cannot have more than {} stress",
+                player.name,
+                cap
+            );
+        }
+        player.stress = stress;
         Ok(player.clone())
     }
 
@@ -149,8 +511,9 @@ impl Tracker {
             .timers
             .iter()
             .position(|timer| timer.id == id)
-            .ok_or(anyhow!("Timer id {} not found", id));
-        pos.and_then(|pos| Ok(self.timers.remove(pos)))
+            .ok_or(anyhow!("Timer id {} not found", id))?;
+        self.timers_revision = self.timers_revision.wrapping_add(1);
+        Ok(self.timers.remove(pos))
     }
 
     pub fn delete_player(&mut self, id: usize) -> anyhow::Result<Player> {
@@ -158,7 +521,164 @@ impl Tracker {
             .players
             .iter()
             .position(|player| player.id == id)
-            .ok_or(anyhow!("Player id {} not found", id));
-        pos.and_then(|pos| Ok(self.players.remove(pos)))
+            .ok_or(anyhow!("Player id {} not found", id))?;
+        self.players_revision = self.players_revision.wrapping_add(1);
+        Ok(self.players.remove(pos))
+    }
+}
+
+/// Fails unless a timer may have `value` ticks and at most `max`
+fn check_timer_ticks(name: &str, value: i32, max: Option<i32>) -> anyhow::Result<()> {
+    if value < 0 {
+        bail!("Timer {} cannot have fewer than 0 ticks", name);
+    }
+    match max {
+        Some(max) if max < 1 => bail!("Timer {} needs room for at least 1 tick", name),
+        Some(max) if value > max => bail!("Timer {} cannot have more than {} ticks", name, max),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        CreatePlayer(String),
+        CreateTimer(String, i32),
+        ChangeHarm(usize, i32),
+        ChangeStress(usize, i32),
+        ChangeTimer(usize, i32),
+        DeletePlayer(usize),
+        DeleteTimer(usize),
+        RenamePlayer(usize, String),
+        PinTimer(usize),
+        EditTimer(String, i32, Option<i32>),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        // Few names and ids so that collisions and misses are common, and
+        // both small and extreme values to hit overflows
+        let name = "[a-c]{1,2}";
+        let id = 0..6usize;
+        let val = prop_oneof![-3..=3i32, any::<i32>()];
+        prop_oneof![
+            name.prop_map(Op::CreatePlayer),
+            (name, val.clone()).prop_map(|(name, val)| Op::CreateTimer(name, val)),
+            (id.clone(), val.clone()).prop_map(|(id, val)| Op::ChangeHarm(id, val)),
+            (id.clone(), val.clone()).prop_map(|(id, val)| Op::ChangeStress(id, val)),
+            (id.clone(), val.clone()).prop_map(|(id, val)| Op::ChangeTimer(id, val)),
+            id.clone().prop_map(Op::DeletePlayer),
+            id.clone().prop_map(Op::DeleteTimer),
+            (id.clone(), name).prop_map(|(id, name)| Op::RenamePlayer(id, name)),
+            id.prop_map(Op::PinTimer),
+            (name, val.clone(), prop::option::of(val))
+                .prop_map(|(name, val, max)| Op::EditTimer(name, val, max)),
+        ]
+    }
+
+    fn apply(tracker: &mut Tracker, op: &Op) -> anyhow::Result<()> {
+        match op {
+            Op::CreatePlayer(name) => tracker.create_player(name).map(drop),
+            Op::CreateTimer(name, val) => tracker.create_timer(name, *val).map(drop),
+            Op::ChangeHarm(id, val) => tracker.change_harm(*id, *val).map(drop),
+            Op::ChangeStress(id, val) => tracker.change_stress(*id, *val).map(drop),
+            Op::ChangeTimer(id, val) => tracker.change_timer(*id, *val).map(drop),
+            Op::DeletePlayer(id) => tracker.delete_player(*id).map(drop),
+            Op::DeleteTimer(id) => tracker.delete_timer(*id).map(drop),
+            Op::RenamePlayer(id, name) => tracker.rename_player(*id, name).map(drop),
+            Op::PinTimer(id) => tracker.toggle_pin_timer(*id).map(drop),
+            Op::EditTimer(name, val, max) => tracker.edit_timer(name, *val, *max).map(drop),
+        }
+    }
+
+    fn check_invariants(tracker: &Tracker) {
+        let names: HashSet<_> = tracker.players.iter().map(|p| &p.name).collect();
+        assert_eq!(names.len(), tracker.players.len(), "player names unique");
+        let ids: HashSet<_> = tracker.players.iter().map(|p| p.id).collect();
+        assert_eq!(ids.len(), tracker.players.len(), "player ids unique");
+        assert!(
+            tracker
+                .players
+                .windows(2)
+                .all(|w| (!w[0].pinned, &w[0].name) < (!w[1].pinned, &w[1].name)),
+            "players sorted"
+        );
+
+        let names: HashSet<_> = tracker.timers.iter().map(|t| &t.name).collect();
+        assert_eq!(names.len(), tracker.timers.len(), "timer names unique");
+        let ids: HashSet<_> = tracker.timers.iter().map(|t| t.id).collect();
+        assert_eq!(ids.len(), tracker.timers.len(), "timer ids unique");
+        for timer in &tracker.timers {
+            assert!(timer.value >= 0, "timer {timer:?} not below zero");
+            assert!(
+                timer.max.is_none_or(|max| max >= 1 && timer.value <= max),
+                "timer {timer:?} within its max"
+            );
+        }
+        assert!(
+            tracker
+                .timers
+                .windows(2)
+                .all(|w| (!w[0].pinned, &w[0].name) < (!w[1].pinned, &w[1].name)),
+            "timers sorted"
+        );
+
+        let json = serde_json::to_string(tracker).unwrap();
+        let restored: Tracker = serde_json::from_str(&json).unwrap();
+        assert_eq!(&restored, tracker, "serialization round trip");
+    }
+
+    proptest! {
+        #[test]
+        fn operations_keep_invariants(ops in prop::collection::vec(op(), 0..64)) {
+            let mut tracker = Tracker::new();
+            for op in ops.iter() {
+                let before = tracker.clone();
+                if apply(&mut tracker, op).is_err() {
+                    prop_assert_eq!(&tracker, &before, "failed {:?} changed the tracker", op);
+                }
+                check_invariants(&tracker);
+            }
+        }
+    }
+
+    #[test]
+    fn change_overflow_is_an_error() {
+        let mut tracker = Tracker::new();
+        let player = tracker.create_player("Alice").unwrap();
+        tracker.change_harm(player.id, i32::MAX).unwrap();
+
+        let err = tracker.change_harm(player.id, 1).unwrap_err();
+        assert_eq!(err.to_string(), "Player Alice harm out of range");
+        assert_eq!(tracker.players[0].harm, i32::MAX);
+    }
+
+    #[test]
+    fn edited_timer_keeps_ticks_in_range() {
+        let mut tracker = Tracker::new();
+        tracker.create_timer("Heist", 4).unwrap();
+        let mut error = |value, max| {
+            let err = tracker.edit_timer("Heist", value, max).unwrap_err();
+            err.to_string()
+        };
+        assert_eq!(
+            error(-5, None),
+            "Timer Heist cannot have fewer than 0 ticks"
+        );
+        assert_eq!(
+            error(0, Some(0)),
+            "Timer Heist needs room for at least 1 tick"
+        );
+        assert_eq!(
+            error(7, Some(6)),
+            "Timer Heist cannot have more than 6 ticks"
+        );
+        assert_eq!(tracker.edit_timer("Heist", 0, Some(6)).unwrap().value, 0);
     }
 }
@@ -1,37 +1,55 @@
-use std::env;
+use std::{
+    env,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::{anyhow, bail};
-use lambda_http::{Body, Error, Request, Response};
+use lambda_http::{http::HeaderMap, Body, Error, Response};
+use strum::IntoEnumIterator;
 use teloxide::{
-    adaptors::{throttle::Limits, CacheMe, DefaultParseMode, Throttle},
+    adaptors::{throttle::Limits, CacheMe, Throttle},
     prelude::*,
     requests::RequesterExt,
-    utils::command::BotCommands,
 };
 use tracing::{info, warn};
 
-use crate::dispatcher::Command;
+use crate::{dispatcher::localized_commands, i18n::Locale, settings::Language};
 
 static SECRET_TOKEN_HEADER: &str = "x-telegram-bot-api-secret-token";
-static SECRET_TOKEN_ENV_VAR: &str = "AUTH_TOKEN";
+pub static SECRET_TOKEN_ENV_VAR: &str = "AUTH_TOKEN";
 static DEBUG_CHAT_ID_ENV_VAR: &str = "DEBUG_CHAT_ID";
+static API_URL_ENV_VAR: &str = "TELEGRAM_API_URL";
 
 pub type Bot = CacheMe<Throttle<teloxide::Bot>>;
-pub type MarkdownBot = DefaultParseMode<CacheMe<Throttle<teloxide::Bot>>>;
 
 pub async fn init_bot() -> Bot {
-    let bot = teloxide::Bot::from_env().throttle(Limits::default()).cache_me();
+    let bot = telegram_bot().throttle(Limits::default()).cache_me();
 
-    bot.set_my_commands(Command::bot_commands())
+    bot.set_my_commands(localized_commands(&Locale(Language::En)))
         .await
         .expect("Error setting commands");
+    for language in Language::iter().filter(|language| *language != Language::En) {
+        bot.set_my_commands(localized_commands(&Locale(language)))
+            .language_code(language.as_ref())
+            .await
+            .expect("Error setting commands");
+    }
     bot
 }
 
-pub fn authorize(event: &Request) -> anyhow::Result<()> {
+/// Creates a bot from `TELOXIDE_TOKEN`. `TELEGRAM_API_URL` overrides the Bot
+/// API endpoint, e.g. for a local Bot API server or a test double.
+fn telegram_bot() -> teloxide::Bot {
+    let bot = teloxide::Bot::from_env();
+    match env::var(API_URL_ENV_VAR) {
+        Ok(url) => bot.set_api_url(url.parse().expect("Invalid API URL")),
+        Err(_) => bot,
+    }
+}
+
+pub fn authorize(headers: &HeaderMap) -> anyhow::Result<()> {
     let expected_token = env::var(SECRET_TOKEN_ENV_VAR)?;
-    let token_header = event
-        .headers()
+    let token_header = headers
         .get(SECRET_TOKEN_HEADER)
         .ok_or(anyhow!("No {SECRET_TOKEN_HEADER} found"))?;
 
@@ -61,12 +79,19 @@ pub fn success_response() -> Result<Response<Body>, Error> {
         .unwrap())
 }
 
+/// Seconds since the Unix epoch
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
 pub async fn debug_err(err: &anyhow::Error) {
     if let Ok(chat_id) = env::var(DEBUG_CHAT_ID_ENV_VAR)
         .unwrap_or("invalid".to_string())
         .parse::<i64>()
     {
-        let _ = teloxide::Bot::from_env()
+        let _ = telegram_bot()
             .send_message(ChatId(chat_id), format!("{:#?}", err))
             .await;
     }
@@ -0,0 +1,111 @@
+use std::env;
+
+use anyhow::Context;
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Router,
+};
+use teloxide::{prelude::*, types::Update};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    context::Storage,
+    dispatcher::dispatch_update,
+    utils::{authorize, Bot, SECRET_TOKEN_ENV_VAR},
+};
+
+pub static WEBHOOK_ADDR_ENV_VAR: &str = "WEBHOOK_ADDR";
+static WEBHOOK_URL_ENV_VAR: &str = "WEBHOOK_URL";
+
+/// Validates a webhook delivery and dispatches the update. Shared by the
+/// Lambda and the local webhook server, so both behave the same.
+/// On failure returns the HTTP status and message to respond with.
+#[instrument(skip_all)]
+pub async fn handle_webhook(
+    bot: Bot,
+    storage: Storage,
+    headers: &HeaderMap,
+    payload: anyhow::Result<Option<Update>>,
+) -> Result<(), (u16, String)> {
+    if let Err(e) = authorize(headers) {
+        return Err((401, format!("Unauthorized: {e}")));
+    }
+
+    let update = match payload {
+        Ok(Some(update)) => update,
+        Ok(None) => return Err((400, "Empty payload".to_owned())),
+        Err(e) => return Err((400, format!("Invalid payload: {e}"))),
+    };
+
+    dispatch_update(bot, update, storage)
+        .await
+        .map_err(|e| (400, format!("Error: {e}")))
+}
+
+/// Registers `url` as the bot's webhook, protected by the secret token the
+/// handler expects in `x-telegram-bot-api-secret-token`.
+pub async fn set_webhook(bot: &Bot, url: &str) -> anyhow::Result<()> {
+    let token = env::var(SECRET_TOKEN_ENV_VAR)
+        .with_context(|| format!("{SECRET_TOKEN_ENV_VAR} must be set to register a webhook"))?;
+    bot.set_webhook(url.parse()?)
+        .secret_token(token)
+        .await
+        .with_context(|| format!("Error setting webhook to {url}"))?;
+    info!("Webhook set to {}", url);
+    Ok(())
+}
+
+#[derive(Clone)]
+struct WebhookState {
+    bot: Bot,
+    storage: Storage,
+}
+
+/// Serves the webhook handler over HTTP on `addr`, registering the webhook
+/// first when `WEBHOOK_URL` is set.
+#[instrument(skip(bot, storage))]
+pub async fn serve(bot: Bot, storage: Storage, addr: &str) -> anyhow::Result<()> {
+    if let Ok(url) = env::var(WEBHOOK_URL_ENV_VAR) {
+        set_webhook(&bot, &url).await?;
+    }
+
+    let app = Router::new()
+        .route("/", post(handle_request))
+        .with_state(WebhookState { bot, storage });
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Error binding to {addr}"))?;
+    info!("Listening for webhooks on {}", addr);
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+    Ok(())
+}
+
+async fn handle_request(
+    State(state): State<WebhookState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let payload = if body.is_empty() {
+        Ok(None)
+    } else {
+        serde_json::from_slice(&body).map_err(Into::into)
+    };
+    match handle_webhook(state.bot, state.storage, &headers, payload).await {
+        Ok(()) => {
+            info!("Returning success");
+            StatusCode::OK
+        }
+        Err((code, message)) => {
+            warn!("Returning error code: {}, message: {} ", code, message);
+            StatusCode::from_u16(code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Runs the bot binary in long polling mode against a fake Bot API server and
+//! scripts conversations with it.
+
+use std::{
+    env,
+    process::{Child, Command, Stdio},
+};
+
+use fake_telegram::FakeTelegram;
+
+mod fake_telegram;
+
+/// The bot process, killed when dropped
+struct BotProcess {
+    child: Child,
+    db_path: std::path::PathBuf,
+}
+
+impl BotProcess {
+    fn start(fake: &FakeTelegram, name: &str) -> Self {
+        let dir = env::temp_dir();
+        let db_path = dir.join(format!(
+            "dnd_bot_e2e_{}_{}.sqlite",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&db_path);
+        let child = Command::new(env!("CARGO_BIN_EXE_dnd_bot"))
+            .current_dir(&dir)
+            .env_clear()
+            .env("TELOXIDE_TOKEN", "123:test")
+            .env("TELEGRAM_API_URL", fake.api_url())
+            .env("STORAGE", "sqlite")
+            .env("SQLITE_PATH", &db_path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start bot");
+        Self { child, db_path }
+    }
+}
+
+impl Drop for BotProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_file(&self.db_path);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn manages_players_and_stress() {
+    let fake = FakeTelegram::start().await;
+    let _bot = BotProcess::start(&fake, "players");
+
+    fake.send("/pa Alice");
+    fake.wait_for_text("Player *Alice* added").await;
+
+    fake.send("/p");
+    fake.wait_for_text("*Players:*").await;
+    fake.wait_for_text("*Manage:*").await;
+
+    fake.tap("Manage stress");
+    fake.wait_for_text("*Manage stress:*").await;
+
+    fake.tap("+1 stress");
+    fake.wait_for_text("Player *Alice* has *1* stress").await;
+    let edit = fake
+        .wait_for("players message edit", |call| {
+            call.method == "editMessageText"
+        })
+        .await;
+    assert_eq!(
+        edit.text(),
+        Some("*Players:*\n\n*Alice*: *0* harm, *1* stress\n")
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ticks_timer_until_it_fires() {
+    let fake = FakeTelegram::start().await;
+    let _bot = BotProcess::start(&fake, "timers");
+
+    fake.send("/ta Heist 2");
+    fake.wait_for_text("Timer *Heist* added").await;
+
+    fake.send("/t");
+    fake.wait_for_text("*Timers:*").await;
+    fake.wait_for_text("*Manage:*").await;
+
+    fake.tap("-1");
+    fake.wait_for_text("Timer *Heist* has *1* ticks left").await;
+    fake.tap("-1");
+    fake.wait_for_text("Timer *Heist* has fired\\!").await;
+
+    let transcript = fake.transcript();
+    assert!(transcript.contains("editMessageText: *Timers:*\n\n"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rolls_dice() {
+    let fake = FakeTelegram::start().await;
+    let _bot = BotProcess::start(&fake, "dice");
+
+    fake.send("/r2");
+    fake.wait_for("first die", |call| call.method == "sendDice")
+        .await;
+    let last_die = fake
+        .wait_for("second die", |call| call.method == "sendDice")
+        .await;
+
+    // The summary quotes the last die once the animation is over
+    let summary = fake.wait_for_text("Highest *2* — bad outcome").await;
+    assert_eq!(
+        summary.params["reply_parameters"]["message_id"].as_i64(),
+        last_die.msg_id
+    );
+}
@@ -0,0 +1,271 @@
+//! A stand-in for `api.telegram.org`, serving just enough of the Bot API for
+//! the bot to run against it: `getUpdates` hands out scripted updates and every
+//! other call is recorded and answered with a plausible result.
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    routing::post,
+    Json, Router,
+};
+use serde_json::{json, Value};
+use tokio::{sync::Notify, time::Instant};
+
+pub const BOT_USERNAME: &str = "dnd_bot";
+const BOT_ID: i64 = 1;
+const USER_ID: i64 = 100;
+const DATE: i64 = 1_700_000_000;
+
+/// A Bot API call made by the bot
+#[derive(Clone, Debug)]
+pub struct Call {
+    pub method: String,
+    pub params: Value,
+    /// Id of the message sent or edited by the call
+    pub msg_id: Option<i64>,
+}
+
+impl Call {
+    pub fn text(&self) -> Option<&str> {
+        self.params["text"].as_str()
+    }
+}
+
+#[derive(Default)]
+struct ServerState {
+    calls: Vec<Call>,
+    /// Calls before this index have already been matched by `wait_for`
+    cursor: usize,
+    pending_updates: Vec<Value>,
+    next_update_id: i64,
+    next_msg_id: i64,
+    dice_rolls: u8,
+}
+
+#[derive(Clone)]
+pub struct FakeTelegram {
+    state: Arc<Mutex<ServerState>>,
+    new_update: Arc<Notify>,
+    pub addr: SocketAddr,
+    pub chat_id: i64,
+}
+
+impl FakeTelegram {
+    pub async fn start() -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let fake = Self {
+            state: Default::default(),
+            new_update: Default::default(),
+            addr: listener.local_addr().unwrap(),
+            chat_id: -1001,
+        };
+        let app = Router::new()
+            .route("/:bot/:method", post(handle_call))
+            .with_state(fake.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        fake
+    }
+
+    pub fn api_url(&self) -> String {
+        format!("http://{}/", self.addr)
+    }
+
+    /// Queues a text message from the test user.
+    pub fn send(&self, text: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.next_msg_id += 1;
+        let message = message_json(state.next_msg_id, self.chat_id, user_json(), text);
+        self.push_update(&mut state, "message", message);
+    }
+
+    /// Queues a press of the most recent inline button labelled `label`.
+    pub fn tap(&self, label: &str) {
+        let mut state = self.state.lock().unwrap();
+        let (msg_id, data) = find_button(&state.calls, label)
+            .unwrap_or_else(|| panic!("No button labelled {label:?}"));
+        let callback = json!({
+            "id": format!("cb{}", state.next_update_id + 1),
+            "from": user_json(),
+            "chat_instance": "instance",
+            "data": data,
+            "message": message_json(msg_id, self.chat_id, bot_json(), "*Manage:*"),
+        });
+        self.push_update(&mut state, "callback_query", callback);
+    }
+
+    /// Waits until the bot makes a call satisfying `pred` and returns it.
+    /// Only calls made after the one previously waited for are considered.
+    pub async fn wait_for(&self, what: &str, pred: impl Fn(&Call) -> bool) -> Call {
+        let deadline = Instant::now() + Duration::from_secs(20);
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                let cursor = state.cursor;
+                if let Some(pos) = state.calls[cursor..].iter().position(&pred) {
+                    state.cursor = cursor + pos + 1;
+                    return state.calls[cursor + pos].clone();
+                }
+            }
+            if Instant::now() > deadline {
+                panic!(
+                    "Timed out waiting for {what}. Transcript:\n{}",
+                    self.transcript()
+                );
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    /// Waits for a message or edit whose text starts with `prefix`.
+    pub async fn wait_for_text(&self, prefix: &str) -> Call {
+        self.wait_for(prefix, |call| {
+            call.text().is_some_and(|text| text.starts_with(prefix))
+        })
+        .await
+    }
+
+    /// Every text the bot sent or edited into the chat, one per line.
+    pub fn transcript(&self) -> String {
+        self.state
+            .lock()
+            .unwrap()
+            .calls
+            .iter()
+            .filter_map(|call| call.text().map(|text| format!("{}: {}", call.method, text)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn push_update(&self, state: &mut ServerState, kind: &str, payload: Value) {
+        state.next_update_id += 1;
+        let update_id = state.next_update_id;
+        state
+            .pending_updates
+            .push(json!({ "update_id": update_id, kind: payload }));
+        self.new_update.notify_waiters();
+    }
+}
+
+async fn handle_call(
+    State(fake): State<FakeTelegram>,
+    Path((_bot, method)): Path<(String, String)>,
+    body: Bytes,
+) -> Json<Value> {
+    let params: Value = serde_json::from_slice(&body).unwrap_or(json!({}));
+    // teloxide names methods in PascalCase, the docs use camelCase
+    let method = method[..1].to_lowercase() + &method[1..];
+
+    if method == "getUpdates" {
+        return Json(json!({ "ok": true, "result": get_updates(&fake, &params).await }));
+    }
+
+    let mut state = fake.state.lock().unwrap();
+    let result = match method.as_str() {
+        "getMe" => {
+            let mut me = bot_json();
+            me["can_join_groups"] = json!(true);
+            me["can_read_all_group_messages"] = json!(false);
+            me["supports_inline_queries"] = json!(true);
+            me
+        }
+        "getWebhookInfo" => json!({
+            "url": "",
+            "has_custom_certificate": false,
+            "pending_update_count": 0,
+        }),
+        "sendMessage" | "sendDice" => {
+            state.next_msg_id += 1;
+            let mut message = message_json(
+                state.next_msg_id,
+                fake.chat_id,
+                bot_json(),
+                params["text"].as_str().unwrap_or_default(),
+            );
+            if method == "sendDice" {
+                state.dice_rolls = state.dice_rolls % 6 + 1;
+                message["dice"] = json!({ "emoji": "🎲", "value": state.dice_rolls });
+                message.as_object_mut().unwrap().remove("text");
+            }
+            if !params["reply_markup"].is_null() {
+                message["reply_markup"] = params["reply_markup"].clone();
+            }
+            message
+        }
+        "editMessageText" | "editMessageReplyMarkup" => {
+            let msg_id = params["message_id"].as_i64().unwrap_or_default();
+            let text = params["text"].as_str().unwrap_or("*Manage:*");
+            let mut message = message_json(msg_id, fake.chat_id, bot_json(), text);
+            if !params["reply_markup"].is_null() {
+                message["reply_markup"] = params["reply_markup"].clone();
+            }
+            message
+        }
+        _ => json!(true),
+    };
+    state.calls.push(Call {
+        method,
+        params,
+        msg_id: result["message_id"].as_i64(),
+    });
+    Json(json!({ "ok": true, "result": result }))
+}
+
+/// Long polling: returns pending updates at or after `offset`, waiting a bit
+/// for new ones when there are none.
+async fn get_updates(fake: &FakeTelegram, params: &Value) -> Value {
+    let offset = params["offset"].as_i64().unwrap_or(0);
+    for _ in 0..2 {
+        let notified = fake.new_update.notified();
+        {
+            let mut state = fake.state.lock().unwrap();
+            state
+                .pending_updates
+                .retain(|update| update["update_id"].as_i64().unwrap() >= offset);
+            if !state.pending_updates.is_empty() {
+                return json!(state.pending_updates);
+            }
+        }
+        let _ = tokio::time::timeout(Duration::from_secs(1), notified).await;
+    }
+    json!([])
+}
+
+/// Finds the latest keyboard with a button labelled `label`. Returns the
+/// message id and the button's callback data.
+fn find_button(calls: &[Call], label: &str) -> Option<(i64, String)> {
+    calls.iter().rev().find_map(|call| {
+        call.params["reply_markup"]["inline_keyboard"]
+            .as_array()?
+            .iter()
+            .flat_map(|row| row.as_array().into_iter().flatten())
+            .find(|button| button["text"] == label)
+            .map(|button| {
+                let data = button["callback_data"].as_str().unwrap().to_owned();
+                (call.msg_id.unwrap_or_default(), data)
+            })
+    })
+}
+
+fn message_json(msg_id: i64, chat_id: i64, from: Value, text: &str) -> Value {
+    json!({
+        "message_id": msg_id,
+        "date": DATE,
+        "chat": { "id": chat_id, "type": "group", "title": "Table" },
+        "from": from,
+        "text": text,
+    })
+}
+
+fn bot_json() -> Value {
+    json!({ "id": BOT_ID, "is_bot": true, "first_name": "DnD", "username": BOT_USERNAME })
+}
+
+fn user_json() -> Value {
+    json!({ "id": USER_ID, "is_bot": false, "first_name": "Alice", "username": "alice" })
+}